@@ -0,0 +1,30 @@
+-- Tables from before migrations were used, existing databases will already have these
+CREATE TABLE IF NOT EXISTS death (
+    id bigserial PRIMARY KEY,
+    create_date timestamp with time zone DEFAULT now() NOT NULL,
+    victim character varying(255) NOT NULL,
+    killer character varying(255),
+    weapon character varying(255),
+    message text NOT NULL,
+    seconds_since_last integer,
+    is_pk boolean DEFAULT false NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS message (
+    id bigserial PRIMARY KEY,
+    create_date timestamp with time zone DEFAULT now() NOT NULL,
+    author character varying(255) NOT NULL,
+    content character varying(1024) NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS server_join (
+    id bigserial PRIMARY KEY,
+    create_date timestamp with time zone DEFAULT now() NOT NULL,
+    username character varying(255) NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS server_leave (
+    id bigserial PRIMARY KEY,
+    create_date timestamp with time zone DEFAULT now() NOT NULL,
+    username character varying(255) NOT NULL
+);
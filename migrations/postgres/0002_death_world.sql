@@ -0,0 +1 @@
+ALTER TABLE death ADD COLUMN IF NOT EXISTS world character varying(255);
@@ -0,0 +1,21 @@
+CREATE TABLE IF NOT EXISTS boss_fight (
+    id bigserial PRIMARY KEY,
+    boss character varying(255) NOT NULL,
+    start_date timestamp with time zone DEFAULT now(),
+    end_date timestamp with time zone,
+    defeated boolean DEFAULT false NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS boss_fight_player (
+    boss_fight_id bigint NOT NULL REFERENCES boss_fight(id) ON DELETE CASCADE,
+    username character varying(255) NOT NULL,
+    PRIMARY KEY (boss_fight_id, username)
+);
+
+CREATE TABLE IF NOT EXISTS world_event (
+    id bigserial PRIMARY KEY,
+    name character varying(255) NOT NULL,
+    is_invasion boolean NOT NULL,
+    start_date timestamp with time zone DEFAULT now() NOT NULL,
+    end_date timestamp with time zone
+);
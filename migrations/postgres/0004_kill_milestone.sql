@@ -0,0 +1,8 @@
+CREATE TABLE IF NOT EXISTS kill_milestone (
+    id bigserial PRIMARY KEY,
+    create_date timestamp with time zone DEFAULT now() NOT NULL,
+    player character varying(255),
+    count integer NOT NULL,
+    npc_key character varying(255) NOT NULL,
+    npc_name character varying(255) NOT NULL
+);
@@ -0,0 +1 @@
+CREATE INDEX IF NOT EXISTS message_content_idx ON message USING gin (to_tsvector('english', content));
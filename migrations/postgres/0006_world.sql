@@ -0,0 +1,26 @@
+CREATE TABLE world (
+    id bigserial PRIMARY KEY,
+    name character varying(255) NOT NULL UNIQUE,
+    create_date timestamp with time zone DEFAULT now() NOT NULL,
+    is_current boolean DEFAULT false NOT NULL
+);
+
+-- Only one world is being played at a time
+CREATE UNIQUE INDEX world_current_idx ON world (is_current) WHERE is_current;
+
+ALTER TABLE death ADD COLUMN world_id bigint REFERENCES world(id);
+ALTER TABLE message ADD COLUMN world_id bigint REFERENCES world(id);
+ALTER TABLE server_join ADD COLUMN world_id bigint REFERENCES world(id);
+ALTER TABLE server_leave ADD COLUMN world_id bigint REFERENCES world(id);
+ALTER TABLE boss_fight ADD COLUMN world_id bigint REFERENCES world(id);
+ALTER TABLE world_event ADD COLUMN world_id bigint REFERENCES world(id);
+ALTER TABLE kill_milestone ADD COLUMN world_id bigint REFERENCES world(id);
+
+-- Deaths by other players or enemies already recorded their world's name
+INSERT INTO world(name, create_date) SELECT world, min(create_date) FROM death WHERE world IS NOT NULL GROUP BY world;
+UPDATE death SET world_id = world.id FROM world WHERE death.world = world.name;
+ALTER TABLE death DROP COLUMN world;
+
+-- New rows are assumed to be from the world most recently died in. Older rows without a known world
+-- are left without one, they're only counted in stats for every world
+UPDATE world SET is_current = true WHERE id = (SELECT world_id FROM death WHERE world_id IS NOT NULL ORDER BY create_date DESC, id DESC LIMIT 1);
@@ -0,0 +1,11 @@
+-- Audit trail of players forgotten with /forget
+CREATE TABLE player_erasure (
+    id bigserial PRIMARY KEY,
+    create_date timestamp with time zone DEFAULT now() NOT NULL,
+    player character varying(255) NOT NULL,
+    requested_by character varying(255) NOT NULL,
+    messages bigint NOT NULL,
+    deaths bigint NOT NULL,
+    sessions bigint NOT NULL,
+    kills bigint NOT NULL
+);
@@ -0,0 +1,7 @@
+-- Server logs imported with `terraria-discord import-log`, by a fingerprint of the start of the file
+CREATE TABLE log_import (
+    fingerprint character varying(16) PRIMARY KEY,
+    create_date timestamp with time zone DEFAULT now() NOT NULL,
+    update_date timestamp with time zone DEFAULT now() NOT NULL,
+    lines bigint NOT NULL
+);
@@ -0,0 +1,7 @@
+-- Messages for discord, kept until they're sent so they survive outages and restarts
+CREATE TABLE outbox (
+    id bigserial PRIMARY KEY,
+    create_date timestamp with time zone DEFAULT now() NOT NULL,
+    channel_id bigint NOT NULL,
+    content text NOT NULL
+);
@@ -0,0 +1,7 @@
+-- Indexes for per player stats and for reading and pruning by date, matching sqlite
+CREATE INDEX IF NOT EXISTS death_victim_idx ON death (victim);
+CREATE INDEX IF NOT EXISTS message_author_idx ON message (author);
+CREATE INDEX IF NOT EXISTS death_create_date_idx ON death (create_date);
+CREATE INDEX IF NOT EXISTS message_create_date_idx ON message (create_date);
+CREATE INDEX IF NOT EXISTS server_join_create_date_idx ON server_join (create_date);
+CREATE INDEX IF NOT EXISTS server_leave_username_idx ON server_leave (username, create_date);
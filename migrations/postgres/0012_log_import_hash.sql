@@ -0,0 +1,6 @@
+-- Logs that start the same are told apart by a hash of every line imported from them, so there can
+-- be more than one import per fingerprint. Logs imported before hashes were kept have none
+ALTER TABLE log_import DROP CONSTRAINT log_import_pkey;
+ALTER TABLE log_import ADD COLUMN id bigserial PRIMARY KEY;
+ALTER TABLE log_import ADD COLUMN hash character varying(16);
+CREATE INDEX log_import_fingerprint_idx ON log_import (fingerprint);
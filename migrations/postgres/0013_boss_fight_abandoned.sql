@@ -0,0 +1,2 @@
+-- Fights whose end was missed, which aren't attempts at the boss
+ALTER TABLE boss_fight ADD COLUMN abandoned boolean DEFAULT false NOT NULL;
@@ -0,0 +1,2 @@
+-- Searches match words anywhere in a message, which the full text index can't help with
+DROP INDEX IF EXISTS message_content_idx;
@@ -0,0 +1,65 @@
+-- Dates are stored as unix timestamps
+CREATE TABLE death (
+    id integer PRIMARY KEY,
+    create_date integer DEFAULT (unixepoch()) NOT NULL,
+    victim text NOT NULL,
+    killer text,
+    weapon text,
+    message text NOT NULL,
+    seconds_since_last integer,
+    is_pk boolean DEFAULT false NOT NULL,
+    world text
+);
+
+CREATE TABLE message (
+    id integer PRIMARY KEY,
+    create_date integer DEFAULT (unixepoch()) NOT NULL,
+    author text NOT NULL,
+    content text NOT NULL
+);
+
+CREATE TABLE server_join (
+    id integer PRIMARY KEY,
+    create_date integer DEFAULT (unixepoch()) NOT NULL,
+    username text NOT NULL
+);
+
+CREATE TABLE server_leave (
+    id integer PRIMARY KEY,
+    create_date integer DEFAULT (unixepoch()) NOT NULL,
+    username text NOT NULL
+);
+
+CREATE TABLE boss_fight (
+    id integer PRIMARY KEY,
+    boss text NOT NULL,
+    start_date integer DEFAULT (unixepoch()),
+    end_date integer,
+    defeated boolean DEFAULT false NOT NULL
+);
+
+CREATE TABLE boss_fight_player (
+    boss_fight_id integer NOT NULL REFERENCES boss_fight(id) ON DELETE CASCADE,
+    username text NOT NULL,
+    PRIMARY KEY (boss_fight_id, username)
+);
+
+CREATE TABLE world_event (
+    id integer PRIMARY KEY,
+    name text NOT NULL,
+    is_invasion boolean NOT NULL,
+    start_date integer DEFAULT (unixepoch()) NOT NULL,
+    end_date integer
+);
+
+CREATE TABLE kill_milestone (
+    id integer PRIMARY KEY,
+    create_date integer DEFAULT (unixepoch()) NOT NULL,
+    player text,
+    count integer NOT NULL,
+    npc_key text NOT NULL,
+    npc_name text NOT NULL
+);
+
+CREATE INDEX death_victim_idx ON death (victim);
+CREATE INDEX message_author_idx ON message (author);
@@ -0,0 +1,11 @@
+-- Audit trail of players forgotten with /forget
+CREATE TABLE player_erasure (
+    id integer PRIMARY KEY,
+    create_date integer DEFAULT (unixepoch()) NOT NULL,
+    player text NOT NULL,
+    requested_by text NOT NULL,
+    messages integer NOT NULL,
+    deaths integer NOT NULL,
+    sessions integer NOT NULL,
+    kills integer NOT NULL
+);
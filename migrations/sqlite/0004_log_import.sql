@@ -0,0 +1,7 @@
+-- Server logs imported with `terraria-discord import-log`, by a fingerprint of the start of the file
+CREATE TABLE log_import (
+    fingerprint text PRIMARY KEY,
+    create_date integer DEFAULT (unixepoch()) NOT NULL,
+    update_date integer DEFAULT (unixepoch()) NOT NULL,
+    lines integer NOT NULL
+);
@@ -0,0 +1,7 @@
+-- Messages for discord, kept until they're sent so they survive outages and restarts
+CREATE TABLE outbox (
+    id integer PRIMARY KEY,
+    create_date integer DEFAULT (unixepoch()) NOT NULL,
+    channel_id integer NOT NULL,
+    content text NOT NULL
+);
@@ -0,0 +1,5 @@
+-- Indexes for reading and pruning by date, matching postgres
+CREATE INDEX death_create_date_idx ON death (create_date);
+CREATE INDEX message_create_date_idx ON message (create_date);
+CREATE INDEX server_join_create_date_idx ON server_join (create_date);
+CREATE INDEX server_leave_username_idx ON server_leave (username, create_date);
@@ -0,0 +1,3 @@
+-- Who a queued message is about, so forgetting a player only drops their messages
+ALTER TABLE outbox ADD COLUMN player text;
+ALTER TABLE player_erasure ADD COLUMN queued integer DEFAULT 0 NOT NULL;
@@ -0,0 +1,15 @@
+-- Logs that start the same are told apart by a hash of every line imported from them, so there can
+-- be more than one import per fingerprint. Logs imported before hashes were kept have none
+CREATE TABLE log_import_new (
+    id integer PRIMARY KEY,
+    fingerprint text NOT NULL,
+    create_date integer DEFAULT (unixepoch()) NOT NULL,
+    update_date integer DEFAULT (unixepoch()) NOT NULL,
+    lines integer NOT NULL,
+    hash text
+);
+INSERT INTO log_import_new(fingerprint, create_date, update_date, lines)
+SELECT fingerprint, create_date, update_date, lines FROM log_import;
+DROP TABLE log_import;
+ALTER TABLE log_import_new RENAME TO log_import;
+CREATE INDEX log_import_fingerprint_idx ON log_import (fingerprint);
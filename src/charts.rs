@@ -0,0 +1,290 @@
+use crate::storage::DeathsPer;
+use chrono::{NaiveDate, NaiveDateTime, NaiveTime};
+use plotters::coord::ranged1d::SegmentValue;
+use plotters::prelude::*;
+use plotters::style::{FontStyle, register_font};
+use poise::ChoiceParameter;
+use std::collections::BTreeMap;
+
+pub const DEFAULT_FONT: &str = "/usr/share/fonts/truetype/dejavu/DejaVuSans.ttf";
+// Most players to show in a chart by player
+const MAX_PLAYERS: usize = 20;
+const WIDTH: u32 = 1200;
+const HEIGHT: u32 = 600;
+const BAR_COLOR: RGBColor = RGBColor(0x58, 0x65, 0xf2);
+
+#[derive(Clone, Copy, Default, ChoiceParameter)]
+pub enum GroupBy {
+    #[default]
+    #[name = "day"]
+    Day,
+    #[name = "hour"]
+    Hour,
+    #[name = "player"]
+    Player,
+}
+
+// Loads the font used for all chart text, without one charts can't be drawn
+pub fn load_font(path: &str) -> Result<(), String> {
+    let bytes = match std::fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(e) => return Err(format!("Unable to read chart font {path}: {e}")),
+    };
+    // fonts are registered for the life of the program
+    register_font("sans-serif", FontStyle::Normal, bytes.leak())
+        .map_err(|_| format!("Unable to load chart font {path}"))
+}
+
+// Bars of deaths counted per date, hour of the day, or victim, as labels and counts
+#[allow(clippy::cast_precision_loss)]
+pub fn deaths(
+    deaths: &[DeathsPer],
+    by: GroupBy,
+    first: NaiveDate,
+    last: NaiveDate,
+) -> (Vec<String>, Vec<f64>) {
+    match by {
+        GroupBy::Day => {
+            let mut counts: BTreeMap<NaiveDate, f64> = first
+                .iter_days()
+                .take_while(|d| *d <= last)
+                .map(|d| (d, 0.0))
+                .collect();
+            for day in deaths {
+                if let Some(count) = NaiveDate::parse_from_str(&day.label, "%Y-%m-%d")
+                    .ok()
+                    .and_then(|date| counts.get_mut(&date))
+                {
+                    *count += day.deaths as f64;
+                }
+            }
+            counts
+                .into_iter()
+                .map(|(date, count)| (date.format("%m-%d").to_string(), count))
+                .unzip()
+        }
+        GroupBy::Hour => {
+            let mut counts = [0.0; 24];
+            for hour in deaths {
+                if let Some(count) = hour
+                    .label
+                    .parse::<usize>()
+                    .ok()
+                    .and_then(|hour| counts.get_mut(hour))
+                {
+                    *count += hour.deaths as f64;
+                }
+            }
+            (
+                (0..24).map(|hour| format!("{hour}")).collect(),
+                counts.to_vec(),
+            )
+        }
+        GroupBy::Player => {
+            let mut counts: Vec<&DeathsPer> = deaths.iter().collect();
+            counts.sort_by(|a, b| b.deaths.cmp(&a.deaths).then(a.label.cmp(&b.label)));
+            counts.truncate(MAX_PLAYERS);
+            counts
+                .into_iter()
+                .map(|victim| (victim.label.clone(), victim.deaths as f64))
+                .unzip()
+        }
+    }
+}
+
+// Hours played on each day from first to last, with sessions split at midnight
+pub fn playtime(
+    spans: &[(NaiveDateTime, NaiveDateTime)],
+    first: NaiveDate,
+    last: NaiveDate,
+) -> (Vec<String>, Vec<f64>) {
+    let mut hours: BTreeMap<NaiveDate, f64> = first
+        .iter_days()
+        .take_while(|d| *d <= last)
+        .map(|d| (d, 0.0))
+        .collect();
+    for (start, end) in spans {
+        let mut start = *start;
+        while start < *end {
+            let midnight = start
+                .date()
+                .succ_opt()
+                .map_or(*end, |d| d.and_time(NaiveTime::MIN));
+            let until = midnight.min(*end);
+            if let Some(day) = hours.get_mut(&start.date()) {
+                #[allow(clippy::cast_precision_loss)]
+                let played = (until - start).num_seconds() as f64 / 3600.0;
+                *day += played;
+            }
+            start = until;
+        }
+    }
+    hours
+        .into_iter()
+        .map(|(date, hours)| (date.format("%m-%d").to_string(), hours))
+        .unzip()
+}
+
+// Draws a bar for each value, returning a PNG
+pub fn bar_chart(title: &str, labels: &[String], values: &[f64]) -> Result<Vec<u8>, String> {
+    draw(|root| {
+        let max = values.iter().copied().fold(1.0, f64::max);
+        let mut chart = ChartBuilder::on(&root)
+            .caption(title, ("sans-serif", 28))
+            .margin(16)
+            .x_label_area_size(40)
+            .y_label_area_size(50)
+            .build_cartesian_2d((0..labels.len()).into_segmented(), 0.0..max * 1.1)?;
+        chart
+            .configure_mesh()
+            .disable_x_mesh()
+            .x_labels(labels.len())
+            .x_label_formatter(&|x| match x {
+                SegmentValue::CenterOf(i) => labels.get(*i).cloned().unwrap_or_default(),
+                _ => String::new(),
+            })
+            .draw()?;
+        chart.draw_series(values.iter().enumerate().map(|(i, value)| {
+            let mut bar = Rectangle::new(
+                [
+                    (SegmentValue::Exact(i), 0.0),
+                    (SegmentValue::Exact(i + 1), *value),
+                ],
+                BAR_COLOR.filled(),
+            );
+            bar.set_margin(0, 0, 4, 4);
+            bar
+        }))?;
+        Ok(())
+    })
+}
+
+// Draws a line through values, returning a PNG
+pub fn line_chart(title: &str, labels: &[String], values: &[f64]) -> Result<Vec<u8>, String> {
+    draw(|root| {
+        let max = values.iter().copied().fold(1.0, f64::max);
+        let mut chart = ChartBuilder::on(&root)
+            .caption(title, ("sans-serif", 28))
+            .margin(16)
+            .x_label_area_size(40)
+            .y_label_area_size(50)
+            .build_cartesian_2d(0..labels.len().saturating_sub(1).max(1), 0.0..max * 1.1)?;
+        chart
+            .configure_mesh()
+            .disable_x_mesh()
+            .x_labels(labels.len().min(15))
+            .x_label_formatter(&|i| labels.get(*i).cloned().unwrap_or_default())
+            .draw()?;
+        chart.draw_series(LineSeries::new(
+            values.iter().copied().enumerate(),
+            BAR_COLOR.stroke_width(3),
+        ))?;
+        Ok(())
+    })
+}
+
+type DrawResult = Result<(), Box<dyn std::error::Error>>;
+
+fn draw(
+    chart: impl FnOnce(DrawingArea<BitMapBackend, plotters::coord::Shift>) -> DrawResult,
+) -> Result<Vec<u8>, String> {
+    let mut pixels = vec![0; (WIDTH * HEIGHT * 3) as usize];
+    {
+        let root = BitMapBackend::with_buffer(&mut pixels, (WIDTH, HEIGHT)).into_drawing_area();
+        let drawn = root
+            .fill(&WHITE)
+            .map_err(Into::into)
+            .and_then(|()| chart(root.clone()))
+            .and_then(|()| root.present().map_err(Into::into));
+        if let Err(e) = drawn {
+            return Err(format!("Unable to draw chart: {e}"));
+        }
+    }
+
+    let mut png = vec![];
+    let mut encoder = png::Encoder::new(&mut png, WIDTH, HEIGHT);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    if let Err(e) = encoder
+        .write_header()
+        .and_then(|mut writer| writer.write_image_data(&pixels))
+    {
+        return Err(format!("Unable to encode chart: {e}"));
+    }
+    Ok(png)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::GroupBy;
+    use crate::storage::DeathsPer;
+    use chrono::{NaiveDate, NaiveDateTime};
+
+    fn at(day: u32, hour: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2024, 1, day)
+            .and_then(|d| d.and_hms_opt(hour, 0, 0))
+            .unwrap_or_default()
+    }
+
+    fn counts(counts: &[(&str, i64)]) -> Vec<DeathsPer> {
+        counts
+            .iter()
+            .map(|(label, deaths)| DeathsPer {
+                label: (*label).to_string(),
+                deaths: *deaths,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn deaths() {
+        let (labels, values) = super::deaths(
+            &counts(&[("2023-12-31", 4), ("2024-01-02", 3)]),
+            GroupBy::Day,
+            at(1, 0).date(),
+            at(3, 0).date(),
+        );
+        assert_eq!(labels, ["01-01", "01-02", "01-03"]);
+        assert_eq!(values, [0.0, 3.0, 0.0]);
+
+        let (labels, values) = super::deaths(
+            &counts(&[("00", 1), ("23", 2)]),
+            GroupBy::Hour,
+            at(1, 0).date(),
+            at(1, 0).date(),
+        );
+        assert_eq!((labels.len(), labels[23].as_str()), (24, "23"));
+        assert_eq!((values[0], values[1], values[23]), (1.0, 0.0, 2.0));
+
+        let (labels, values) = super::deaths(
+            &counts(&[("alice", 1), ("bob", 2), ("carol", 1)]),
+            GroupBy::Player,
+            at(1, 0).date(),
+            at(1, 0).date(),
+        );
+        assert_eq!(labels, ["bob", "alice", "carol"]);
+        assert_eq!(values, [2.0, 1.0, 1.0]);
+    }
+
+    #[test]
+    fn playtime() {
+        let first = at(1, 0).date();
+        let last = at(3, 0).date();
+        let spans = [
+            (at(1, 22), at(2, 1)),
+            (at(2, 12), at(2, 13)),
+            (at(3, 23), at(4, 2)),
+        ];
+        assert_eq!(
+            (
+                vec![
+                    "01-01".to_string(),
+                    "01-02".to_string(),
+                    "01-03".to_string()
+                ],
+                vec![2.0, 2.0, 1.0]
+            ),
+            super::playtime(&spans, first, last)
+        );
+    }
+}
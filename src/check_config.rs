@@ -0,0 +1,178 @@
+use crate::config::Config;
+use crate::log_patterns::LogPatterns;
+use crate::{charts, connect_storage, digest};
+use serenity::http::Http;
+use serenity::model::id::{ChannelId, UserId};
+use std::path::Path;
+use std::process::Stdio;
+use tokio::process::Command;
+use tokio::time::{Duration, timeout};
+use tracing::{error, info, warn};
+
+// How long tcpdump has to fail before it's assumed to be capturing
+const TCPDUMP_GRACE: Duration = Duration::from_secs(2);
+
+// Checks everything the bot needs from the config is usable, logging what isn't, and returns whether
+// it all is
+pub async fn run(cfg: &Config) -> bool {
+    let mut ok = true;
+    let mut report = |check: &str, result: Result<(), String>| match result {
+        Ok(()) => info!(check, "ok"),
+        Err(e) => {
+            error!(check, error = %e, "failed");
+            ok = false;
+        }
+    };
+
+    report("server_logfile", logfile(&cfg.server_logfile));
+    report(
+        "log_offset_file",
+        parent_dir(
+            cfg.log_offset_file
+                .as_deref()
+                .unwrap_or(crate::DEFAULT_LOG_OFFSET_FILE),
+        ),
+    );
+    report("server_dir", server_dir(&cfg.server_dir));
+    report(
+        "log_patterns",
+        LogPatterns::new(&cfg.log_patterns).map(|_| ()),
+    );
+    if let Some(digest) = &cfg.digest {
+        report(
+            "digest",
+            digest::Schedule::new(
+                digest.every,
+                digest.at.as_deref(),
+                digest.weekday,
+                digest.timezone.as_deref(),
+            )
+            .map(|_| ()),
+        );
+    }
+    if let Err(e) = charts::load_font(cfg.chart_font.as_deref().unwrap_or(charts::DEFAULT_FONT)) {
+        warn!(check = "chart_font", error = %e, "charts will not be available");
+    }
+
+    if let Some(addr) = cfg.metrics_addr {
+        report(
+            "metrics_addr",
+            tokio::net::TcpListener::bind(addr)
+                .await
+                .map(|_| ())
+                .map_err(|e| format!("Unable to listen on {addr}: {e}")),
+        );
+    }
+
+    report("discord", discord(cfg).await);
+    report("storage", connect_storage(cfg).await.map(|_| ()));
+    report(
+        "tcpdump",
+        tcpdump(&cfg.tcpdump.interface, cfg.tcpdump.port).await,
+    );
+
+    if ok {
+        info!("config is valid");
+    }
+    ok
+}
+
+fn logfile(path: &str) -> Result<(), String> {
+    match std::fs::File::open(path) {
+        Ok(_) => Ok(()),
+        // it's created when the server starts
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => parent_dir(path).map(|()| {
+            warn!(
+                path,
+                "server log doesn't exist yet, it will be read once it does"
+            );
+        }),
+        Err(e) => Err(format!("Unable to open {path}: {e}")),
+    }
+}
+
+fn parent_dir(path: &str) -> Result<(), String> {
+    let parent = match Path::new(path).parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    if parent.is_dir() {
+        Ok(())
+    } else {
+        Err(format!("{} isn't a directory", parent.display()))
+    }
+}
+
+fn server_dir(path: &str) -> Result<(), String> {
+    let dir = Path::new(path);
+    if !dir.is_dir() {
+        return Err(format!("{path} isn't a directory"));
+    }
+    // /update and /restart start the server with it
+    if !dir.join("start_server.sh").is_file() {
+        warn!(
+            path,
+            "start_server.sh is missing, /update and /restart won't work"
+        );
+    }
+    Ok(())
+}
+
+// Checks the token works, the bridge channel can be seen, and the admins exist
+async fn discord(cfg: &Config) -> Result<(), String> {
+    if cfg.bot_token.is_empty() {
+        return Err("Missing bot_token, set it or bot_token_file".to_string());
+    }
+    let http = Http::new(&cfg.bot_token);
+    let bot = match http.get_current_user().await {
+        Ok(bot) => bot,
+        Err(e) => return Err(format!("Unable to log in with bot_token: {e}")),
+    };
+    if let Err(e) = http
+        .get_channel(ChannelId::new(cfg.bridge_channel_id))
+        .await
+    {
+        return Err(format!(
+            "Unable to see bridge_channel_id {} as {}: {e}",
+            cfg.bridge_channel_id, bot.name
+        ));
+    }
+    for &admin in std::iter::once(&cfg.admin_user_id).chain(&cfg.admin_user_ids) {
+        if let Err(e) = http.get_user(UserId::new(admin)).await {
+            return Err(format!("Unable to find admin user {admin}: {e}"));
+        }
+    }
+    Ok(())
+}
+
+// Starts tcpdump the way terraria_pcap does, it's working if it's still running after a moment
+async fn tcpdump(interface: &str, port: u16) -> Result<(), String> {
+    let child = Command::new("tcpdump")
+        .args([
+            "-i",
+            interface,
+            "tcp",
+            "src",
+            "port",
+            &port.to_string(),
+            "-w",
+            "/dev/null",
+        ])
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn();
+    let child = match child {
+        Ok(child) => child,
+        Err(e) => return Err(format!("Unable to run tcpdump: {e}")),
+    };
+    match timeout(TCPDUMP_GRACE, child.wait_with_output()).await {
+        Err(_) => Ok(()),
+        Ok(Ok(output)) => Err(format!(
+            "tcpdump exited with {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )),
+        Ok(Err(e)) => Err(format!("Unable to run tcpdump: {e}")),
+    }
+}
@@ -1,50 +1,617 @@
 use crate::Data;
-use std::collections::HashMap;
+use crate::charts::{self, GroupBy};
+use crate::export::{self, Format, Table};
+use crate::metrics;
+use crate::storage::{DeathFilter, DeathGroup, session_spans};
+use crate::terraria_pcap::friendly_duration;
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, NaiveTime, TimeDelta, Utc};
+use chrono_tz::Tz;
+use futures::TryStreamExt;
+use poise::serenity_prelude::{CreateAttachment, CreateEmbed};
+use poise::{ChoiceParameter, CreateReply};
 use std::fmt::Write as _;
 use tokio::process::Command;
 use tokio::sync::oneshot;
-use tokio::time::{Duration, sleep};
+use tokio::time::{Duration, Instant, sleep};
 
 type Error = Box<dyn std::error::Error + Send + Sync>;
 type Context<'a> = poise::Context<'a, Data, Error>;
 
+const LINES_PER_PAGE: usize = 20;
+// Most characters on a page, embed descriptions can be up to 4096
+const CHARS_PER_PAGE: usize = 4000;
+// How far back charts by day go unless told otherwise
+const CHART_DAYS: i64 = 30;
+
 /// Show players sorted by how many times they've died
 #[poise::command(slash_command, prefix_command)]
-pub async fn deaths(ctx: Context<'_>) -> Result<(), Error> {
-    let data = ctx.data();
-    #[allow(clippy::expect_used)]
-    let db = &data.db;
+pub async fn deaths(
+    ctx: Context<'_>,
+    #[description = "Only count deaths since (e.g. 7d, 12h, 2024-01-31)"] since: Option<String>,
+    #[description = "Only count deaths caused by this killer"] killer: Option<String>,
+    #[description = "Only count deaths from other players"] pvp: Option<bool>,
+    #[description = "World to count deaths in, or all (defaults to the current world)"]
+    world: Option<String>,
+) -> Result<(), Error> {
+    let db = &ctx.data().db;
+    let world = stats_world(ctx, world).await?;
+
+    let since = match since.as_deref().map(parse_since).transpose() {
+        Ok(since) => since,
+        Err(e) => {
+            ctx.say(e).await?;
+            return Ok(());
+        }
+    };
 
-    #[allow(clippy::panic)]
-    match sqlx::query!("SELECT victim FROM death").fetch_all(db).await {
+    match db
+        .death_counts(&DeathFilter {
+            since: since.map(|since| since.with_timezone(&Utc)),
+            killer,
+            pvp_only: pvp.unwrap_or(false),
+            world,
+        })
+        .await
+    {
         Err(e) => Err(format!("Unable to query deaths: {e}").into()),
         Ok(rows) => {
-            let mut death_map: HashMap<String, u32> = HashMap::new();
-            for row in rows {
-                let victim: String = row.victim;
-                match death_map.get(&victim) {
-                    None => death_map.insert(victim, 1),
-                    Some(&victim_deaths) => death_map.insert(victim, victim_deaths + 1),
-                };
-            }
-            let mut deaths = vec![];
-            for (victim, victim_deaths) in &death_map {
-                deaths.push((victim, victim_deaths));
+            let lines: Vec<String> = rows
+                .iter()
+                .map(|row| format!("{} - {}", row.victim, row.deaths))
+                .collect();
+
+            if let Err(e) = say_paginated(ctx, &lines, "No deaths, yet...").await {
+                return Err(format!("Error replying to deaths command: {e}").into());
             }
-            deaths.sort_by(|a, b| b.1.cmp(a.1));
 
-            let mut content = String::new();
-            for death in deaths {
-                writeln!(content, "{} - {}", death.0, death.1)?;
+            Ok(())
+        }
+    }
+}
+
+/// Show player vs player kills, deaths, favorite weapons, and nemeses
+#[poise::command(slash_command, prefix_command)]
+pub async fn pvp(
+    ctx: Context<'_>,
+    #[description = "World to show, or all (defaults to the current world)"] world: Option<String>,
+) -> Result<(), Error> {
+    let db = &ctx.data().db;
+    let world = stats_world(ctx, world).await?;
+
+    let players = match db.pvp_players(world.as_deref()).await {
+        Ok(rows) => rows,
+        Err(e) => return Err(format!("Unable to query pvp kills: {e}").into()),
+    };
+
+    let weapons = match db.pvp_weapons(5, world.as_deref()).await {
+        Ok(rows) => rows,
+        Err(e) => return Err(format!("Unable to query pvp weapons: {e}").into()),
+    };
+
+    let nemeses = match db.pvp_nemeses(world.as_deref()).await {
+        Ok(rows) => rows,
+        Err(e) => return Err(format!("Unable to query pvp nemeses: {e}").into()),
+    };
+
+    if players.is_empty() {
+        ctx.say("No PvP kills, yet...").await?;
+        return Ok(());
+    }
+
+    let mut lines = vec!["**Kills / Deaths (K/D)**".to_string()];
+    for player in &players {
+        #[allow(clippy::cast_precision_loss)]
+        let ratio = if player.deaths == 0 {
+            player.kills as f64
+        } else {
+            player.kills as f64 / player.deaths as f64
+        };
+        lines.push(format!(
+            "{} - {} / {} ({ratio:.2})",
+            player.player, player.kills, player.deaths
+        ));
+    }
+    lines.push(String::new());
+    lines.push("**Weapons**".to_string());
+    for weapon in &weapons {
+        lines.push(format!("{} - {} kills", weapon.weapon, weapon.kills));
+    }
+    lines.push(String::new());
+    lines.push("**Nemeses**".to_string());
+    for nemesis in &nemeses {
+        lines.push(format!(
+            "{} was killed by {} {} times",
+            nemesis.victim, nemesis.killer, nemesis.kills
+        ));
+    }
+
+    if let Err(e) = say_paginated(ctx, &lines, "No PvP kills, yet...").await {
+        return Err(format!("Error replying to pvp command: {e}").into());
+    }
+
+    Ok(())
+}
+
+/// Show survival streak records and who holds them
+#[poise::command(slash_command, prefix_command)]
+pub async fn records(
+    ctx: Context<'_>,
+    #[description = "World to show, or all (defaults to the current world)"] world: Option<String>,
+) -> Result<(), Error> {
+    let db = &ctx.data().db;
+    let world = stats_world(ctx, world).await?;
+
+    let rows = match db.survival_records(None, world.as_deref()).await {
+        Ok(rows) => rows,
+        Err(e) => return Err(format!("Unable to query records: {e}").into()),
+    };
+
+    let (Some(longest), Some(fastest)) = (rows.first(), rows.iter().min_by_key(|row| row.fastest))
+    else {
+        ctx.say("No records, yet...").await?;
+        return Ok(());
+    };
+
+    let mut lines = vec![
+        format!(
+            "**Longest survival:** {} - {}",
+            longest.victim,
+            friendly_duration(longest.longest)
+        ),
+        format!(
+            "**Fastest back-to-back deaths:** {} - {}",
+            fastest.victim,
+            friendly_duration(fastest.fastest)
+        ),
+        String::new(),
+        "**Personal bests**".to_string(),
+    ];
+    for row in &rows {
+        lines.push(format!(
+            "{} - {}",
+            row.victim,
+            friendly_duration(row.longest)
+        ));
+    }
+
+    if let Err(e) = say_paginated(ctx, &lines, "No records, yet...").await {
+        return Err(format!("Error replying to records command: {e}").into());
+    }
+
+    Ok(())
+}
+
+/// Show when each boss was first defeated and how many times it's been killed
+#[poise::command(slash_command, prefix_command)]
+pub async fn bosses(
+    ctx: Context<'_>,
+    #[description = "World to show, or all (defaults to the current world)"] world: Option<String>,
+) -> Result<(), Error> {
+    let db = &ctx.data().db;
+    let world = stats_world(ctx, world).await?;
+
+    let rows = match db.boss_summaries(world.as_deref()).await {
+        Ok(rows) => rows,
+        Err(e) => return Err(format!("Unable to query bosses: {e}").into()),
+    };
+
+    let lines: Vec<String> = rows
+        .iter()
+        .map(|row| {
+            let mut line = match row.first_kill {
+                None => format!(
+                    "**{}** - not defeated yet, {} attempts",
+                    row.boss, row.attempts
+                ),
+                Some(first_kill) => format!(
+                    "**{}** - first defeated {}, {} kills",
+                    row.boss,
+                    first_kill.with_timezone(&Local).format("%Y-%m-%d"),
+                    row.kills
+                ),
+            };
+            if row.first_kill.is_some() && row.attempts > 0 {
+                write!(line, ", took {} attempts", row.attempts).ok();
             }
-            if content.is_empty() {
-                writeln!(content, "No deaths, yet...")?;
+            if let Some(fastest) = row.fastest {
+                write!(line, ", fastest in {}", friendly_duration(fastest)).ok();
             }
+            line
+        })
+        .collect();
 
-            if let Err(e) = ctx.say(content).await {
-                return Err(format!("Error replying to deaths command: {e}").into());
+    if let Err(e) = say_paginated(ctx, &lines, "No bosses, yet...").await {
+        return Err(format!("Error replying to bosses command: {e}").into());
+    }
+
+    Ok(())
+}
+
+/// Show a timeline of invasions and other world events
+#[poise::command(slash_command, prefix_command)]
+pub async fn events(
+    ctx: Context<'_>,
+    #[description = "World to show, or all (defaults to the current world)"] world: Option<String>,
+) -> Result<(), Error> {
+    let db = &ctx.data().db;
+    let world = stats_world(ctx, world).await?;
+
+    let rows = match db.world_events(200, world.as_deref()).await {
+        Ok(rows) => rows,
+        Err(e) => return Err(format!("Unable to query events: {e}").into()),
+    };
+
+    let lines: Vec<String> = rows
+        .iter()
+        .map(|row| {
+            let start = row
+                .start_date
+                .with_timezone(&Local)
+                .format("%Y-%m-%d %H:%M");
+            match (row.is_invasion, row.duration) {
+                (true, Some(duration)) => format!(
+                    "{start} - {} (defeated in {})",
+                    row.name,
+                    friendly_duration(duration)
+                ),
+                (true, None) => format!("{start} - {} (not defeated)", row.name),
+                (false, _) => format!("{start} - {}", row.name),
             }
+        })
+        .collect();
 
+    if let Err(e) = say_paginated(ctx, &lines, "No events, yet...").await {
+        return Err(format!("Error replying to events command: {e}").into());
+    }
+
+    Ok(())
+}
+
+/// Show which enemy kill milestones players have reached
+#[poise::command(slash_command, prefix_command)]
+pub async fn kills(
+    ctx: Context<'_>,
+    #[description = "Only show milestones reached by this player"] player: Option<String>,
+    #[description = "World to show, or all (defaults to the current world)"] world: Option<String>,
+) -> Result<(), Error> {
+    let db = &ctx.data().db;
+    let world = stats_world(ctx, world).await?;
+
+    let rows = match db
+        .kill_milestones(player.as_deref(), world.as_deref())
+        .await
+    {
+        Ok(rows) => rows,
+        Err(e) => return Err(format!("Unable to query kill milestones: {e}").into()),
+    };
+
+    // one line per enemy, listing everyone who's reached a milestone for it
+    let mut lines: Vec<String> = vec![];
+    let mut last_npc = None;
+    for row in &rows {
+        if last_npc == Some(&row.npc_name)
+            && let Some(line) = lines.last_mut()
+        {
+            write!(line, ", {} ({})", row.player, row.count).ok();
+        } else {
+            lines.push(format!(
+                "**{}** - {} ({})",
+                row.npc_name, row.player, row.count
+            ));
+        }
+        last_npc = Some(&row.npc_name);
+    }
+
+    if let Err(e) = say_paginated(ctx, &lines, "No kill milestones, yet...").await {
+        return Err(format!("Error replying to kills command: {e}").into());
+    }
+
+    Ok(())
+}
+
+/// Search in-game chat history
+#[poise::command(slash_command, prefix_command)]
+pub async fn search(
+    ctx: Context<'_>,
+    #[description = "Words to search for"] text: String,
+    #[description = "Only search messages from this player"] player: Option<String>,
+    #[description = "Only search messages since (e.g. 7d, 12h, 2024-01-31)"] since: Option<String>,
+    #[description = "World to search messages in, or all (defaults to the current world)"]
+    world: Option<String>,
+) -> Result<(), Error> {
+    let db = &ctx.data().db;
+    let world = stats_world(ctx, world).await?;
+
+    let since = match since.as_deref().map(parse_since).transpose() {
+        Ok(since) => since,
+        Err(e) => {
+            ctx.say(e).await?;
+            return Ok(());
+        }
+    };
+
+    let rows = match db
+        .search_messages(
+            &text,
+            player.as_deref(),
+            since.map(|since| since.with_timezone(&Utc)),
+            world.as_deref(),
+            500,
+        )
+        .await
+    {
+        Ok(rows) => rows,
+        Err(e) => return Err(format!("Unable to search messages: {e}").into()),
+    };
+
+    let lines: Vec<String> = rows
+        .iter()
+        .map(|row| {
+            format!(
+                "`{}` <{}> {}",
+                row.create_date
+                    .with_timezone(&Local)
+                    .format("%Y-%m-%d %H:%M"),
+                row.author,
+                row.content
+            )
+        })
+        .collect();
+
+    if let Err(e) = say_paginated(ctx, &lines, "No matching messages").await {
+        return Err(format!("Error replying to search command: {e}").into());
+    }
+
+    Ok(())
+}
+
+/// Export deaths, chat messages, or play sessions as a file
+#[poise::command(slash_command, prefix_command)]
+pub async fn export(
+    ctx: Context<'_>,
+    #[description = "What to export"] table: Table,
+    #[description = "File format (default csv)"] format: Option<Format>,
+    #[description = "Only export since (e.g. 7d, 12h, 2024-01-31)"] since: Option<String>,
+    #[description = "World to export, or all (defaults to the current world)"] world: Option<
+        String,
+    >,
+) -> Result<(), Error> {
+    let world = stats_world(ctx, world).await?;
+    let since = match since.as_deref().map(parse_since).transpose() {
+        Ok(since) => since,
+        Err(e) => {
+            ctx.say(e).await?;
+            return Ok(());
+        }
+    };
+
+    // reading and compressing a large export can take longer than discord waits for a response
+    ctx.defer().await?;
+    let export = match export::export(
+        ctx.data().db.as_ref(),
+        table,
+        format.unwrap_or_default(),
+        since.map(|since| since.with_timezone(&Utc)),
+        world.as_deref(),
+        Some(export::ATTACHMENT_BYTES),
+    )
+    .await
+    {
+        Ok(export) => export,
+        Err(e) => {
+            ctx.say(e).await?;
+            return Ok(());
+        }
+    };
+
+    if export.rows == 0 {
+        ctx.say(format!("No {}, yet...", table.name())).await?;
+        return Ok(());
+    }
+    ctx.send(
+        CreateReply::default()
+            .content(format!("{} {}", export.rows, table.name()))
+            .attachment(CreateAttachment::bytes(export.data, export.filename)),
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// Draw a chart of deaths or playtime
+#[allow(clippy::unused_async)]
+#[poise::command(
+    slash_command,
+    prefix_command,
+    subcommands("chart_deaths", "chart_playtime"),
+    subcommand_required
+)]
+pub async fn chart(_ctx: Context<'_>) -> Result<(), Error> {
+    Ok(())
+}
+
+/// Chart deaths per day, by hour of the day, or by player
+#[poise::command(slash_command, prefix_command, rename = "deaths")]
+pub async fn chart_deaths(
+    ctx: Context<'_>,
+    #[description = "Group deaths by (default day)"] by: Option<GroupBy>,
+    #[description = "Only chart deaths since (e.g. 7d, 12h, 2024-01-31)"] since: Option<String>,
+    #[description = "World to chart deaths in, or all (defaults to the current world)"]
+    world: Option<String>,
+) -> Result<(), Error> {
+    let world = stats_world(ctx, world).await?;
+    let by = by.unwrap_or_default();
+    let since = match since.as_deref().map(parse_since).transpose() {
+        Ok(None) if matches!(by, GroupBy::Day) => Some(Local::now() - TimeDelta::days(CHART_DAYS)),
+        Ok(since) => since,
+        Err(e) => {
+            ctx.say(e).await?;
+            return Ok(());
+        }
+    };
+
+    ctx.defer().await?;
+    let group = match by {
+        GroupBy::Day => DeathGroup::Day,
+        GroupBy::Hour => DeathGroup::Hour,
+        GroupBy::Player => DeathGroup::Victim,
+    };
+    let timezone = local_timezone();
+    let now = Utc::now().with_timezone(&timezone);
+    let deaths = match ctx
+        .data()
+        .db
+        .deaths_per(
+            group,
+            since.map(|since| since.with_timezone(&Utc)),
+            world.as_deref(),
+            timezone,
+        )
+        .await
+    {
+        Ok(deaths) => deaths,
+        Err(e) => return Err(format!("Unable to query deaths: {e}").into()),
+    };
+    if deaths.is_empty() {
+        ctx.say("No deaths, yet...").await?;
+        return Ok(());
+    }
+
+    // day charts always have a since
+    let first = since.map_or(now.date_naive(), |since| {
+        since.with_timezone(&timezone).date_naive()
+    });
+    let (labels, values) = charts::deaths(&deaths, by, first, now.date_naive());
+    let title = match by {
+        GroupBy::Day => "Deaths per day",
+        GroupBy::Hour => "Deaths by hour of the day",
+        GroupBy::Player => "Deaths by player",
+    };
+    send_chart(
+        ctx,
+        charts::bar_chart(title, &labels, &values),
+        "deaths.png",
+    )
+    .await
+}
+
+/// Chart hours played per day
+#[poise::command(slash_command, prefix_command, rename = "playtime")]
+pub async fn chart_playtime(
+    ctx: Context<'_>,
+    #[description = "Only chart playtime since (e.g. 7d, 2024-01-31)"] since: Option<String>,
+    #[description = "World to chart playtime in, or all (defaults to the current world)"]
+    world: Option<String>,
+) -> Result<(), Error> {
+    let world = stats_world(ctx, world).await?;
+    let since = match since.as_deref().map(parse_since).transpose() {
+        Ok(since) => since.unwrap_or_else(|| Local::now() - TimeDelta::days(CHART_DAYS)),
+        Err(e) => {
+            ctx.say(e).await?;
+            return Ok(());
+        }
+    };
+
+    ctx.defer().await?;
+    let sessions: Vec<_> = match ctx
+        .data()
+        .db
+        .export_sessions(Some(since.with_timezone(&Utc)), world.as_deref())
+        .try_collect()
+        .await
+    {
+        Ok(sessions) => sessions,
+        Err(e) => return Err(format!("Unable to query sessions: {e}").into()),
+    };
+    if sessions.is_empty() {
+        ctx.say("Nobody has played, yet...").await?;
+        return Ok(());
+    }
+
+    let spans: Vec<(NaiveDateTime, NaiveDateTime)> = session_spans(&sessions, Utc::now())
+        .into_iter()
+        .map(|(_, joined, left)| {
+            (
+                joined.with_timezone(&Local).naive_local(),
+                left.with_timezone(&Local).naive_local(),
+            )
+        })
+        .collect();
+    let (labels, values) = charts::playtime(&spans, since.date_naive(), Local::now().date_naive());
+    send_chart(
+        ctx,
+        charts::line_chart("Hours played per day", &labels, &values),
+        "playtime.png",
+    )
+    .await
+}
+
+async fn send_chart(
+    ctx: Context<'_>,
+    png: Result<Vec<u8>, String>,
+    filename: &str,
+) -> Result<(), Error> {
+    let png = png?;
+    ctx.send(CreateReply::default().attachment(CreateAttachment::bytes(png, filename)))
+        .await?;
+    Ok(())
+}
+
+/// Show a random chat message from a player
+#[poise::command(slash_command, prefix_command)]
+pub async fn quote(
+    ctx: Context<'_>,
+    #[description = "Player to quote"] player: String,
+    #[description = "World to quote from, or all (defaults to the current world)"] world: Option<
+        String,
+    >,
+) -> Result<(), Error> {
+    let db = &ctx.data().db;
+    let world = stats_world(ctx, world).await?;
+
+    match db.random_message(&player, world.as_deref()).await {
+        Err(e) => Err(format!("Unable to query quote: {e}").into()),
+        Ok(None) => {
+            ctx.say(format!("{player} hasn't said anything, yet..."))
+                .await?;
+            Ok(())
+        }
+        Ok(Some(row)) => {
+            ctx.say(format!(
+                "> {}\n— {}, {}",
+                row.content,
+                row.author,
+                row.create_date.with_timezone(&Local).format("%Y-%m-%d")
+            ))
+            .await?;
+            Ok(())
+        }
+    }
+}
+
+/// Delete a player's chat and history, and anonymize deaths they caused
+#[poise::command(slash_command, prefix_command)]
+pub async fn forget(
+    ctx: Context<'_>,
+    #[description = "Player to forget"] player: String,
+) -> Result<(), Error> {
+    if !is_admin(ctx).await? {
+        return Ok(());
+    }
+
+    match ctx
+        .data()
+        .db
+        .forget_player(&player, &ctx.author().id.to_string())
+        .await
+    {
+        Err(e) => Err(format!("Unable to forget player: {e}").into()),
+        Ok(erasure) => {
+            ctx.say(format!(
+                "Forgot {player}: deleted {} messages, {} deaths, {} joins and leaves, and {} unsent bridge messages, anonymized {} kills",
+                erasure.messages, erasure.deaths, erasure.sessions, erasure.queued, erasure.kills
+            ))
+            .await?;
             Ok(())
         }
     }
@@ -69,18 +636,13 @@ pub async fn update(
     #[description = "Current server version"] old_version: String,
     #[description = "New version to update to"] new_version: String,
 ) -> Result<(), Error> {
-    // only allow admin to invocate command
-    let author_id = ctx.author().id;
-    let admin_user_id = ctx.data().admin_user_id;
-    if author_id != admin_user_id {
-        ctx.say(format!("I only listen to <@{admin_user_id}>"))
-            .await?;
+    if !is_admin(ctx).await? {
         return Ok(());
     }
 
     ctx.defer().await?;
 
-    let server_dir = ctx.data().server_dir.clone();
+    let server_dir = ctx.data().settings.borrow().server_dir.clone();
     let zipfile = format!("terraria-server-{new_version}.zip");
 
     // download new zip
@@ -89,7 +651,7 @@ pub async fn update(
         .args([
             "-o",
             &zipfile,
-            &format!("https://terraria.org/api/download/pc-dedicated-server/{zipfile}",),
+            &format!("https://terraria.org/api/download/pc-dedicated-server/{zipfile}"),
         ])
         .output()
         .await?
@@ -189,12 +751,7 @@ pub async fn update(
 /// Restarts the server
 #[poise::command(slash_command, prefix_command)]
 pub async fn restart(ctx: Context<'_>) -> Result<(), Error> {
-    // only allow admin to invocate command
-    let author_id = ctx.author().id;
-    let admin_user_id = ctx.data().admin_user_id;
-    if author_id != admin_user_id {
-        ctx.say(format!("I only listen to <@{admin_user_id}>"))
-            .await?;
+    if !is_admin(ctx).await? {
         return Ok(());
     }
 
@@ -240,7 +797,8 @@ pub async fn restart(ctx: Context<'_>) -> Result<(), Error> {
     Ok(())
 }
 
-async fn send_server_command(ctx: Context<'_>, command: &str) -> Result<(), Error> {
+async fn send_server_command(ctx: Context<'_>, command: &'static str) -> Result<(), Error> {
+    let sent = Instant::now();
     let rx = {
         let channels_arc = ctx.data().command_response_channels.clone();
         let mut channels = channels_arc.lock().await;
@@ -267,6 +825,7 @@ async fn send_server_command(ctx: Context<'_>, command: &str) -> Result<(), Erro
         res = rx => Some(res?),
         () = sleep(Duration::from_secs(5)) => None,
     } {
+        metrics::command_answered(command, sent);
         ctx.say(response).await?;
     } else {
         ctx.say("⚠ unable to read server log").await?;
@@ -274,3 +833,180 @@ async fn send_server_command(ctx: Context<'_>, command: &str) -> Result<(), Erro
 
     Ok(())
 }
+
+// Whether the author can use admin commands, telling them they can't if not
+async fn is_admin(ctx: Context<'_>) -> Result<bool, Error> {
+    let (admin_user_id, users, roles) = {
+        let settings = ctx.data().settings.borrow();
+        (
+            settings.admin_user_id,
+            settings.admin_user_ids.clone(),
+            settings.admin_role_ids.clone(),
+        )
+    };
+    if users.contains(&ctx.author().id) {
+        return Ok(true);
+    }
+    if !roles.is_empty()
+        && let Some(member) = ctx.author_member().await
+        && member.roles.iter().any(|role| roles.contains(role))
+    {
+        return Ok(true);
+    }
+    ctx.say(format!("I only listen to <@{admin_user_id}> and admins"))
+        .await?;
+    Ok(false)
+}
+
+// The world a stats command is for, or None for every world. Defaults to the world being played
+async fn stats_world(ctx: Context<'_>, world: Option<String>) -> Result<Option<String>, Error> {
+    match world {
+        Some(world) if world.eq_ignore_ascii_case("all") => Ok(None),
+        Some(world) => Ok(Some(world)),
+        None => match ctx.data().db.current_world().await {
+            Ok(world) => Ok(world),
+            Err(e) => Err(format!("Unable to query current world: {e}").into()),
+        },
+    }
+}
+
+// Parses either a relative duration ("30m", "12h", "7d", "2w") or a date ("2024-01-31") into the
+// point in time it refers to
+// The system's timezone by name, so charts follow its daylight saving changes
+fn local_timezone() -> Tz {
+    iana_time_zone::get_timezone()
+        .ok()
+        .and_then(|timezone| timezone.parse().ok())
+        .unwrap_or(Tz::UTC)
+}
+
+pub fn parse_since(since: &str) -> Result<DateTime<Local>, String> {
+    let since = since.trim();
+    if let Ok(date) = NaiveDate::parse_from_str(since, "%Y-%m-%d") {
+        return date
+            .and_time(NaiveTime::MIN)
+            .and_local_timezone(Local)
+            .earliest()
+            .ok_or_else(|| format!("{since} doesn't exist in the local timezone"));
+    }
+
+    let invalid =
+        || format!("Unable to understand `{since}`, try something like 7d, 12h, or 2024-01-31");
+    let unit_start = since
+        .find(|c: char| !c.is_ascii_digit())
+        .ok_or_else(invalid)?;
+    let amount: i64 = since[..unit_start].parse().map_err(|_| invalid())?;
+    let ago = match &since[unit_start..] {
+        "m" => TimeDelta::try_minutes(amount),
+        "h" => TimeDelta::try_hours(amount),
+        "d" => TimeDelta::try_days(amount),
+        "w" => TimeDelta::try_weeks(amount),
+        _ => None,
+    }
+    .ok_or_else(invalid)?;
+
+    Local::now().checked_sub_signed(ago).ok_or_else(invalid)
+}
+
+// Replies with lines split across pages of an embed, with buttons to flip between them
+async fn say_paginated(ctx: Context<'_>, lines: &[String], empty: &str) -> Result<(), Error> {
+    if lines.is_empty() {
+        ctx.say(empty).await?;
+        return Ok(());
+    }
+
+    let pages = pages(lines);
+    if pages.len() == 1 {
+        ctx.send(CreateReply::default().embed(CreateEmbed::new().description(&pages[0])))
+            .await?;
+        return Ok(());
+    }
+
+    let pages: Vec<&str> = pages.iter().map(String::as_str).collect();
+    poise::builtins::paginate(ctx, &pages).await?;
+
+    Ok(())
+}
+
+// Splits lines into pages of up to LINES_PER_PAGE lines and CHARS_PER_PAGE characters, cutting
+// short any line that wouldn't fit on a page by itself
+fn pages(lines: &[String]) -> Vec<String> {
+    let mut pages = vec![];
+    let mut page = String::new();
+    let mut page_lines = 0;
+    let mut page_chars = 0;
+    for line in lines {
+        let line = match line.char_indices().nth(CHARS_PER_PAGE - 1) {
+            Some((end, _)) => format!("{}…", &line[..end]),
+            None => line.clone(),
+        };
+        let chars = line.chars().count();
+        // the newline before it
+        let needed = if page_lines == 0 { chars } else { chars + 1 };
+        if page_lines > 0 && (page_lines == LINES_PER_PAGE || page_chars + needed > CHARS_PER_PAGE)
+        {
+            pages.push(std::mem::take(&mut page));
+            page_lines = 0;
+            page_chars = 0;
+        }
+        if page_lines > 0 {
+            page.push('\n');
+            page_chars += 1;
+        }
+        page.push_str(&line);
+        page_lines += 1;
+        page_chars += chars;
+    }
+    if page_lines > 0 {
+        pages.push(page);
+    }
+    pages
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CHARS_PER_PAGE, LINES_PER_PAGE};
+    use chrono::{Local, NaiveDate, TimeDelta};
+
+    #[test]
+    fn pages() {
+        let short: Vec<String> = (0..45).map(|i| i.to_string()).collect();
+        let pages = super::pages(&short);
+        assert_eq!(pages.len(), 3);
+        assert_eq!(pages[0].lines().count(), LINES_PER_PAGE);
+        assert_eq!(pages[2], "40\n41\n42\n43\n44");
+
+        let long: Vec<String> = (0..20).map(|_| "a".repeat(500)).collect();
+        let pages = super::pages(&long);
+        assert_eq!(pages.len(), 3);
+        assert!(
+            pages
+                .iter()
+                .all(|page| page.chars().count() <= CHARS_PER_PAGE)
+        );
+        assert_eq!(pages.concat().matches('a').count(), 20 * 500);
+
+        let pages = super::pages(&["é".repeat(5000), "next".into()]);
+        assert_eq!(pages.len(), 2);
+        assert_eq!(pages[0].chars().count(), CHARS_PER_PAGE);
+        assert_eq!(pages[1], "next");
+    }
+
+    #[test]
+    #[allow(clippy::unwrap_used)]
+    fn parse_since() {
+        let date = super::parse_since("2024-01-31").unwrap();
+        assert_eq!(
+            NaiveDate::from_ymd_opt(2024, 1, 31).unwrap(),
+            date.date_naive()
+        );
+
+        let week = super::parse_since("1w").unwrap();
+        let diff = Local::now() - week;
+        assert!(diff >= TimeDelta::days(7) && diff < TimeDelta::days(7) + TimeDelta::minutes(1));
+
+        assert!(super::parse_since("12").is_err());
+        assert!(super::parse_since("d").is_err());
+        assert!(super::parse_since("3y").is_err());
+    }
+}
@@ -0,0 +1,386 @@
+use crate::{digest, log_patterns, storage};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::net::SocketAddr;
+
+pub const DEFAULT_PATH: &str = "config.toml";
+// Environment variables starting with this override config.toml, with a double underscore between
+// a table and its keys, e.g. TERRARIA_DISCORD_POSTGRES__PASS sets pass under [postgres]
+const ENV_PREFIX: &str = "TERRARIA_DISCORD_";
+
+#[derive(Clone, PartialEq, Deserialize)]
+pub struct Config {
+    #[serde(default)]
+    pub bot_token: String,
+    // a file to read bot_token from instead, like a mounted secret
+    bot_token_file: Option<String>,
+    pub bridge_channel_id: u64,
+    pub admin_user_id: u64,
+    // more users, and members with any of these roles, who can use admin commands
+    #[serde(default)]
+    pub admin_user_ids: Vec<u64>,
+    #[serde(default)]
+    pub admin_role_ids: Vec<u64>,
+    pub server_dir: String,
+    pub server_logfile: String,
+    pub log_offset_file: Option<String>,
+    #[serde(default)]
+    pub log_patterns: log_patterns::PatternsConfig,
+    pub killing_spree: Option<u32>,
+    pub chart_font: Option<String>,
+    pub migrate_on_start: Option<bool>,
+    #[serde(default)]
+    pub storage: StorageKind,
+    #[cfg(feature = "postgres")]
+    pub postgres: Option<PgConfig>,
+    #[cfg(feature = "sqlite")]
+    pub sqlite: Option<SqliteConfig>,
+    pub tcpdump: TcpDumpConfig,
+    pub digest: Option<DigestConfig>,
+    // days to keep each table's rows, forever if a table isn't listed
+    #[serde(default)]
+    pub retention: HashMap<storage::PrunedTable, u32>,
+    // where to serve /metrics and /healthz, not served if unset
+    pub metrics_addr: Option<SocketAddr>,
+}
+
+#[derive(Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageKind {
+    #[default]
+    Postgres,
+    Sqlite,
+}
+
+impl StorageKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Postgres => "postgres",
+            Self::Sqlite => "sqlite",
+        }
+    }
+}
+
+#[cfg(feature = "postgres")]
+#[derive(Clone, PartialEq, Deserialize)]
+pub struct PgConfig {
+    pub host: String,
+    pub port: u16,
+    pub user: String,
+    #[serde(default)]
+    pub pass: String,
+    // a file to read pass from instead
+    pass_file: Option<String>,
+    pub dbname: String,
+}
+
+#[cfg(feature = "sqlite")]
+#[derive(Clone, PartialEq, Deserialize)]
+pub struct SqliteConfig {
+    pub path: String,
+}
+
+#[derive(Clone, PartialEq, Deserialize)]
+pub struct TcpDumpConfig {
+    pub interface: String,
+    pub port: u16,
+}
+
+#[derive(Clone, PartialEq, Deserialize)]
+pub struct DigestConfig {
+    pub every: digest::Period,
+    pub at: Option<String>,
+    pub weekday: Option<chrono::Weekday>,
+    pub timezone: Option<String>,
+}
+
+impl Config {
+    // Reads the config at path, with any overrides from the environment and secrets read from files
+    pub fn load(path: &str) -> Result<Self, String> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) => return Err(format!("Unable to read {path}: {e}")),
+        };
+        let table: toml::Table = match text.parse() {
+            Ok(table) => table,
+            Err(e) => return Err(format!("Unable to parse {path}: {e}")),
+        };
+        // vars() would panic on a variable that isn't unicode, those can't be overrides anyway
+        let vars = std::env::vars_os().filter_map(|(name, value)| {
+            Some((name.into_string().ok()?, value.into_string().ok()?))
+        });
+        Self::from_table(path, table, vars)
+    }
+
+    // The config in table, read from path, with overrides from vars applied
+    fn from_table(
+        path: &str,
+        mut table: toml::Table,
+        vars: impl Iterator<Item = (String, String)>,
+    ) -> Result<Self, String> {
+        let mut parsed = apply_env(&mut table, vars)?;
+        let mut cfg: Self = loop {
+            match toml::Value::Table(table.clone()).try_into() {
+                Ok(cfg) => break cfg,
+                Err(e) => {
+                    // toml names the key it couldn't read, an override there that read as a
+                    // number, boolean, or array was meant as a string
+                    let error = e.to_string();
+                    let Some(i) = parsed
+                        .iter()
+                        .position(|(keys, _)| error.contains(&format!("in `{}`", keys.join("."))))
+                    else {
+                        return Err(format!("Invalid config in {path}: {e}"));
+                    };
+                    let (keys, raw) = parsed.swap_remove(i);
+                    if let Some((key, tables)) = keys.split_last() {
+                        table_at(&mut table, tables)?.insert(key.clone(), toml::Value::String(raw));
+                    }
+                }
+            }
+        };
+
+        if cfg.bridge_channel_id == 0 {
+            return Err("Missing bridge_channel_id".to_string());
+        }
+        if cfg.admin_user_id == 0 {
+            return Err("Missing admin_user_id".to_string());
+        }
+        if cfg.admin_user_ids.contains(&0) || cfg.admin_role_ids.contains(&0) {
+            return Err("Invalid admin_user_ids or admin_role_ids, IDs can't be 0".to_string());
+        }
+        cfg.bot_token = read_secret("bot_token", cfg.bot_token, cfg.bot_token_file.as_deref())?;
+        #[cfg(feature = "postgres")]
+        if let Some(pg) = &mut cfg.postgres {
+            pg.pass = read_secret(
+                "postgres.pass",
+                std::mem::take(&mut pg.pass),
+                pg.pass_file.as_deref(),
+            )?;
+        }
+        Ok(cfg)
+    }
+}
+
+// Removes `--config <path>` or `--config=<path>` from args, returning the path or the default
+pub fn take_path(args: &mut Vec<String>) -> Result<String, String> {
+    let Some(i) = args
+        .iter()
+        .position(|arg| arg == "--config" || arg.starts_with("--config="))
+    else {
+        return Ok(DEFAULT_PATH.to_string());
+    };
+    let flag = args.remove(i);
+    if let Some(path) = flag.strip_prefix("--config=") {
+        return Ok(path.to_string());
+    }
+    if i < args.len() {
+        Ok(args.remove(i))
+    } else {
+        Err("--config needs a path".to_string())
+    }
+}
+
+// Removes `--<name>` or `--<name>=<value>` from args, returning its value, empty if it didn't have one,
+// or None if it wasn't there
+pub fn take_flag(args: &mut Vec<String>, name: &str) -> Option<String> {
+    let i = args.iter().position(|arg| {
+        arg.strip_prefix("--")
+            .and_then(|arg| arg.strip_prefix(name))
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('='))
+    })?;
+    let flag = args.remove(i);
+    Some(
+        flag.split_once('=')
+            .map(|(_, value)| value.to_string())
+            .unwrap_or_default(),
+    )
+}
+
+// Sets the key each TERRARIA_DISCORD_ variable names in table. Values replacing a string are kept
+// as strings, otherwise they're read as TOML if they can be, so numbers and booleans work. Returns
+// the keys and raw values of those read as TOML, in case they were meant as strings
+fn apply_env(
+    table: &mut toml::Table,
+    vars: impl Iterator<Item = (String, String)>,
+) -> Result<Vec<(Vec<String>, String)>, String> {
+    let mut parsed = vec![];
+    for (name, raw) in vars {
+        let Some(path) = name.strip_prefix(ENV_PREFIX) else {
+            continue;
+        };
+        let keys: Vec<String> = path.split("__").map(str::to_lowercase).collect();
+        let Some((key, tables)) = keys.split_last() else {
+            continue;
+        };
+        let parent =
+            table_at(table, tables).map_err(|e| format!("Unable to override with {path}: {e}"))?;
+        let value = match parent.get(key) {
+            Some(toml::Value::String(_)) => None,
+            _ => format!("value = {raw}")
+                .parse::<toml::Table>()
+                .ok()
+                .and_then(|mut parsed| parsed.remove("value")),
+        };
+        let value = match value {
+            Some(value) if !value.is_str() => {
+                parsed.push((keys.clone(), raw));
+                value
+            }
+            _ => toml::Value::String(raw),
+        };
+        parent.insert(key.clone(), value);
+    }
+    Ok(parsed)
+}
+
+// The table under table named by each of names in turn, added if it's missing
+fn table_at<'a>(
+    mut table: &'a mut toml::Table,
+    names: &[String],
+) -> Result<&'a mut toml::Table, String> {
+    for name in names {
+        let entry = table
+            .entry(name.clone())
+            .or_insert_with(|| toml::Value::Table(toml::Table::new()));
+        table = match entry {
+            toml::Value::Table(child) => child,
+            _ => return Err(format!("{name} isn't a table")),
+        };
+    }
+    Ok(table)
+}
+
+// The secret from file if one was given, otherwise value
+fn read_secret(name: &str, value: String, file: Option<&str>) -> Result<String, String> {
+    match file {
+        Some(file) => match fs::read_to_string(file) {
+            Ok(secret) => Ok(secret.trim_end_matches(['\r', '\n']).to_string()),
+            Err(e) => Err(format!("Unable to read {name} from {file}: {e}")),
+        },
+        None => Ok(value),
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::{Config, apply_env, take_flag, take_path};
+
+    #[test]
+    fn env() {
+        let mut table: toml::Table = r#"
+            bot_token = ""
+            bridge_channel_id = 0
+            [postgres]
+            pass = ""
+        "#
+        .parse()
+        .unwrap();
+        let vars = [
+            ("TERRARIA_DISCORD_BOT_TOKEN", "abc"),
+            ("TERRARIA_DISCORD_BRIDGE_CHANNEL_ID", "1234"),
+            ("TERRARIA_DISCORD_POSTGRES__PASS", "5678"),
+            ("TERRARIA_DISCORD_TCPDUMP__PORT", "7777"),
+            ("TERRARIA_DISCORD_RETENTION__MESSAGE", "30"),
+            ("HOME", "/root"),
+        ];
+        apply_env(
+            &mut table,
+            vars.iter()
+                .map(|(name, value)| (name.to_string(), value.to_string())),
+        )
+        .unwrap();
+        assert_eq!(
+            table,
+            r#"
+            bot_token = "abc"
+            bridge_channel_id = 1234
+            [postgres]
+            pass = "5678"
+            [tcpdump]
+            port = 7777
+            [retention]
+            message = 30
+            "#
+            .parse()
+            .unwrap()
+        );
+
+        assert!(
+            apply_env(
+                &mut table,
+                [("TERRARIA_DISCORD_BOT_TOKEN__X".to_string(), "1".to_string())].into_iter()
+            )
+            .is_err()
+        );
+    }
+
+    // values that read as TOML are still strings where strings are expected, even when the file
+    // doesn't set them
+    #[test]
+    fn env_strings() {
+        let table: toml::Table = r#"
+            admin_user_id = 1
+            server_dir = "/srv/terraria"
+            server_logfile = "server.log"
+            [postgres]
+            host = "localhost"
+            port = 5432
+            user = "terraria"
+            dbname = "terraria"
+            [tcpdump]
+            interface = "eth0"
+            port = 7777
+        "#
+        .parse()
+        .unwrap();
+        let vars = [
+            ("TERRARIA_DISCORD_BOT_TOKEN", "true"),
+            ("TERRARIA_DISCORD_BRIDGE_CHANNEL_ID", "1234"),
+            ("TERRARIA_DISCORD_POSTGRES__PASS", "12345678"),
+            ("TERRARIA_DISCORD_CHART_FONT", "[x]"),
+        ];
+        let config = Config::from_table(
+            "config.toml",
+            table,
+            vars.iter()
+                .map(|(name, value)| (name.to_string(), value.to_string())),
+        )
+        .unwrap();
+        assert_eq!(config.bot_token, "true");
+        assert_eq!(config.bridge_channel_id, 1234);
+        assert_eq!(config.chart_font.as_deref(), Some("[x]"));
+        #[cfg(feature = "postgres")]
+        assert_eq!(config.postgres.unwrap().pass, "12345678");
+    }
+
+    #[test]
+    fn path() {
+        let mut args = vec![
+            "export".to_string(),
+            "--config".to_string(),
+            "a.toml".to_string(),
+        ];
+        assert_eq!(take_path(&mut args).unwrap(), "a.toml");
+        assert_eq!(args, ["export"]);
+        let mut args = vec!["--config=b.toml".to_string()];
+        assert_eq!(take_path(&mut args).unwrap(), "b.toml");
+        assert_eq!(take_path(&mut args).unwrap(), "config.toml");
+        assert!(take_path(&mut vec!["--config".to_string()]).is_err());
+    }
+
+    #[test]
+    fn flags() {
+        let mut args = vec![
+            "--dry-run=out.txt".to_string(),
+            "--no-db".to_string(),
+            "--dry-runs".to_string(),
+        ];
+        assert_eq!(take_flag(&mut args, "no-db"), Some(String::new()));
+        assert_eq!(take_flag(&mut args, "dry-run"), Some("out.txt".to_string()));
+        assert_eq!(take_flag(&mut args, "dry-run"), None);
+        assert_eq!(args, ["--dry-runs"]);
+    }
+}
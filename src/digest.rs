@@ -0,0 +1,417 @@
+use crate::metrics::{self, Counter};
+use crate::outbox::Outbox;
+use crate::settings;
+use crate::storage::{Death, DeathCause, Storage, SurvivalRecord, session_spans};
+use crate::terraria_pcap::friendly_duration;
+use chrono::{DateTime, Datelike, NaiveTime, TimeDelta, TimeZone, Utc, Weekday};
+use chrono_tz::Tz;
+use futures::TryStreamExt;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::sync::Arc;
+use tokio::sync::watch;
+use tokio::time::sleep;
+use tracing::{error, info};
+
+// Most players to list as having played before eliding the rest
+const MAX_PLAYERS: usize = 10;
+// Most broken records to list
+const MAX_RECORDS: usize = 5;
+
+#[derive(Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Period {
+    Day,
+    Week,
+}
+
+pub struct Schedule {
+    period: Period,
+    at: NaiveTime,
+    weekday: Weekday,
+    tz: Tz,
+}
+
+impl Schedule {
+    // at is "HH:MM", defaulting to midnight. weekday is only used for weekly digests, defaulting to
+    // Monday. timezone defaults to the system's
+    pub fn new(
+        period: Period,
+        at: Option<&str>,
+        weekday: Option<Weekday>,
+        timezone: Option<&str>,
+    ) -> Result<Self, String> {
+        let at = match at.map(|at| NaiveTime::parse_from_str(at, "%H:%M")) {
+            None => NaiveTime::MIN,
+            Some(Ok(at)) => at,
+            Some(Err(e)) => return Err(format!("Invalid digest time, expected HH:MM: {e}")),
+        };
+        let timezone = match timezone {
+            Some(timezone) => timezone.to_string(),
+            None => iana_time_zone::get_timezone().unwrap_or_else(|_| "UTC".to_string()),
+        };
+        let tz = match timezone.parse() {
+            Ok(tz) => tz,
+            Err(e) => return Err(format!("Invalid digest timezone {timezone}: {e}")),
+        };
+        Ok(Self {
+            period,
+            at,
+            weekday: weekday.unwrap_or(Weekday::Mon),
+            tz,
+        })
+    }
+
+    // The first time a digest is due after now
+    fn next(&self, now: DateTime<Utc>) -> DateTime<Utc> {
+        let mut date = now.with_timezone(&self.tz).date_naive();
+        loop {
+            if matches!(self.period, Period::Day) || date.weekday() == self.weekday {
+                let local = date.and_time(self.at);
+                // when the clocks skip over the time go an hour later, when they go over it twice
+                // use the first
+                let due = self.tz.from_local_datetime(&local).earliest().or_else(|| {
+                    self.tz
+                        .from_local_datetime(&(local + TimeDelta::hours(1)))
+                        .earliest()
+                });
+                if let Some(due) = due
+                    && due > now
+                {
+                    return due.with_timezone(&Utc);
+                }
+            }
+            date = date.succ_opt().unwrap_or(date);
+        }
+    }
+
+    fn length(&self) -> TimeDelta {
+        match self.period {
+            Period::Day => TimeDelta::days(1),
+            Period::Week => TimeDelta::weeks(1),
+        }
+    }
+}
+
+// Posts a digest to the bridge channel every time one is due, rescheduling when settings are reloaded
+pub async fn run(
+    outbox: Outbox,
+    db: Arc<dyn Storage>,
+    mut settings: settings::Live,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    loop {
+        let current = settings.borrow_and_update().clone();
+        let Some(schedule) = &current.digest else {
+            // wait for a reload that schedules digests
+            tokio::select! {
+                changed = settings.changed() => {
+                    if changed.is_err() {
+                        return;
+                    }
+                }
+                _ = shutdown.changed() => return,
+            }
+            continue;
+        };
+        let due = schedule.next(Utc::now());
+        info!(%due, "waiting for next digest");
+        tokio::select! {
+            () = sleep((due - Utc::now()).to_std().unwrap_or_default()) => {}
+            changed = settings.changed() => {
+                if changed.is_err() {
+                    return;
+                }
+                continue;
+            }
+            _ = shutdown.changed() => return,
+        }
+
+        let title = match schedule.period {
+            Period::Day => "Daily digest",
+            Period::Week => "Weekly digest",
+        };
+        let world = match db.current_world().await {
+            Ok(world) => world,
+            Err(e) => {
+                error!(error = %e, "Error building digest");
+                metrics::count(Counter::DbErrors);
+                continue;
+            }
+        };
+        match build(
+            db.as_ref(),
+            title,
+            due - schedule.length(),
+            due,
+            world.as_deref(),
+        )
+        .await
+        {
+            Ok(Some(digest)) => outbox.send(current.bridge_channel_id, &digest).await,
+            Ok(None) => info!("nothing happened, skipping digest"),
+            Err(e) => {
+                error!(error = %e, "Error building digest");
+                metrics::count(Counter::DbErrors);
+            }
+        }
+    }
+}
+
+// Summarizes what happened in world from start until end, or None if nothing did
+pub async fn build(
+    db: &dyn Storage,
+    title: &str,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    world: Option<&str>,
+) -> Result<Option<String>, sqlx::Error> {
+    let sessions: Vec<_> = db.export_sessions(Some(start), world).try_collect().await?;
+    let mut played: HashMap<&str, i64> = HashMap::new();
+    for (username, joined, left) in session_spans(&sessions, end) {
+        let seconds = (left.min(end) - joined.max(start)).num_seconds();
+        if seconds > 0 {
+            *played.entry(username).or_default() += seconds;
+        }
+    }
+    let mut played: Vec<(&str, i64)> = played.into_iter().collect();
+    played.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+
+    let mut period_deaths: Vec<Death> = vec![];
+    let mut rows = db.export_deaths(Some(start), world);
+    while let Some(death) = rows.try_next().await? {
+        if death.create_date >= end {
+            break;
+        }
+        period_deaths.push(death);
+    }
+    // hands its connection back before querying again
+    drop(rows);
+
+    let bosses = db.bosses_defeated(start, end, world).await?;
+
+    let mut messages: HashMap<String, i64> = HashMap::new();
+    let mut rows = db.export_messages(Some(start), world);
+    while let Some(message) = rows.try_next().await? {
+        if message.create_date >= end {
+            break;
+        }
+        *messages.entry(message.author).or_default() += 1;
+    }
+    drop(rows);
+    let talkative = messages
+        .into_iter()
+        .max_by(|a, b| a.1.cmp(&b.1).then(b.0.cmp(&a.0)));
+
+    if played.is_empty() && period_deaths.is_empty() && bosses.is_empty() && talkative.is_none() {
+        return Ok(None);
+    }
+
+    let mut digest = format!("**{title}**");
+    if !played.is_empty() {
+        let players: Vec<String> = played
+            .iter()
+            .take(MAX_PLAYERS)
+            .map(|(username, seconds)| {
+                format!(
+                    "{username} ({})",
+                    friendly_duration(i32::try_from(*seconds).unwrap_or(i32::MAX))
+                )
+            })
+            .collect();
+        write!(digest, "\n**Played:** {}", players.join(", ")).ok();
+        if played.len() > MAX_PLAYERS {
+            write!(digest, ", and {} more", played.len() - MAX_PLAYERS).ok();
+        }
+    }
+    if !period_deaths.is_empty() {
+        write!(digest, "\n**Deaths:** {}", period_deaths.len()).ok();
+        let causes = db.death_causes(start, world).await?;
+        if let Some(funniest) = funniest_death(&causes, &period_deaths) {
+            write!(digest, ", funniest: *{funniest}*").ok();
+        }
+    }
+    if !bosses.is_empty() {
+        write!(digest, "\n**Bosses defeated:** {}", bosses.join(", ")).ok();
+    }
+    if let Some((author, count)) = talkative {
+        write!(digest, "\n**Most talkative:** {author} ({count} messages)").ok();
+    }
+    let records = records_broken(
+        &db.survival_records(Some(start), world).await?,
+        &period_deaths,
+    );
+    if !records.is_empty() {
+        write!(digest, "\n**Records broken:** {}", records.join(", ")).ok();
+    }
+
+    Ok(Some(digest))
+}
+
+// The death during the digest that's the rarest way anyone has died, given the causes of deaths
+// before it
+fn funniest_death<'a>(before: &[DeathCause], period_deaths: &'a [Death]) -> Option<&'a str> {
+    let cause = |death: &Death| death.message.replacen(&death.victim, "", 1);
+    let mut causes: HashMap<String, i64> = before
+        .iter()
+        .map(|cause| (cause.cause.clone(), cause.deaths))
+        .collect();
+    for death in period_deaths {
+        *causes.entry(cause(death)).or_default() += 1;
+    }
+    period_deaths
+        .iter()
+        .min_by_key(|death| causes.get(&cause(death)).copied().unwrap_or_default())
+        .map(|death| death.message.as_str())
+}
+
+// Survival records set by deaths during the digest, given the records that stood before it. Deaths
+// must be oldest first
+fn records_broken(before: &[SurvivalRecord], period_deaths: &[Death]) -> Vec<String> {
+    let mut records = vec![];
+    let mut server = before.iter().map(|record| record.longest).max();
+    let mut personal: HashMap<&str, i32> = before
+        .iter()
+        .map(|record| (record.victim.as_str(), record.longest))
+        .collect();
+    for death in period_deaths {
+        let Some(seconds) = death.seconds_since_last else {
+            continue;
+        };
+        let best = personal.get(death.victim.as_str()).copied();
+        if server.is_some_and(|server| seconds > server) {
+            records.push(format!(
+                ":trophy: {} set a server record ({})",
+                death.victim,
+                friendly_duration(seconds)
+            ));
+        } else if best.is_some_and(|best| seconds > best) {
+            records.push(format!(
+                ":medal: {} beat their personal best ({})",
+                death.victim,
+                friendly_duration(seconds)
+            ));
+        }
+        server = server.max(Some(seconds));
+        personal.insert(
+            &death.victim,
+            best.map_or(seconds, |best| best.max(seconds)),
+        );
+    }
+    records.truncate(MAX_RECORDS);
+    records
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::{Period, Schedule, build};
+    use crate::storage::{MemoryStorage, NewDeath, Storage};
+    use chrono::{DateTime, TimeDelta, Utc, Weekday};
+
+    fn utc(date: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(date).unwrap().to_utc()
+    }
+
+    #[test]
+    fn next() {
+        let daily =
+            Schedule::new(Period::Day, Some("09:00"), None, Some("America/Chicago")).unwrap();
+        assert_eq!(
+            utc("2024-03-09T15:00:00Z"),
+            daily.next(utc("2024-03-09T12:00:00Z"))
+        );
+        // clocks went forward overnight
+        assert_eq!(
+            utc("2024-03-10T14:00:00Z"),
+            daily.next(utc("2024-03-09T15:00:00Z"))
+        );
+
+        let weekly = Schedule::new(
+            Period::Week,
+            Some("18:30"),
+            Some(Weekday::Sun),
+            Some("Europe/London"),
+        )
+        .unwrap();
+        assert_eq!(
+            utc("2024-03-31T17:30:00Z"),
+            weekly.next(utc("2024-03-27T00:00:00Z"))
+        );
+
+        assert!(Schedule::new(Period::Day, Some("9am"), None, None).is_err());
+        assert!(Schedule::new(Period::Day, None, None, Some("Mars/Olympus")).is_err());
+    }
+
+    fn death<'a>(
+        victim: &'a str,
+        message: &'a str,
+        seconds_since_last: Option<i32>,
+    ) -> NewDeath<'a> {
+        NewDeath {
+            victim,
+            killer: None,
+            weapon: None,
+            message,
+            seconds_since_last,
+            is_pk: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn digest() {
+        let db = MemoryStorage::default();
+        // before the digest
+        db.insert_death(&death("alice", "alice was slain by a Zombie.", Some(600)))
+            .await
+            .unwrap();
+        db.insert_death(&death("bob", "bob was slain by a Zombie.", Some(300)))
+            .await
+            .unwrap();
+        db.insert_message("bob", "hi").await.unwrap();
+        // still playing
+        db.insert_join("carol").await.unwrap();
+        db.age(2 * 86400);
+
+        db.insert_join("alice").await.unwrap();
+        db.insert_join("bob").await.unwrap();
+        db.age(3600);
+        db.insert_death(&death("bob", "bob was slain by a Zombie.", Some(400)))
+            .await
+            .unwrap();
+        db.insert_death(&death("alice", "alice fell into the shimmer.", Some(900)))
+            .await
+            .unwrap();
+        db.insert_message("alice", "hello").await.unwrap();
+        db.insert_message("alice", "again").await.unwrap();
+        db.insert_message("bob", "hi").await.unwrap();
+        db.insert_defeat("Skeletron").await.unwrap();
+        db.insert_leave("bob").await.unwrap();
+        db.age(3600);
+
+        let end = Utc::now();
+        let digest = build(&db, "Daily digest", end - TimeDelta::days(1), end, None)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            digest,
+            "**Daily digest**\n\
+             **Played:** carol (24.0 hours), alice (2.0 hours), bob (60 minutes)\n\
+             **Deaths:** 2, funniest: *alice fell into the shimmer.*\n\
+             **Bosses defeated:** Skeletron\n\
+             **Most talkative:** alice (2 messages)\n\
+             **Records broken:** :medal: bob beat their personal best (7 minutes), \
+             :trophy: alice set a server record (15 minutes)"
+        );
+
+        let start = end - TimeDelta::days(30);
+        assert!(
+            build(&db, "Daily digest", start, start + TimeDelta::days(1), None)
+                .await
+                .unwrap()
+                .is_none()
+        );
+    }
+}
@@ -0,0 +1,309 @@
+use crate::metrics::{self, Counter};
+use crate::storage::{NewKillMilestone, Storage};
+use crate::terraria_pcap::{NetText, friendly_duration};
+use chrono::{DateTime, Utc};
+use std::fmt::Write as _;
+use tracing::{error, info};
+
+// Most deaths to list in a fight summary before eliding the rest
+const SUMMARY_DEATHS: usize = 10;
+
+// Invasions are announced as they approach and arrive, and again when they're defeated
+const INVASIONS: [(&str, &[&str], &str); 4] = [
+    (
+        "Goblin Army",
+        &["LegacyMisc.1", "LegacyMisc.2", "LegacyMisc.3"],
+        "LegacyMisc.0",
+    ),
+    (
+        "Pirate Invasion",
+        &["LegacyMisc.25", "LegacyMisc.26", "LegacyMisc.27"],
+        "LegacyMisc.24",
+    ),
+    (
+        "Frost Legion",
+        &["LegacyMisc.5", "LegacyMisc.6", "LegacyMisc.7"],
+        "LegacyMisc.4",
+    ),
+    ("Martian Madness", &["LegacyMisc.41"], "LegacyMisc.42"),
+];
+
+// Events that are only announced as they start
+const EVENTS: [(&str, &str); 5] = [
+    ("Blood Moon", "LegacyMisc.8"),
+    ("Solar Eclipse", "LegacyMisc.20"),
+    ("Pumpkin Moon", "LegacyMisc.31"),
+    ("Frost Moon", "LegacyMisc.34"),
+    ("Lunar Events", "LegacyMisc.43"),
+];
+
+// Bosses that have their own awoken string instead of using Announcement.HasAwoken
+const BOSSES: [(&str, &str); 3] = [
+    ("Moon Lord", "LegacyMisc.47"),
+    ("The Twins", "LegacyMisc.48"),
+    ("Mechdusa", "LegacyMisc.107"),
+];
+
+#[derive(Debug, PartialEq)]
+pub enum Event {
+    BossAwoken(String),
+    BossDefeated(String),
+    // announced for bosses that despawn, as well as for NPCs like the Traveling Merchant leaving
+    Departed(String),
+    PlayerDied,
+    KillMilestone {
+        player: Option<String>,
+        count: i32,
+        npc_key: String,
+        npc_name: String,
+    },
+    InvasionStarted(&'static str),
+    InvasionEnded(&'static str),
+    // world events like blood moons that aren't announced as ending
+    Started(&'static str),
+}
+
+impl Event {
+    // Finds the boss or world event (if any) that a server announcement is about
+    pub fn from_text(text: &NetText) -> Option<Self> {
+        let key = text.key.as_str();
+        match key {
+            "Announcement.HasAwoken" => {
+                return text.subs.first().map(|s| Self::BossAwoken(s.text.clone()));
+            }
+            "Announcement.HasBeenDefeated_Single" | "Announcement.HasBeenDefeated_Plural" => {
+                return text
+                    .subs
+                    .first()
+                    .map(|s| Self::BossDefeated(s.text.clone()));
+            }
+            "Game.EnemiesDefeatedAnnouncement" | "Game.EnemiesDefeatedByAnnouncement" => {
+                // the player doing the defeating is only included when known
+                let (player, subs) = match text.subs.as_slice() {
+                    [player, subs @ ..] if key == "Game.EnemiesDefeatedByAnnouncement" => {
+                        (Some(player.text.clone()), subs)
+                    }
+                    subs => (None, subs),
+                };
+                return match subs {
+                    [count, npc] => Some(Self::KillMilestone {
+                        player,
+                        count: count.text.parse().ok()?,
+                        npc_key: npc.key.clone(),
+                        npc_name: npc.text.clone(),
+                    }),
+                    _ => None,
+                };
+            }
+            "LegacyMisc.35" => {
+                return text.subs.first().map(|s| Self::Departed(s.text.clone()));
+            }
+            _ => {}
+        }
+
+        if let Some((boss, _)) = BOSSES.iter().find(|(_, k)| *k == key) {
+            return Some(Self::BossAwoken((*boss).to_string()));
+        }
+        for (invasion, start_keys, end_key) in &INVASIONS {
+            if start_keys.contains(&key) {
+                return Some(Self::InvasionStarted(invasion));
+            }
+            if *end_key == key {
+                return Some(Self::InvasionEnded(invasion));
+            }
+        }
+        EVENTS
+            .iter()
+            .find(|(_, k)| *k == key)
+            .map(|(event, _)| Self::Started(event))
+    }
+
+    // Records event, returning a summary to announce if it ended a boss fight
+    pub async fn record(&self, db: &dyn Storage) -> Option<String> {
+        info!(event = ?self, "recording event");
+        let result = match self {
+            Self::BossAwoken(boss) => {
+                // a fight that never ended is from before a restart or otherwise missed its end
+                for (id, _) in open_fights(Some(boss), db).await {
+                    if let Err(e) = db.abandon_fight(id).await {
+                        error!(error = %e, "Error abandoning boss fight");
+                        metrics::count(Counter::DbErrors);
+                    }
+                }
+                match db.start_fight(boss).await {
+                    Ok(id) => {
+                        add_participants(id, &online_players(db).await, db).await;
+                        Ok(())
+                    }
+                    Err(e) => Err(e),
+                }
+            }
+            Self::BossDefeated(boss) => match open_fights(Some(boss), db).await.first() {
+                Some((id, _)) => return close_fight(*id, Outcome::Defeated, db).await,
+                // record a fight we didn't see start
+                None => db.insert_defeat(boss).await,
+            },
+            Self::Departed(boss) => match open_fights(Some(boss), db).await.first() {
+                Some((id, _)) => return close_fight(*id, Outcome::Despawned, db).await,
+                None => Ok(()),
+            },
+            Self::PlayerDied => return check_wipe(db).await,
+            Self::KillMilestone {
+                player,
+                count,
+                npc_key,
+                npc_name,
+            } => {
+                db.insert_kill_milestone(&NewKillMilestone {
+                    player: player.as_deref(),
+                    count: *count,
+                    npc_key,
+                    npc_name,
+                })
+                .await
+            }
+            Self::InvasionStarted(name) => db.start_invasion(name).await,
+            Self::InvasionEnded(name) => db.end_invasion(name).await,
+            Self::Started(name) => db.insert_world_event(name).await,
+        };
+        if let Err(e) = result {
+            error!(error = %e, event = ?self, "Error recording event");
+            metrics::count(Counter::DbErrors);
+        }
+        None
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Outcome {
+    Defeated,
+    Despawned,
+    Wiped,
+}
+
+// Fights (id and start) that have started but not ended, optionally only those against boss
+async fn open_fights(boss: Option<&str>, db: &dyn Storage) -> Vec<(i64, DateTime<Utc>)> {
+    match db.open_fights(boss).await {
+        Ok(fights) => fights,
+        Err(e) => {
+            error!(error = %e, "Error getting open boss fights");
+            metrics::count(Counter::DbErrors);
+            vec![]
+        }
+    }
+}
+
+// Players who have joined the server more recently than they've left it
+pub async fn online_players(db: &dyn Storage) -> Vec<String> {
+    match db.online_players().await {
+        Ok(players) => players,
+        Err(e) => {
+            error!(error = %e, "Error getting online players");
+            metrics::count(Counter::DbErrors);
+            vec![]
+        }
+    }
+}
+
+async fn add_participants(fight_id: i64, players: &[String], db: &dyn Storage) {
+    if let Err(e) = db.add_fight_players(fight_id, players).await {
+        error!(error = %e, "Error adding boss fight participants");
+        metrics::count(Counter::DbErrors);
+    }
+}
+
+// When every online player has died within a respawn timer of each other the boss despawns, fail
+// any open fights
+async fn check_wipe(db: &dyn Storage) -> Option<String> {
+    let fights = open_fights(None, db).await;
+    if fights.is_empty() {
+        return None;
+    }
+    let online = online_players(db).await;
+    if online.is_empty() {
+        return None;
+    }
+
+    match db.recently_dead(&online, 10).await {
+        Ok(dead) if usize::try_from(dead).ok() == Some(online.len()) => {}
+        Ok(_) => return None,
+        Err(e) => {
+            error!(error = %e, "Error checking for boss fight wipe");
+            metrics::count(Counter::DbErrors);
+            return None;
+        }
+    }
+
+    let mut summaries = vec![];
+    for (id, _) in fights {
+        if let Some(summary) = close_fight(id, Outcome::Wiped, db).await {
+            summaries.push(summary);
+        }
+    }
+    if summaries.is_empty() {
+        None
+    } else {
+        Some(summaries.join("\n\n"))
+    }
+}
+
+// Ends fight with outcome, returning a summary of the fight
+async fn close_fight(id: i64, outcome: Outcome, db: &dyn Storage) -> Option<String> {
+    let fight = match db.end_fight(id, outcome == Outcome::Defeated).await {
+        Ok(fight) => fight,
+        Err(e) => {
+            error!(error = %e, "Error closing boss fight");
+            metrics::count(Counter::DbErrors);
+            return None;
+        }
+    };
+    // anyone who died during the fight was there for it, even if they joined after it started
+    let deaths = match db
+        .deaths_between(fight.start_date, fight.end_date, fight.world.as_deref())
+        .await
+    {
+        Ok(deaths) => deaths,
+        Err(e) => {
+            error!(error = %e, "Error getting deaths during boss fight");
+            metrics::count(Counter::DbErrors);
+            vec![]
+        }
+    };
+    let victims: Vec<String> = deaths.iter().map(|d| d.victim.clone()).collect();
+    add_participants(id, &victims, db).await;
+
+    let players = match db.fight_players(id).await {
+        Ok(players) => players,
+        Err(e) => {
+            error!(error = %e, "Error getting boss fight participants");
+            metrics::count(Counter::DbErrors);
+            vec![]
+        }
+    };
+
+    let duration = friendly_duration(
+        i32::try_from((fight.end_date - fight.start_date).num_seconds()).unwrap_or(i32::MAX),
+    );
+    let mut summary = match outcome {
+        Outcome::Defeated => format!(
+            "**{}** was defeated in {duration} (attempt {})",
+            fight.boss, fight.attempt
+        ),
+        Outcome::Despawned => format!("**{}** left after {duration}", fight.boss),
+        Outcome::Wiped => format!("**{}** won after {duration}, everyone died", fight.boss),
+    };
+    if !players.is_empty() {
+        write!(summary, "\nPlayers: {}", players.join(", ")).ok();
+    }
+    if !deaths.is_empty() {
+        write!(summary, "\nDeaths ({}):", deaths.len()).ok();
+        for death in deaths.iter().take(SUMMARY_DEATHS) {
+            write!(summary, "\n- {}", death.message).ok();
+        }
+        if deaths.len() > SUMMARY_DEATHS {
+            write!(summary, "\n- ...and {} more", deaths.len() - SUMMARY_DEATHS).ok();
+        }
+    }
+
+    Some(summary)
+}
@@ -0,0 +1,229 @@
+use crate::commands::parse_since;
+use crate::storage::Storage;
+use chrono::{DateTime, Utc};
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use futures::TryStreamExt;
+use futures::stream::BoxStream;
+use poise::ChoiceParameter;
+use serde::Serialize;
+use std::io::Write as _;
+
+// Exports larger than this are gzipped
+const GZIP_BYTES: usize = 1024 * 1024;
+// Discord's attachment limit for bots in servers without boosts
+pub const ATTACHMENT_BYTES: usize = 8 * 1024 * 1024;
+// Stop reading rows for a capped export past this, it won't compress enough to fit
+const UNCOMPRESSED_CAP_MULTIPLE: usize = 8;
+const TOO_LARGE: &str = "Too much to export, try a more recent since";
+
+#[derive(Clone, Copy, ChoiceParameter)]
+pub enum Table {
+    #[name = "deaths"]
+    Deaths,
+    #[name = "messages"]
+    Messages,
+    #[name = "sessions"]
+    Sessions,
+}
+
+#[derive(Clone, Copy, Default, ChoiceParameter)]
+pub enum Format {
+    #[default]
+    #[name = "csv"]
+    Csv,
+    #[name = "json"]
+    Json,
+}
+
+pub struct Export {
+    pub filename: String,
+    pub data: Vec<u8>,
+    pub rows: usize,
+}
+
+// Writes rows of table to a file, gzipping it if it's large. When max_bytes is set, errors if the
+// file is larger than that even after gzipping
+pub async fn export(
+    db: &dyn Storage,
+    table: Table,
+    format: Format,
+    since: Option<DateTime<Utc>>,
+    world: Option<&str>,
+    max_bytes: Option<usize>,
+) -> Result<Export, String> {
+    let cap = max_bytes.map(|max| max * UNCOMPRESSED_CAP_MULTIPLE);
+    let (data, rows) = match table {
+        Table::Deaths => write_rows(db.export_deaths(since, world), format, cap).await,
+        Table::Messages => write_rows(db.export_messages(since, world), format, cap).await,
+        Table::Sessions => write_rows(db.export_sessions(since, world), format, cap).await,
+    }?;
+
+    let filename = format!("{}.{}", table.name(), format.name());
+    if data.len() <= GZIP_BYTES {
+        return Ok(Export {
+            filename,
+            data,
+            rows,
+        });
+    }
+
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    let data = match encoder.write_all(&data).and_then(|()| encoder.finish()) {
+        Ok(data) => data,
+        Err(e) => return Err(format!("Unable to compress export: {e}")),
+    };
+    if max_bytes.is_some_and(|max| data.len() > max) {
+        return Err(TOO_LARGE.to_string());
+    }
+    Ok(Export {
+        filename: format!("{filename}.gz"),
+        data,
+        rows,
+    })
+}
+
+// Serializes each row as it's read, returning the file and how many rows are in it
+async fn write_rows<T: Serialize>(
+    mut rows: BoxStream<'_, sqlx::Result<T>>,
+    format: Format,
+    cap: Option<usize>,
+) -> Result<(Vec<u8>, usize), String> {
+    let mut count = 0;
+    let data = match format {
+        Format::Csv => {
+            let mut writer = csv::Writer::from_writer(vec![]);
+            while let Some(row) = rows
+                .try_next()
+                .await
+                .map_err(|e| format!("Unable to read rows to export: {e}"))?
+            {
+                if let Err(e) = writer.serialize(row) {
+                    return Err(format!("Unable to write csv row: {e}"));
+                }
+                count += 1;
+                if cap.is_some_and(|cap| writer.get_ref().len() > cap) {
+                    return Err(TOO_LARGE.to_string());
+                }
+            }
+            match writer.into_inner() {
+                Ok(data) => data,
+                Err(e) => return Err(format!("Unable to write csv: {e}")),
+            }
+        }
+        Format::Json => {
+            let mut data = vec![b'['];
+            while let Some(row) = rows
+                .try_next()
+                .await
+                .map_err(|e| format!("Unable to read rows to export: {e}"))?
+            {
+                if count > 0 {
+                    data.push(b',');
+                }
+                if let Err(e) = serde_json::to_writer(&mut data, &row) {
+                    return Err(format!("Unable to write json row: {e}"));
+                }
+                count += 1;
+                if cap.is_some_and(|cap| data.len() > cap) {
+                    return Err(TOO_LARGE.to_string());
+                }
+            }
+            data.push(b']');
+            data
+        }
+    };
+    Ok((data, count))
+}
+
+// `terraria-discord export <deaths|messages|sessions> [csv|json] [since]` writes an export of every
+// world to the current directory, returning its filename
+pub async fn cli(db: &dyn Storage, args: &[String]) -> Result<String, String> {
+    let usage = "usage: terraria-discord export <deaths|messages|sessions> [csv|json] [since]";
+    let mut args = args.iter().map(String::as_str).peekable();
+    let Some(table) = args.next().and_then(Table::from_name) else {
+        return Err(usage.to_string());
+    };
+    let format = match args.peek().and_then(|arg| Format::from_name(arg)) {
+        Some(format) => {
+            args.next();
+            format
+        }
+        None => Format::default(),
+    };
+    let since = match args.next().map(parse_since).transpose() {
+        Ok(since) => since.map(|since| since.with_timezone(&Utc)),
+        Err(e) => return Err(e),
+    };
+    if args.next().is_some() {
+        return Err(usage.to_string());
+    }
+
+    let export = export(db, table, format, since, None, None).await?;
+    if let Err(e) = std::fs::write(&export.filename, &export.data) {
+        return Err(format!("Unable to write {}: {e}", export.filename));
+    }
+    Ok(export.filename)
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::{Format, Table, export};
+    use crate::storage::{MemoryStorage, Storage};
+
+    #[tokio::test]
+    async fn sessions() {
+        let db = MemoryStorage::default();
+        db.insert_join("alice").await.unwrap();
+        db.insert_join("bob").await.unwrap();
+        db.insert_leave("alice").await.unwrap();
+
+        let csv = export(&db, Table::Sessions, Format::Csv, None, None, None)
+            .await
+            .unwrap();
+        assert_eq!(csv.filename, "sessions.csv");
+        assert_eq!(csv.rows, 2);
+        let csv = String::from_utf8(csv.data).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines[0], "username,joined,left");
+        assert!(lines[1].starts_with("alice,") && !lines[1].ends_with(','));
+        assert!(lines[2].starts_with("bob,") && lines[2].ends_with(','));
+
+        let json = export(&db, Table::Sessions, Format::Json, None, None, None)
+            .await
+            .unwrap();
+        let sessions: Vec<serde_json::Value> = serde_json::from_slice(&json.data).unwrap();
+        assert_eq!(sessions.len(), 2);
+        assert_eq!(sessions[1]["username"], "bob");
+        assert!(sessions[1]["left"].is_null());
+    }
+
+    #[tokio::test]
+    async fn large() {
+        let db = MemoryStorage::default();
+        let content = "a".repeat(1000);
+        for _ in 0..2000 {
+            db.insert_message("alice", &content).await.unwrap();
+        }
+
+        let gzipped = export(
+            &db,
+            Table::Messages,
+            Format::Json,
+            None,
+            None,
+            Some(300_000),
+        )
+        .await
+        .unwrap();
+        assert_eq!(gzipped.filename, "messages.json.gz");
+        assert!(gzipped.data.len() < 300_000);
+
+        assert!(
+            export(&db, Table::Messages, Format::Csv, None, None, Some(1000))
+                .await
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,271 @@
+use crate::log_patterns::{LogEvent, LogPatterns};
+use crate::storage::{ImportedEvent, ImportedLine, Storage};
+use chrono::{DateTime, Local, NaiveDateTime, TimeDelta, Utc};
+
+// How much of the start of a log it's looked up by, so a log that's grown or been renamed since it
+// was imported is found. Hashes of its lines tell it apart from other logs that start the same
+const FINGERPRINT_LEN: usize = 4096;
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+// Formats anchor times are accepted in, besides RFC 3339, in the local timezone
+const ANCHOR_FORMATS: [&str; 4] = [
+    "%Y-%m-%dT%H:%M:%S",
+    "%Y-%m-%d %H:%M:%S",
+    "%Y-%m-%dT%H:%M",
+    "%Y-%m-%d %H:%M",
+];
+
+// `terraria-discord import-log <file> [line=time]...` records the chat, joins, and leaves in an old
+// server log without sending them to discord, returning how many were inserted. Server logs don't
+// have timestamps, so lines are given times from anchors of what time a line was written,
+// defaulting to the last line being written when the file was last modified
+pub async fn cli(db: &dyn Storage, patterns: &LogPatterns, args: &[String]) -> Result<u64, String> {
+    let usage = "usage: terraria-discord import-log <file> [line=time]...";
+    let Some(filename) = args.first() else {
+        return Err(usage.to_string());
+    };
+    let data = match std::fs::read(filename) {
+        Ok(data) => data,
+        Err(e) => return Err(format!("Unable to read {filename}: {e}")),
+    };
+    let text = String::from_utf8_lossy(&data);
+    let lines: Vec<&str> = text.lines().map(str::trim).collect();
+    if lines.is_empty() {
+        return Ok(0);
+    }
+    // its fingerprint would change as it grows, so importing it again would duplicate everything
+    if data.len() < FINGERPRINT_LEN {
+        return Err(format!(
+            "{filename} is too short to recognize if it's imported again, it needs at least {FINGERPRINT_LEN} bytes"
+        ));
+    }
+
+    let mut anchors = vec![];
+    for arg in &args[1..] {
+        anchors.push(parse_anchor(arg, lines.len())?);
+    }
+    if anchors.is_empty() {
+        let modified = match std::fs::metadata(filename).and_then(|metadata| metadata.modified()) {
+            Ok(modified) => DateTime::<Utc>::from(modified),
+            Err(e) => {
+                return Err(format!(
+                    "Unable to read modification time of {filename}: {e}"
+                ));
+            }
+        };
+        anchors.push((lines.len() - 1, modified));
+    }
+    let times = line_times(lines.len(), anchors)?;
+
+    let imported: Vec<ImportedLine> = lines
+        .iter()
+        .zip(times)
+        .enumerate()
+        .filter_map(|(i, (line, create_date))| {
+            let event = match patterns.parse(line)? {
+                LogEvent::Chat { user: "Server", .. } => return None,
+                LogEvent::Chat { user, message } => ImportedEvent::Message {
+                    author: user,
+                    content: message,
+                },
+                LogEvent::Join(user) => ImportedEvent::Join(user),
+                LogEvent::Leave(user) => ImportedEvent::Leave(user),
+                _ => return None,
+            };
+            Some(ImportedLine {
+                line: i64::try_from(i).unwrap_or(i64::MAX),
+                create_date,
+                event,
+            })
+        })
+        .collect();
+
+    db.import_log(&fingerprint(&data), &line_hashes(&lines), &imported)
+        .await
+        .map_err(|e| format!("Unable to import {filename}: {e}"))
+}
+
+// Parses "line=time", with lines counted from 1, into a line index and time
+fn parse_anchor(arg: &str, lines: usize) -> Result<(usize, DateTime<Utc>), String> {
+    let invalid =
+        || format!("Invalid anchor `{arg}`, expected line=time like 120=2024-01-31T18:00");
+    let (line, time) = arg.split_once('=').ok_or_else(invalid)?;
+    let line: usize = line.trim().parse().map_err(|_| invalid())?;
+    if line == 0 || line > lines {
+        return Err(format!(
+            "Anchor line {line} isn't in the log, it has {lines} lines"
+        ));
+    }
+    let time = time.trim();
+    let time = match DateTime::parse_from_rfc3339(time) {
+        Ok(time) => time.to_utc(),
+        Err(_) => ANCHOR_FORMATS
+            .iter()
+            .find_map(|format| NaiveDateTime::parse_from_str(time, format).ok())
+            .and_then(|time| time.and_local_timezone(Local).earliest())
+            .ok_or_else(invalid)?
+            .to_utc(),
+    };
+    Ok((line - 1, time))
+}
+
+// The time of each line, spread evenly between the anchors around it and a second apart before the
+// first anchor and after the last
+fn line_times(
+    lines: usize,
+    mut anchors: Vec<(usize, DateTime<Utc>)>,
+) -> Result<Vec<DateTime<Utc>>, String> {
+    if anchors.is_empty() {
+        return Err("No anchors to give lines times from".to_string());
+    }
+    anchors.sort_by_key(|(line, _)| *line);
+    anchors.dedup_by_key(|(line, _)| *line);
+    if anchors.windows(2).any(|pair| pair[1].1 < pair[0].1) {
+        return Err("Anchors must be in the same order as their lines".to_string());
+    }
+    let seconds = |lines: usize| TimeDelta::seconds(i64::try_from(lines).unwrap_or(i64::MAX));
+
+    let mut times = Vec::with_capacity(lines);
+    let mut next = 0;
+    for line in 0..lines {
+        while next < anchors.len() && anchors[next].0 < line {
+            next += 1;
+        }
+        let time = match (next.checked_sub(1).map(|i| anchors[i]), anchors.get(next)) {
+            (_, Some(&(after, time))) if after == line => time,
+            (Some((before, start)), Some(&(after, end))) => {
+                #[allow(clippy::cast_possible_truncation, clippy::cast_precision_loss)]
+                let offset = (end - start).num_milliseconds() as f64 * (line - before) as f64
+                    / (after - before) as f64;
+                #[allow(clippy::cast_possible_truncation)]
+                let offset = TimeDelta::milliseconds(offset as i64);
+                start + offset
+            }
+            (None, Some(&(after, time))) => time - seconds(after - line),
+            (Some((before, time)), None) => time + seconds(line - before),
+            (None, None) => return Err("No anchors to give lines times from".to_string()),
+        };
+        times.push(time);
+    }
+    Ok(times)
+}
+
+// FNV-1a of the start of the log
+fn fingerprint(data: &[u8]) -> String {
+    let hash = fnv(FNV_OFFSET, &data[..data.len().min(FINGERPRINT_LEN)]);
+    format!("{hash:016x}")
+}
+
+// FNV-1a of the log's first 1, 2, 3... lines, telling whether it continues a log imported before or
+// only starts the same
+fn line_hashes(lines: &[&str]) -> Vec<String> {
+    let mut hash = FNV_OFFSET;
+    lines
+        .iter()
+        .map(|line| {
+            hash = fnv(fnv(hash, line.as_bytes()), b"\n");
+            format!("{hash:016x}")
+        })
+        .collect()
+}
+
+fn fnv(hash: u64, data: &[u8]) -> u64 {
+    data.iter().fold(hash, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::{cli, line_times};
+    use crate::log_patterns::{LogPatterns, PatternsConfig};
+    use crate::storage::{MemoryStorage, Storage};
+    use chrono::{DateTime, TimeDelta, Utc};
+    use futures::TryStreamExt;
+
+    fn utc(date: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(date).unwrap().to_utc()
+    }
+
+    #[test]
+    fn times() {
+        let start = utc("2024-01-01T00:00:00Z");
+        let times = line_times(6, vec![(4, start + TimeDelta::minutes(10)), (1, start)]).unwrap();
+        assert_eq!(
+            times,
+            [
+                start - TimeDelta::seconds(1),
+                start,
+                start + TimeDelta::seconds(200),
+                start + TimeDelta::seconds(400),
+                start + TimeDelta::minutes(10),
+                start + TimeDelta::seconds(601),
+            ]
+        );
+        assert!(line_times(3, vec![(0, start), (2, start - TimeDelta::hours(1))]).is_err());
+    }
+
+    #[tokio::test]
+    async fn import() {
+        let dir = std::env::temp_dir().join(format!("import_log_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let log = dir.join("server_log.txt");
+        // long enough that appending to it doesn't change its fingerprint
+        let mut text = "Settling liquids: 50%\n".repeat(200);
+        text.push_str(": alice has joined.\n<alice> hi\n<Server> hello\nalice has left.\n");
+        std::fs::write(&log, &text).unwrap();
+        let db = MemoryStorage::default();
+        let patterns = LogPatterns::new(&PatternsConfig::default()).unwrap();
+        let args = |anchors: &[&str]| {
+            let mut args = vec![log.to_string_lossy().to_string()];
+            args.extend(anchors.iter().map(ToString::to_string));
+            args
+        };
+
+        let anchors = ["201=2024-01-01T12:00:00Z", "204=2024-01-01T14:00:00Z"];
+        assert_eq!(cli(&db, &patterns, &args(&anchors)).await.unwrap(), 3);
+        // the same lines aren't imported again
+        assert_eq!(cli(&db, &patterns, &args(&anchors)).await.unwrap(), 0);
+        let sessions: Vec<_> = db.export_sessions(None, None).try_collect().await.unwrap();
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0].joined, utc("2024-01-01T12:00:00Z"));
+        assert_eq!(sessions[0].left, Some(utc("2024-01-01T14:00:00Z")));
+        let messages: Vec<_> = db.export_messages(None, None).try_collect().await.unwrap();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].create_date, utc("2024-01-01T12:40:00Z"));
+
+        // only what's been written since is imported, with the modification time as the anchor
+        text.push_str("bob has joined.\n");
+        std::fs::write(&log, text).unwrap();
+        assert_eq!(cli(&db, &patterns, &args(&[])).await.unwrap(), 1);
+        assert_eq!(db.online_players().await.unwrap(), ["bob"]);
+
+        assert!(
+            cli(&db, &patterns, &args(&["900=2024-01-01T12:00:00Z"]))
+                .await
+                .is_err()
+        );
+        assert!(cli(&db, &patterns, &args(&["2=noon"])).await.is_err());
+
+        // a different log that starts the same is imported in full
+        let other = dir.join("other_log.txt");
+        let mut other_text = "Settling liquids: 50%\n".repeat(200);
+        other_text.push_str("dave has joined.\n");
+        std::fs::write(&other, other_text).unwrap();
+        let other_args = vec![other.to_string_lossy().to_string()];
+        assert_eq!(cli(&db, &patterns, &other_args).await.unwrap(), 1);
+        assert_eq!(cli(&db, &patterns, &other_args).await.unwrap(), 0);
+        assert_eq!(db.online_players().await.unwrap(), ["bob", "dave"]);
+
+        // a short log can't be recognized once it's grown, so it's refused rather than imported twice
+        let short = dir.join("short_log.txt");
+        let short_args = vec![short.to_string_lossy().to_string()];
+        std::fs::write(&short, "carol has joined.\n").unwrap();
+        assert!(cli(&db, &patterns, &short_args).await.is_err());
+        std::fs::write(&short, "carol has joined.\ncarol has left.\n").unwrap();
+        assert!(cli(&db, &patterns, &short_args).await.is_err());
+        assert_eq!(db.online_players().await.unwrap(), ["bob", "dave"]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,283 @@
+use std::io::{ErrorKind, SeekFrom};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use tokio::fs::{self, File};
+use tokio::io::{AsyncBufReadExt, AsyncSeekExt, BufReader};
+use tokio::time::{Duration, sleep};
+use tracing::{error, info, warn};
+
+// How long to wait for the log to grow, or to be created
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+// Follows a log file by name like `tail -F`, reopening it when it's rotated or recreated and
+// starting over when it's truncated. How far it's read is saved to offset_file, so lines written
+// while the bot was down are read when it starts again
+pub struct LogFollower {
+    path: PathBuf,
+    offset_file: Option<PathBuf>,
+    reader: Option<BufReader<File>>,
+    // device and inode of the open file, to tell when path is a different file
+    id: (u64, u64),
+    // bytes read from the open file, and how many of those were complete lines
+    read: u64,
+    offset: u64,
+    // bytes read after the last complete line
+    partial: Vec<u8>,
+    // where to start reading the next time the file is opened
+    start: Start,
+    saved: Option<((u64, u64), u64)>,
+    missing_warned: bool,
+}
+
+enum Start {
+    // the file was already being followed, everything in it is new
+    Beginning,
+    // nothing was saved, so skip what's already there like `tail -n 0`
+    End,
+    // the saved id and offset, used if the file is the same one
+    Saved((u64, u64), u64),
+}
+
+impl LogFollower {
+    pub async fn new(path: PathBuf, offset_file: Option<PathBuf>) -> Self {
+        let saved = match &offset_file {
+            Some(offset_file) => load_offset(offset_file).await,
+            None => None,
+        };
+        Self {
+            path,
+            offset_file,
+            reader: None,
+            id: (0, 0),
+            read: 0,
+            offset: 0,
+            partial: vec![],
+            start: saved.map_or(Start::End, |(id, offset)| Start::Saved(id, offset)),
+            saved,
+            missing_warned: false,
+        }
+    }
+
+    // Waits for the next complete line, without its line ending
+    pub async fn next_line(&mut self) -> String {
+        loop {
+            let Some(reader) = &mut self.reader else {
+                if !self.open().await {
+                    sleep(POLL_INTERVAL).await;
+                }
+                continue;
+            };
+            match reader.read_until(b'\n', &mut self.partial).await {
+                Ok(0) => {
+                    if !self.reopen_if_changed().await {
+                        sleep(POLL_INTERVAL).await;
+                    }
+                }
+                Ok(read) => {
+                    self.read += read as u64;
+                    if self.partial.ends_with(b"\n") {
+                        self.offset = self.read;
+                        let line = String::from_utf8_lossy(&self.partial)
+                            .trim_end_matches(['\r', '\n'])
+                            .to_string();
+                        self.partial.clear();
+                        return line;
+                    }
+                }
+                Err(e) => {
+                    error!(error = %e, path = %self.path.display(), "Error reading log");
+                    // pick up where it left off once it's reopened
+                    self.reader = None;
+                    self.start = Start::Saved(self.id, self.offset);
+                    sleep(POLL_INTERVAL).await;
+                }
+            }
+        }
+    }
+
+    // Saves that every line returned so far has been handled
+    pub async fn commit(&mut self) {
+        let Some(offset_file) = &self.offset_file else {
+            return;
+        };
+        if self.saved == Some((self.id, self.offset)) {
+            return;
+        }
+        // written then renamed so a crash can't leave half an offset behind
+        let tmp = offset_file.with_extension("tmp");
+        let contents = format!("{} {} {}\n", self.id.0, self.id.1, self.offset);
+        if let Err(e) = async {
+            fs::write(&tmp, contents).await?;
+            fs::rename(&tmp, offset_file).await
+        }
+        .await
+        {
+            error!(error = %e, path = %offset_file.display(), "Unable to save log offset");
+            return;
+        }
+        self.saved = Some((self.id, self.offset));
+    }
+
+    // Opens the file at path, returning false if it can't be
+    async fn open(&mut self) -> bool {
+        let mut file = match File::open(&self.path).await {
+            Ok(file) => file,
+            Err(e) => {
+                if e.kind() == ErrorKind::NotFound {
+                    if !self.missing_warned {
+                        warn!(path = %self.path.display(), "log doesn't exist yet, waiting for it");
+                        self.missing_warned = true;
+                    }
+                    // anything written to it once it's created is new
+                    self.start = Start::Beginning;
+                } else {
+                    error!(error = %e, path = %self.path.display(), "Unable to open log");
+                }
+                return false;
+            }
+        };
+        let metadata = match file.metadata().await {
+            Ok(metadata) => metadata,
+            Err(e) => {
+                error!(error = %e, path = %self.path.display(), "Unable to read log metadata");
+                return false;
+            }
+        };
+        let id = (metadata.dev(), metadata.ino());
+        let start = match self.start {
+            Start::End => metadata.len(),
+            Start::Saved(saved_id, offset) if saved_id == id && offset <= metadata.len() => offset,
+            // a saved offset is from a file that was rotated or truncated while we were down
+            Start::Beginning | Start::Saved(..) => 0,
+        };
+        if let Err(e) = file.seek(SeekFrom::Start(start)).await {
+            error!(error = %e, path = %self.path.display(), "Unable to seek in log");
+            return false;
+        }
+
+        info!(path = %self.path.display(), offset = start, "following log");
+        self.reader = Some(BufReader::new(file));
+        self.id = id;
+        self.read = start;
+        self.offset = start;
+        self.partial.clear();
+        self.start = Start::Beginning;
+        self.missing_warned = false;
+        true
+    }
+
+    // At the end of the open file, checks if path has been rotated to a new file or truncated and
+    // starts reading from the beginning if so, returning whether it did
+    async fn reopen_if_changed(&mut self) -> bool {
+        // when it's been removed keep the old file until a new one shows up
+        let Ok(metadata) = fs::metadata(&self.path).await else {
+            return false;
+        };
+        if (metadata.dev(), metadata.ino()) != self.id {
+            info!(path = %self.path.display(), "log rotated");
+            self.reader = None;
+            return true;
+        }
+        if metadata.len() < self.read
+            && let Some(reader) = &mut self.reader
+        {
+            info!(path = %self.path.display(), "log truncated");
+            if let Err(e) = reader.seek(SeekFrom::Start(0)).await {
+                error!(error = %e, path = %self.path.display(), "Unable to seek in log");
+                self.reader = None;
+            }
+            self.read = 0;
+            self.offset = 0;
+            self.partial.clear();
+            return true;
+        }
+        false
+    }
+}
+
+// Reads the device, inode, and offset saved by commit
+async fn load_offset(offset_file: &Path) -> Option<((u64, u64), u64)> {
+    let contents = match fs::read_to_string(offset_file).await {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == ErrorKind::NotFound => return None,
+        Err(e) => {
+            error!(error = %e, path = %offset_file.display(), "Unable to read log offset");
+            return None;
+        }
+    };
+    let fields: Vec<u64> = contents
+        .split_whitespace()
+        .filter_map(|field| field.parse().ok())
+        .collect();
+    match fields[..] {
+        [dev, ino, offset] => Some(((dev, ino), offset)),
+        _ => {
+            warn!(path = %offset_file.display(), "ignoring invalid log offset");
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::LogFollower;
+    use std::fs::{self, OpenOptions};
+    use std::io::Write as _;
+    use std::path::Path;
+    use tokio::time::{Duration, timeout};
+
+    fn append(path: &Path, text: &str) {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .unwrap();
+        file.write_all(text.as_bytes()).unwrap();
+    }
+
+    async fn next(follower: &mut LogFollower) -> String {
+        let line = timeout(Duration::from_secs(5), follower.next_line())
+            .await
+            .unwrap();
+        follower.commit().await;
+        line
+    }
+
+    #[tokio::test]
+    async fn follow() {
+        let dir = std::env::temp_dir().join(format!("log_follower_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let log = dir.join("server_log.txt");
+        let offset = dir.join("log.offset");
+        append(&log, "before\n");
+
+        // starts at the end without a saved offset
+        let mut follower = LogFollower::new(log.clone(), Some(offset.clone())).await;
+        let reading = tokio::spawn(async move {
+            let first = next(&mut follower).await;
+            (follower, first)
+        });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        append(&log, "first\npart");
+        let (mut follower, first) = reading.await.unwrap();
+        assert_eq!(first, "first");
+        append(&log, "ial\r\n");
+        assert_eq!(next(&mut follower).await, "partial");
+
+        fs::write(&log, "truncated\n").unwrap();
+        assert_eq!(next(&mut follower).await, "truncated");
+
+        fs::rename(&log, dir.join("server_log.txt.1")).unwrap();
+        append(&log, "rotated\n");
+        assert_eq!(next(&mut follower).await, "rotated");
+        drop(follower);
+
+        // lines written while it wasn't running are picked up where it left off
+        append(&log, "while down\n");
+        let mut follower = LogFollower::new(log.clone(), Some(offset.clone())).await;
+        assert_eq!(next(&mut follower).await, "while down");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
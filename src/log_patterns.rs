@@ -0,0 +1,448 @@
+use crate::strings;
+use regex::{Captures, Regex};
+use serde::Deserialize;
+use std::collections::HashMap;
+
+#[derive(Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Preset {
+    #[default]
+    Vanilla,
+    Tshock,
+    Tmodloader,
+}
+
+// [log_patterns] in config.toml, a preset with any of its rules replaced
+#[derive(Clone, Default, PartialEq, Deserialize)]
+pub struct PatternsConfig {
+    #[serde(default)]
+    preset: Preset,
+    chat: Option<RuleConfig>,
+    join_leave: Option<RuleConfig>,
+    playing: Option<RuleConfig>,
+    connected: Option<RuleConfig>,
+    version: Option<RuleConfig>,
+    loading: Option<RuleConfig>,
+    started: Option<RuleConfig>,
+    saving: Option<RuleConfig>,
+    backing_up: Option<RuleConfig>,
+    stopped: Option<RuleConfig>,
+}
+
+#[derive(Clone, PartialEq, Deserialize)]
+pub struct RuleConfig {
+    regex: String,
+    // the capture group, by name or number, holding each of the event's fields
+    #[serde(flatten)]
+    captures: HashMap<String, String>,
+}
+
+// A server log line the bridge cares about
+#[derive(Debug, PartialEq)]
+pub enum LogEvent<'a> {
+    Chat { user: &'a str, message: &'a str },
+    Join(&'a str),
+    Leave(&'a str),
+    // responses to the commands sent to the server console
+    Playing(&'a str),
+    Connected(&'a str),
+    Version(&'a str),
+    Lifecycle(Lifecycle),
+}
+
+impl LogEvent<'_> {
+    // The [log_patterns] rule that matches this event
+    pub fn rule(&self) -> &'static str {
+        match self {
+            Self::Chat { .. } => "chat",
+            Self::Join(_) | Self::Leave(_) => "join_leave",
+            Self::Playing(_) => "playing",
+            Self::Connected(_) => "connected",
+            Self::Version(_) => "version",
+            Self::Lifecycle(Lifecycle::Loading) => "loading",
+            Self::Lifecycle(Lifecycle::Started) => "started",
+            Self::Lifecycle(Lifecycle::Saving) => "saving",
+            Self::Lifecycle(Lifecycle::BackingUp) => "backing_up",
+            Self::Lifecycle(Lifecycle::Stopped) => "stopped",
+        }
+    }
+}
+
+// What the server is doing, from its console output
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Lifecycle {
+    Loading,
+    Started,
+    Saving,
+    BackingUp,
+    Stopped,
+}
+
+// A regex and which of its groups holds each field, in the order of the event's fields
+struct Rule {
+    regex: Regex,
+    groups: Vec<Group>,
+}
+
+enum Group {
+    Index(usize),
+    Name(String),
+}
+
+impl Rule {
+    fn new(event: &str, fields: &[&str], config: &RuleConfig) -> Result<Self, String> {
+        let regex = match Regex::new(&config.regex) {
+            Ok(regex) => regex,
+            Err(e) => return Err(format!("Invalid {event} log pattern: {e}")),
+        };
+        if let Some(unknown) = config
+            .captures
+            .keys()
+            .find(|field| !fields.contains(&field.as_str()))
+        {
+            return Err(format!(
+                "Unknown {event} log pattern field {unknown}, expected one of {}",
+                fields.join(", ")
+            ));
+        }
+
+        let mut groups = vec![];
+        for field in fields {
+            let group = config.captures.get(*field).map_or(*field, String::as_str);
+            let group = match group.parse() {
+                Ok(index) if index < regex.captures_len() => Group::Index(index),
+                Err(_) if regex.capture_names().flatten().any(|name| name == group) => {
+                    Group::Name(group.to_string())
+                }
+                _ => {
+                    return Err(format!(
+                        "{event} log pattern has no group {group} for its {field}"
+                    ));
+                }
+            };
+            groups.push(group);
+        }
+        Ok(Self { regex, groups })
+    }
+
+    // The text of each field if line matches
+    fn captures<'a>(&self, line: &'a str) -> Option<Vec<&'a str>> {
+        let caps: Captures<'a> = self.regex.captures(line)?;
+        self.groups
+            .iter()
+            .map(|group| match group {
+                Group::Index(index) => caps.get(*index),
+                Group::Name(name) => caps.name(name),
+            })
+            .map(|field| field.map(|field| field.as_str()))
+            .collect()
+    }
+}
+
+pub struct LogPatterns {
+    chat: Rule,
+    join_leave: Rule,
+    playing: Rule,
+    connected: Rule,
+    version: Rule,
+    lifecycle: Vec<(Lifecycle, Rule)>,
+}
+
+impl LogPatterns {
+    pub fn new(config: &PatternsConfig) -> Result<Self, String> {
+        let preset = preset(config.preset);
+        let rule = |event: &str, fields: &[&str], custom: Option<&RuleConfig>| {
+            let preset = RuleConfig {
+                regex: preset[event].clone(),
+                captures: HashMap::new(),
+            };
+            Rule::new(event, fields, custom.unwrap_or(&preset))
+        };
+        Ok(Self {
+            chat: rule("chat", &["user", "message"], config.chat.as_ref())?,
+            join_leave: rule(
+                "join_leave",
+                &["user", "status"],
+                config.join_leave.as_ref(),
+            )?,
+            playing: rule("playing", &["user"], config.playing.as_ref())?,
+            connected: rule("connected", &["players"], config.connected.as_ref())?,
+            version: rule("version", &["version"], config.version.as_ref())?,
+            lifecycle: vec![
+                (
+                    Lifecycle::Loading,
+                    rule("loading", &[], config.loading.as_ref())?,
+                ),
+                (
+                    Lifecycle::Started,
+                    rule("started", &[], config.started.as_ref())?,
+                ),
+                (
+                    Lifecycle::Saving,
+                    rule("saving", &[], config.saving.as_ref())?,
+                ),
+                (
+                    Lifecycle::BackingUp,
+                    rule("backing_up", &[], config.backing_up.as_ref())?,
+                ),
+                (
+                    Lifecycle::Stopped,
+                    rule("stopped", &[], config.stopped.as_ref())?,
+                ),
+            ],
+        })
+    }
+
+    // The first event line matches, if any. Server status and command responses that can't look
+    // like chat are checked first, since a looser chat pattern like TShock's would match them too
+    pub fn parse<'a>(&self, line: &'a str) -> Option<LogEvent<'a>> {
+        if let Some((lifecycle, _)) = self
+            .lifecycle
+            .iter()
+            .find(|(_, rule)| rule.regex.is_match(line))
+        {
+            return Some(LogEvent::Lifecycle(*lifecycle));
+        }
+        if let Some(fields) = self.version.captures(line) {
+            return Some(LogEvent::Version(fields[0]));
+        }
+        if let Some(fields) = self.connected.captures(line) {
+            return Some(LogEvent::Connected(fields[0]));
+        }
+        if let Some(fields) = self.chat.captures(line) {
+            return Some(LogEvent::Chat {
+                user: fields[0],
+                message: fields[1],
+            });
+        }
+        if let Some(fields) = self.join_leave.captures(line) {
+            // status has to be one of these for the line to be a join or leave
+            match fields[1] {
+                "joined" => return Some(LogEvent::Join(fields[0])),
+                "left" => return Some(LogEvent::Leave(fields[0])),
+                _ => {}
+            }
+        }
+        self.playing
+            .captures(line)
+            .map(|fields| LogEvent::Playing(fields[0]))
+    }
+}
+
+// Each event's regex in a preset, capturing its fields in groups named after them
+fn preset(preset: Preset) -> HashMap<&'static str, String> {
+    let world_gen = &strings::get()["LegacyWorldGen"];
+    let mut rules: HashMap<&str, String> = HashMap::from([
+        (
+            "chat",
+            r"^(?:: )*<(?P<user>.+?)> (?P<message>.+)$".to_string(),
+        ),
+        (
+            "join_leave",
+            r"^(?:: )*(?P<user>\S.*) has (?P<status>joined|left)\.$".to_string(),
+        ),
+        (
+            "playing",
+            r"^(?:: )*(?P<user>.+?) \((?:\d{1,3}\.){3}\d{1,3}:\d+\)$".to_string(),
+        ),
+        (
+            "connected",
+            r"^(?:: )*(?P<players>\w+ players? connected\.)$".to_string(),
+        ),
+        (
+            "version",
+            r"^(?:: )*Terraria Server (?P<version>v[0-9.]+)$".to_string(),
+        ),
+        // progress while the world is generated or loaded
+        (
+            "loading",
+            world_gen_pattern(world_gen, |key| !NOT_LOADING.contains(&key)),
+        ),
+        ("started", r"^(?:: )*Server started$".to_string()),
+        ("saving", world_gen_pattern(world_gen, |key| key == "49")),
+        (
+            "backing_up",
+            world_gen_pattern(world_gen, |key| key == "50"),
+        ),
+        // the vanilla server doesn't print anything as it exits, so this is expected to be echoed
+        // into the log by the script running it
+        ("stopped", r"^(?:: )*Server stopped$".to_string()),
+    ]);
+    match preset {
+        Preset::Vanilla => {}
+        Preset::Tshock => {
+            // chat is echoed with the group's prefix, joins include the player's IP and group, and
+            // `playing` answers with a count before listing names
+            rules.extend([
+                (
+                    "chat",
+                    r"^(?:\[[^\]]*\] )?(?P<user>[^\s:\[][^:]*?): (?P<message>.+)$".to_string(),
+                ),
+                (
+                    "join_leave",
+                    r"^(?P<user>\S.*?)(?: \([0-9a-fA-F.:\[\]]+\)(?: from '[^']*' group(?: from '[^']*')?)?)? (?:has )?(?P<status>joined|left)\.(?: \(\d+/\d+\))?$".to_string(),
+                ),
+                ("connected", r"^(?P<players>Online Players \(\d+/\d+\)):?$".to_string()),
+                ("version", r"^TShock: (?P<version>v?[0-9.]+)".to_string()),
+                ("stopped", r"^(?:Server stopped|Server shutting down.*)$".to_string()),
+            ]);
+        }
+        Preset::Tmodloader => {
+            rules.insert(
+                "version",
+                r"^(?:: )*Terraria Server (?P<version>v[0-9.]+ - tModLoader v[0-9.]+)$".to_string(),
+            );
+        }
+    }
+    rules
+}
+
+// LegacyWorldGen strings that are printed while saving, on failure, or during play
+const NOT_LOADING: [&str; 12] = [
+    "49", "50", "53", "54", "57", "59", "66", "73", "74", "75", "92", "93",
+];
+
+// Matches a line that's only one of the LegacyWorldGen strings with keys that pass include, followed
+// by its progress if any, so a player named after one can still chat and join
+fn world_gen_pattern(
+    world_gen: &HashMap<&'static str, &'static str>,
+    include: impl Fn(&str) -> bool,
+) -> String {
+    let mut texts: Vec<String> = world_gen
+        .iter()
+        .filter(|(key, _)| include(key))
+        .map(|(_, text)| regex::escape(text))
+        .collect();
+    texts.sort();
+    format!(r"^(?:: )*(?:{})(?::? *\d{{1,3}}%)?$", texts.join("|"))
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::{Lifecycle, LogEvent, LogPatterns, PatternsConfig};
+
+    fn patterns(config: &str) -> Result<LogPatterns, String> {
+        LogPatterns::new(&toml::from_str::<PatternsConfig>(config).unwrap())
+    }
+
+    #[test]
+    fn presets() {
+        let vanilla = patterns("").unwrap();
+        assert_eq!(
+            Some(LogEvent::Chat {
+                user: "alice",
+                message: "hi <bob>"
+            }),
+            vanilla.parse(": <alice> hi <bob>")
+        );
+        assert_eq!(
+            Some(LogEvent::Join("bob")),
+            vanilla.parse("bob has joined.")
+        );
+        assert_eq!(
+            Some(LogEvent::Playing("carol")),
+            vanilla.parse(": carol (127.0.0.1:50123)")
+        );
+        assert_eq!(
+            Some(LogEvent::Connected("2 players connected.")),
+            vanilla.parse("2 players connected.")
+        );
+        assert_eq!(
+            Some(LogEvent::Version("v1.4.4.9")),
+            vanilla.parse(": Terraria Server v1.4.4.9")
+        );
+        assert_eq!(
+            Some(LogEvent::Lifecycle(Lifecycle::Loading)),
+            vanilla.parse("Loading world data: 42%")
+        );
+        assert_eq!(
+            Some(LogEvent::Lifecycle(Lifecycle::Started)),
+            vanilla.parse(": Server started")
+        );
+        assert_eq!(
+            Some(LogEvent::Lifecycle(Lifecycle::Saving)),
+            vanilla.parse("Saving world data: 100%")
+        );
+        assert_eq!(
+            Some(LogEvent::Lifecycle(Lifecycle::BackingUp)),
+            vanilla.parse("Backing up world file")
+        );
+        assert_eq!(None, vanilla.parse("Saving world..."));
+        assert_eq!(
+            Some(LogEvent::Lifecycle(Lifecycle::Loading)),
+            vanilla.parse("Clean up 7%")
+        );
+        assert_eq!(
+            Some(LogEvent::Join("Clean up")),
+            vanilla.parse("Clean up has joined.")
+        );
+
+        let tshock = patterns(r#"preset = "tshock""#).unwrap();
+        assert_eq!(
+            Some(LogEvent::Chat {
+                user: "alice",
+                message: "hi"
+            }),
+            tshock.parse("[Admin] alice: hi")
+        );
+        assert_eq!(
+            Some(LogEvent::Join("bob")),
+            tshock.parse("bob (127.0.0.1) from 'default' group joined. (1/8)")
+        );
+        assert_eq!(Some(LogEvent::Leave("bob")), tshock.parse("bob has left."));
+        assert_eq!(
+            Some(LogEvent::Version("5.2.0.0")),
+            tshock.parse("TShock: 5.2.0.0 (Topaz).")
+        );
+        assert_eq!(
+            Some(LogEvent::Connected("Online Players (2/8)")),
+            tshock.parse("Online Players (2/8):")
+        );
+        assert_eq!(
+            Some(LogEvent::Lifecycle(Lifecycle::Saving)),
+            tshock.parse("Saving world data: 12%")
+        );
+        assert_eq!(
+            Some(LogEvent::Chat {
+                user: "Adding sand",
+                message: "hi"
+            }),
+            tshock.parse("Adding sand: hi")
+        );
+
+        let tmodloader = patterns(r#"preset = "tmodloader""#).unwrap();
+        assert_eq!(
+            Some(LogEvent::Version("v1.4.4.9 - tModLoader v2023.8.3.4")),
+            tmodloader.parse("Terraria Server v1.4.4.9 - tModLoader v2023.8.3.4")
+        );
+    }
+
+    #[test]
+    fn custom_rules() {
+        let custom = patterns(
+            r#"
+            [chat]
+            regex = '^\[Chat\] (\S+) says (?P<text>.+)$'
+            user = "1"
+            message = "text"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            Some(LogEvent::Chat {
+                user: "alice",
+                message: "hi"
+            }),
+            custom.parse("[Chat] alice says hi")
+        );
+        // the rest of the preset is kept
+        assert_eq!(Some(LogEvent::Leave("bob")), custom.parse("bob has left."));
+
+        assert!(patterns("[version]\nregex = '('").is_err());
+        assert!(patterns("[version]\nregex = '^v(.+)$'").is_err());
+        assert!(patterns("[version]\nregex = '^v(.+)$'\nversion = \"2\"").is_err());
+        assert!(patterns("[version]\nregex = '^v(.+)$'\nversion = \"1\"").is_ok());
+        assert!(patterns("[playing]\nregex = '(?P<user>.+)'\nname = \"user\"").is_err());
+    }
+}
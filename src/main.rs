@@ -1,65 +1,56 @@
+mod charts;
+mod check_config;
 mod commands;
+mod config;
+mod digest;
+mod events;
+mod export;
+mod import_log;
+mod log_follower;
+mod log_patterns;
+mod metrics;
+mod outbox;
+mod retention;
+mod server_status;
+mod settings;
+mod storage;
 mod strings;
 mod terraria_pcap;
 
+use config::{Config, StorageKind};
+use log_patterns::LogEvent;
+use metrics::Counter;
+use outbox::Outbox;
 use poise::serenity_prelude as serenity;
-use regex::Regex;
-use serde::Deserialize;
-use serenity::all::UserId;
 use serenity::client::Client;
-use serenity::http::Http;
-use serenity::model::id::ChannelId;
 use serenity::prelude::*;
-use sqlx::postgres::{PgConnectOptions, PgPoolOptions};
-use sqlx::{ConnectOptions, Pool, Postgres};
+use server_status::ServerStatus;
+use settings::Settings;
 use std::collections::VecDeque;
-use std::fs;
-use std::process::{Stdio, exit};
+use std::process::exit;
 use std::sync::Arc;
-use tokio::io::{AsyncBufReadExt, BufReader};
-use tokio::process::Command;
+use storage::Storage;
 use tokio::signal::unix::{SignalKind, signal};
-use tokio::sync::{Mutex, oneshot};
-use tokio::time::{Duration, sleep};
-use tracing::{error, info};
+use tokio::sync::{Mutex, oneshot, watch};
+use tokio::task::JoinHandle;
+use tokio::time::{Duration, Instant, sleep, timeout_at};
+use tracing::{error, info, warn};
+
+// Where how far the server log has been read is saved, relative to the working directory
+const DEFAULT_LOG_OFFSET_FILE: &str = "server_log.offset";
+// How long background tasks get to finish what they've started when shutting down
+const SHUTDOWN_DEADLINE: Duration = Duration::from_secs(10);
 
 struct Data {
-    db: Pool<Postgres>,
-    admin_user_id: UserId,
-    server_dir: String,
+    db: Arc<dyn Storage>,
+    settings: settings::Live,
     command_response_channels: Arc<Mutex<VecDeque<oneshot::Sender<String>>>>,
 }
 
-#[derive(Deserialize)]
-struct Config {
-    bot_token: String,
-    bridge_channel_id: u64,
-    admin_user_id: u64,
-    server_dir: String,
-    server_logfile: String,
-    postgres: PgConfig,
-    tcpdump: TcpDumpConfig,
-}
-
-#[derive(Deserialize)]
-struct PgConfig {
-    host: String,
-    port: u16,
-    user: String,
-    pass: String,
-    dbname: String,
-}
-
-#[derive(Deserialize)]
-struct TcpDumpConfig {
-    interface: String,
-    port: u16,
-}
-
 pub struct DbClient;
 
 impl TypeMapKey for DbClient {
-    type Value = Pool<Postgres>;
+    type Value = Arc<dyn Storage>;
 }
 
 #[tokio::main]
@@ -67,9 +58,30 @@ impl TypeMapKey for DbClient {
 async fn main() {
     tracing_subscriber::fmt::init();
 
-    let cfg: Config =
-        toml::from_str(&fs::read_to_string("config.toml").expect("Error reading config.toml"))
-            .expect("Error parsing config.toml");
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    let path = match config::take_path(&mut args) {
+        Ok(path) => path,
+        Err(e) => {
+            error!(error = %e, "Unable to load config");
+            exit(1);
+        }
+    };
+    let cfg = match Config::load(&path) {
+        Ok(cfg) => cfg,
+        Err(e) => {
+            error!(error = %e, "Unable to load config");
+            exit(1);
+        }
+    };
+    // `--dry-run` writes what would be posted to discord to stdout, or the file given with
+    // `--dry-run=<file>`, without connecting to discord. `--no-db` keeps everything in memory
+    let dry_run = config::take_flag(&mut args, "dry-run");
+    let no_db = config::take_flag(&mut args, "no-db").is_some();
+
+    // `terraria-discord check-config` reports everything wrong with the config and exits
+    if args.first().map(String::as_str) == Some("check-config") {
+        exit(i32::from(!check_config::run(&cfg).await));
+    }
 
     let mut sigint = match signal(SignalKind::interrupt()) {
         Ok(s) => s,
@@ -85,30 +97,245 @@ async fn main() {
             exit(1);
         }
     };
+    let sighup = match signal(SignalKind::hangup()) {
+        Ok(s) => s,
+        Err(e) => {
+            error!(%e, "Error registering SIGHUP handler");
+            exit(1);
+        }
+    };
 
-    let db_options = PgConnectOptions::new()
-        .host(&cfg.postgres.host)
-        .port(cfg.postgres.port)
-        .username(&cfg.postgres.user)
-        .database(&cfg.postgres.dbname)
-        .password(&cfg.postgres.pass)
-        .disable_statement_logging();
-
-    let db_pool = PgPoolOptions::new()
-        .min_connections(1)
-        .max_connections(4)
-        .connect_with(db_options)
-        .await
-        .expect("Unable to connect to postgres");
+    if let Err(e) = charts::load_font(cfg.chart_font.as_deref().unwrap_or(charts::DEFAULT_FONT)) {
+        warn!(error = %e, "charts will not be available");
+    }
+
+    let settings = match Settings::new(&cfg) {
+        Ok(settings) => settings,
+        Err(e) => {
+            error!(error = %e, "Invalid config");
+            exit(1);
+        }
+    };
+
+    if dry_run.is_none() && cfg.bot_token.is_empty() {
+        error!("Missing bot_token, set it or bot_token_file");
+        exit(1);
+    }
+
+    let db: Arc<dyn Storage> = if no_db {
+        info!("not using a database, nothing will be kept");
+        Arc::new(storage::MemoryStorage::default())
+    } else {
+        match connect_storage(&cfg).await {
+            Ok(db) => db,
+            Err(e) => {
+                error!(error = %e, "Unable to connect to storage");
+                exit(1);
+            }
+        }
+    };
+
+    // `terraria-discord migrate` only applies migrations, for when the bot's user can't
+    let migrate_only = args.first().map(String::as_str) == Some("migrate");
+    if migrate_only || cfg.migrate_on_start.unwrap_or(true) {
+        if let Err(e) = db.migrate().await {
+            error!(error = %e, "Error running database migrations");
+            exit(1);
+        }
+        info!("database migrations applied");
+        if migrate_only {
+            return;
+        }
+    }
+
+    if args.first().map(String::as_str) == Some("export") {
+        match export::cli(db.as_ref(), &args[1..]).await {
+            Ok(filename) => info!(filename, "exported"),
+            Err(e) => {
+                error!(error = %e, "Unable to export");
+                exit(1);
+            }
+        }
+        return;
+    }
+
+    if args.first().map(String::as_str) == Some("import-log") {
+        match import_log::cli(db.as_ref(), &settings.patterns, &args[1..]).await {
+            Ok(inserted) => info!(inserted, "imported log"),
+            Err(e) => {
+                error!(error = %e, "Unable to import log");
+                exit(1);
+            }
+        }
+        return;
+    }
+
+    // settings are reloaded from the config on SIGHUP
+    let (settings_tx, settings) = watch::channel(Arc::new(settings));
+    tokio::spawn(settings::reload_on_hangup(
+        sighup,
+        path,
+        cfg.clone(),
+        settings_tx,
+    ));
 
-    let intents = GatewayIntents::GUILD_MESSAGES | GatewayIntents::MESSAGE_CONTENT;
-    let data_db = db_pool.clone();
     let command_response_channels = Arc::new(Mutex::new(VecDeque::new()));
-    let data_channels = command_response_channels.clone();
+    let lifecycle = Arc::new(Mutex::new(None));
+    let mut client = match dry_run {
+        Some(_) => None,
+        None => Some(
+            discord_client(
+                &cfg.bot_token,
+                db.clone(),
+                settings.clone(),
+                command_response_channels.clone(),
+                lifecycle.clone(),
+            )
+            .await,
+        ),
+    };
+    let shard_manager = client.as_ref().map(|client| client.shard_manager.clone());
+
+    // the outbox stops separately, after everything that could add to it
+    let outbox = match (&client, dry_run) {
+        (Some(client), _) => Outbox::new(client.http.clone(), db.clone()),
+        (None, Some(file)) if !file.is_empty() => {
+            let out = tokio::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&file)
+                .await;
+            match out {
+                Ok(out) => Outbox::dry_run(Box::new(out), db.clone()),
+                Err(e) => {
+                    error!(error = %e, file, "Unable to open dry run output");
+                    exit(1);
+                }
+            }
+        }
+        (None, _) => Outbox::dry_run(Box::new(tokio::io::stdout()), db.clone()),
+    };
+    let (outbox_shutdown_tx, outbox_shutdown) = watch::channel(false);
+    let outbox_task = tokio::spawn(outbox.clone().run(outbox_shutdown));
+
+    // set when shutting down, for background tasks to stop once they've finished what they're doing
+    let (shutdown_tx, shutdown) = watch::channel(false);
+    let mut tasks = vec![];
+
+    {
+        let db = db.clone();
+        let status = ServerStatus::new(
+            outbox.clone(),
+            settings.clone(),
+            shard_manager.clone(),
+            lifecycle,
+        );
+        let follower = log_follower::LogFollower::new(
+            cfg.server_logfile.into(),
+            Some(
+                cfg.log_offset_file
+                    .unwrap_or_else(|| DEFAULT_LOG_OFFSET_FILE.to_string())
+                    .into(),
+            ),
+        )
+        .await;
+        tasks.push(tokio::spawn(send_loglines(
+            follower,
+            settings.clone(),
+            outbox.clone(),
+            db,
+            command_response_channels,
+            status,
+            shutdown.clone(),
+        )));
+    }
+
+    tasks.push(tokio::spawn(terraria_pcap::parse_packets(
+        outbox.clone(),
+        cfg.tcpdump.interface.clone(),
+        cfg.tcpdump.port,
+        db.clone(),
+        settings.clone(),
+        shutdown.clone(),
+    )));
+
+    tasks.push(tokio::spawn(digest::run(
+        outbox.clone(),
+        db.clone(),
+        settings.clone(),
+        shutdown.clone(),
+    )));
+    if let Some(addr) = cfg.metrics_addr {
+        tasks.push(tokio::spawn(metrics::serve(
+            addr,
+            shard_manager.clone(),
+            shutdown.clone(),
+        )));
+    }
+    tasks.push(tokio::spawn(retention::run(
+        db.clone(),
+        settings.clone(),
+        shutdown,
+    )));
+
+    let stop = async move {
+        tokio::select! {
+            _ = sigint.recv() => {},
+            _ = sigterm.recv() => {},
+        };
+        info!("shutting down");
+        let deadline = Instant::now() + SHUTDOWN_DEADLINE;
+        shutdown_tx.send_replace(true);
+        finish_tasks(deadline, tasks).await;
+        // behind everything else still waiting to be sent
+        let channel_id = settings.borrow().bridge_channel_id;
+        outbox.send(channel_id, ":wave: Bridge going offline").await;
+        outbox_shutdown_tx.send_replace(true);
+        finish_tasks(deadline, vec![outbox_task]).await;
+        if let Some(shard_manager) = shard_manager {
+            shard_manager.shutdown_all().await;
+        }
+    };
+
+    match &mut client {
+        Some(client) => {
+            tokio::spawn(stop);
+            if let Err(e) = client.start().await {
+                error!(error = %e, "An error occurred while running the client");
+            }
+        }
+        None => {
+            info!("dry run, not connecting to discord");
+            stop.await;
+        }
+    }
+}
+
+// The discord client, with the slash commands
+#[allow(clippy::expect_used)]
+async fn discord_client(
+    token: &str,
+    db: Arc<dyn Storage>,
+    settings: settings::Live,
+    command_response_channels: Arc<Mutex<VecDeque<oneshot::Sender<String>>>>,
+    lifecycle: Arc<Mutex<Option<log_patterns::Lifecycle>>>,
+) -> Client {
+    let intents = GatewayIntents::GUILD_MESSAGES | GatewayIntents::MESSAGE_CONTENT;
+    let data_db = db.clone();
     let framework = poise::Framework::builder()
         .options(poise::FrameworkOptions {
             commands: vec![
                 commands::deaths(),
+                commands::pvp(),
+                commands::records(),
+                commands::bosses(),
+                commands::events(),
+                commands::kills(),
+                commands::search(),
+                commands::export(),
+                commands::chart(),
+                commands::quote(),
+                commands::forget(),
                 commands::playing(),
                 commands::update(),
                 commands::version(),
@@ -119,173 +346,176 @@ async fn main() {
         .setup(move |ctx, _ready, framework| {
             Box::pin(async move {
                 poise::builtins::register_globally(ctx, &framework.options().commands).await?;
+                let (activity, status) = server_status::presence(*lifecycle.lock().await);
+                ctx.set_presence(activity, status);
                 Ok(Data {
                     db: data_db,
-                    admin_user_id: UserId::from(cfg.admin_user_id),
-                    server_dir: cfg.server_dir,
-                    command_response_channels: data_channels,
+                    settings,
+                    command_response_channels,
                 })
             })
         })
         .build();
-    let mut client = Client::builder(cfg.bot_token, intents)
+    Client::builder(token, intents)
         .framework(framework)
-        .type_map_insert::<DbClient>(db_pool.clone())
+        .type_map_insert::<DbClient>(db)
         .await
-        .expect("Error creating discord client");
+        .expect("Error creating discord client")
+}
 
-    {
-        let http = client.http.clone();
-        let pool = db_pool.clone();
-        tokio::spawn(send_loglines(
-            cfg.server_logfile,
-            http,
-            ChannelId::new(cfg.bridge_channel_id),
-            pool,
-            command_response_channels,
-        ));
+// Waits for tasks to stop until deadline. Unsent messages are left in the outbox for next time
+async fn finish_tasks(deadline: Instant, tasks: Vec<JoinHandle<()>>) {
+    match timeout_at(deadline, futures::future::join_all(tasks)).await {
+        Ok(results) => {
+            for result in results {
+                if let Err(e) = result {
+                    error!(error = %e, "Background task failed");
+                }
+            }
+        }
+        Err(_) => warn!("background tasks didn't finish in time, dropping what's left"),
     }
+}
 
-    {
-        let http = client.http.clone();
-        let pool = db_pool.clone();
-        tokio::spawn(terraria_pcap::parse_packets(
-            http,
-            ChannelId::new(cfg.bridge_channel_id),
-            cfg.tcpdump.interface.clone(),
-            cfg.tcpdump.port,
-            pool,
-        ));
-    }
+// Connects to the database backend chosen in config
+async fn connect_storage(cfg: &Config) -> Result<Arc<dyn Storage>, String> {
+    match cfg.storage {
+        #[cfg(feature = "postgres")]
+        StorageKind::Postgres => {
+            use sqlx::ConnectOptions;
+            use sqlx::postgres::{PgConnectOptions, PgPoolOptions};
 
-    let shard_manager = client.shard_manager.clone();
-    tokio::spawn(async move {
-        tokio::select! {
-            _ = sigint.recv() => {},
-            _ = sigterm.recv() => {},
-        };
-        shard_manager.shutdown_all().await;
-    });
+            let Some(pg) = cfg.postgres.as_ref() else {
+                return Err("Missing [postgres] in config".to_string());
+            };
+            let db_options = PgConnectOptions::new()
+                .host(&pg.host)
+                .port(pg.port)
+                .username(&pg.user)
+                .database(&pg.dbname)
+                .password(&pg.pass)
+                .disable_statement_logging();
+
+            let db_pool = PgPoolOptions::new()
+                .min_connections(1)
+                .max_connections(4)
+                .connect_with(db_options)
+                .await
+                .map_err(|e| format!("Unable to connect to postgres: {e}"))?;
+            Ok(Arc::new(storage::PostgresStorage::new(db_pool)))
+        }
+        #[cfg(feature = "sqlite")]
+        StorageKind::Sqlite => {
+            use sqlx::ConnectOptions;
+            use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
+
+            let Some(sqlite) = cfg.sqlite.as_ref() else {
+                return Err("Missing [sqlite] in config".to_string());
+            };
+            let db_options = SqliteConnectOptions::new()
+                .filename(&sqlite.path)
+                .create_if_missing(true)
+                .disable_statement_logging();
 
-    if let Err(e) = client.start().await {
-        error!(error = %e, "An error occurred while running the client");
+            let db_pool = SqlitePoolOptions::new()
+                .min_connections(1)
+                .max_connections(4)
+                .connect_with(db_options)
+                .await
+                .map_err(|e| format!("Unable to open sqlite database: {e}"))?;
+            Ok(Arc::new(storage::SqliteStorage::new(db_pool)))
+        }
+        #[allow(unreachable_patterns)]
+        kind => Err(format!(
+            "terraria-discord was built without support for {} storage",
+            kind.name()
+        )),
     }
 }
 
-// "tail"s server logfile, sending new lines to discord
+// Follows server logfile, sending new lines to discord
 async fn send_loglines(
-    filename: String,
-    http: Arc<Http>,
-    channel_id: ChannelId,
-    db: Pool<Postgres>,
+    mut follower: log_follower::LogFollower,
+    settings: settings::Live,
+    outbox: Outbox,
+    db: Arc<dyn Storage>,
     command_response_channels: Arc<Mutex<VecDeque<oneshot::Sender<String>>>>,
+    mut status: ServerStatus,
+    mut shutdown: watch::Receiver<bool>,
 ) {
-    #[allow(clippy::expect_used)]
-    #[allow(clippy::expect_used)]
-    let tail = Command::new("tail")
-        .stdout(Stdio::piped())
-        .args(["-n", "0", "-F", &filename])
-        .kill_on_drop(true)
-        .spawn()
-        .expect("error spawning log tail");
-
-    // Look for chat messages, joins, and leaves
-    #[allow(clippy::unwrap_used)]
-    let chat_regex = Regex::new(r"^(?:: )*<(?P<user>.+?)> (?P<message>.+)$").unwrap();
-    #[allow(clippy::unwrap_used)]
-    let join_leave_regex =
-        Regex::new(r"^(?:: )*(?P<user>\S.*) has (?P<status>joined|left)\.$").unwrap();
-    #[allow(clippy::unwrap_used)]
-    let playing_regex =
-        Regex::new(r"^(?:: )*(?P<user>.+?) \((?:\d{1,3}\.){3}\d{1,3}:\d+\)$").unwrap();
-    #[allow(clippy::unwrap_used)]
-    let connected_regex = Regex::new(r"^(?:: )*(\w+ players? connected\.)$").unwrap();
-    #[allow(clippy::unwrap_used)]
-    let version_regex = Regex::new(r"^(?:: )*Terraria Server (v[0-9.]+)$").unwrap();
-
-    #[allow(clippy::expect_used)]
-    let mut reader = BufReader::new(tail.stdout.expect("Missing stdout on tail child"));
-
     info!("starting log reader loop");
+    let _alive = metrics::Alive::new(metrics::Task::Log);
     loop {
-        let mut line = String::new();
-        if let Err(e) = reader.read_line(&mut line).await {
-            error!(error = %e, "Error reading from tail stdout");
-            continue;
-        }
+        // lines are only committed once they've been handled, so one cut off here is read again
+        let line = tokio::select! {
+            line = follower.next_line() => line,
+            _ = shutdown.changed() => break,
+        };
         let line = line.trim();
+        let current = settings.borrow().clone();
+        let event = current.patterns.parse(line);
+        if let Some(event) = &event {
+            metrics::count_by(Counter::LogLinesMatched, event.rule());
+        }
 
-        // If line has content and matches one of the lines we want to send to discord
-        if let Some(caps) = chat_regex.captures(line) {
-            let user = &caps["user"];
-            if user != "Server" {
-                let message = &caps["message"];
-                if let Err(e) = channel_id
-                    .say(&http, format!("<{}> {}", &caps["user"], &caps["message"]))
-                    .await
-                {
-                    error!(error = %e, "Unable to send chat to discord");
-                }
-                #[allow(clippy::panic)]
-                if let Err(e) = sqlx::query!(
-                    r#"INSERT INTO message(author, content) VALUES ($1, $2)"#,
-                    user,
-                    message
-                )
-                .execute(&db)
-                .await
+        match event {
+            Some(
+                LogEvent::Playing(response)
+                | LogEvent::Connected(response)
+                | LogEvent::Version(response),
+            ) => {
+                while let Some(channel) =
+                    get_response_channel(command_response_channels.clone()).await
                 {
-                    error!(error = %e, "Unable to insert terraria message into db");
+                    // if we get a channel but its closed, try the next one
+                    if let Err(e) = channel.send(response.into()) {
+                        error!(error = %e, "command response receiver closed");
+                    }
                 }
             }
-        } else if let Some(caps) = join_leave_regex.captures(line) {
-            let user = &caps["user"];
-            let status = &caps["status"];
-            if let Err(e) = channel_id
-                .say(&http, format!("{} has {}", &caps["user"], &caps["status"]))
-                .await
-            {
-                error!(error = %e, "Unable to send chat to discord");
+            Some(LogEvent::Lifecycle(lifecycle)) => status.update(lifecycle).await,
+            Some(event) => {
+                if let Some((player, message)) = record_logline(&event, db.as_ref()).await {
+                    outbox
+                        .send_about(current.bridge_channel_id, player, &message)
+                        .await;
+                }
             }
-            #[allow(clippy::panic)]
-            if let Err(e) = match status {
-                "joined" => sqlx::query!(r#"INSERT INTO server_join(username) VALUES ($1)"#, user)
-                    .execute(&db)
-                    .await
-                    .map(|_| ()),
-                "left" => sqlx::query!(r#"INSERT INTO server_leave(username) VALUES ($1)"#, user)
-                    .execute(&db)
-                    .await
-                    .map(|_| ()),
-                _ => Ok(()),
-            } {
-                error!(error = %e, "Error inserting terraria user status");
+            None => {}
+        }
+        follower.commit().await;
+    }
+    info!("stopped log reader loop");
+}
+
+// Stores a chat message, join, or leave, returning who it's from and what to relay to discord
+async fn record_logline<'a>(event: &LogEvent<'a>, db: &dyn Storage) -> Option<(&'a str, String)> {
+    match *event {
+        LogEvent::Chat { user, message } => {
+            if user == "Server" {
+                return None;
             }
-        } else if let Some(caps) = playing_regex.captures(line) {
-            while let Some(channel) = get_response_channel(command_response_channels.clone()).await
-            {
-                // if we get a channel but its closed, try the next one
-                if let Err(e) = channel.send(caps[1].into()) {
-                    error!(error = %e, "playing receiver closed");
-                }
+            if let Err(e) = db.insert_message(user, message).await {
+                error!(error = %e, "Unable to insert terraria message into db");
+                metrics::count(Counter::DbErrors);
             }
-        } else if let Some(caps) = connected_regex.captures(line) {
-            while let Some(channel) = get_response_channel(command_response_channels.clone()).await
-            {
-                // if we get a channel but its closed, try the next one
-                if let Err(e) = channel.send(caps[1].into()) {
-                    error!(error = %e, "connected receiver closed");
-                }
+            Some((user, format!("<{user}> {message}")))
+        }
+        LogEvent::Join(user) => {
+            if let Err(e) = db.insert_join(user).await {
+                error!(error = %e, "Error inserting terraria user status");
+                metrics::count(Counter::DbErrors);
             }
-        } else if let Some(caps) = version_regex.captures(line) {
-            while let Some(channel) = get_response_channel(command_response_channels.clone()).await
-            {
-                // if we get a channel but its closed, try the next one
-                if let Err(e) = channel.send(caps[1].into()) {
-                    error!(error = %e, "version receiver closed");
-                }
+            Some((user, format!("{user} has joined")))
+        }
+        LogEvent::Leave(user) => {
+            if let Err(e) = db.insert_leave(user).await {
+                error!(error = %e, "Error inserting terraria user status");
+                metrics::count(Counter::DbErrors);
             }
+            Some((user, format!("{user} has left")))
         }
+        _ => None,
     }
 }
 
@@ -297,3 +527,48 @@ async fn get_response_channel(
         () = sleep(Duration::from_secs(1)) => None
     }
 }
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::record_logline;
+    use crate::log_patterns::{LogPatterns, PatternsConfig};
+    use crate::storage::{MemoryStorage, Storage};
+
+    #[tokio::test]
+    async fn joins_and_leaves() {
+        let db = MemoryStorage::default();
+        let patterns = LogPatterns::new(&PatternsConfig::default()).unwrap();
+        let record = async |line: &str| match patterns.parse(line) {
+            Some(event) => record_logline(&event, &db)
+                .await
+                .map(|(player, message)| (player.to_string(), message)),
+            None => None,
+        };
+
+        for line in [
+            ": alice has joined.",
+            "bob has joined.",
+            "carol has joined.",
+            "bob has left.",
+            ": <alice> has anyone seen bob?",
+            ": <alice> brb",
+        ] {
+            assert!(record(line).await.is_some());
+        }
+        assert_eq!(db.online_players().await.unwrap(), ["alice", "carol"]);
+
+        assert_eq!(
+            Some(("bob".to_string(), "bob has joined".to_string())),
+            record("bob has joined.").await
+        );
+        assert_eq!(None, record("<Server> bob has joined.").await);
+        assert_eq!(
+            db.online_players().await.unwrap(),
+            ["alice", "bob", "carol"]
+        );
+        let quote = db.random_message("alice", None).await.unwrap().unwrap();
+        assert!(["has anyone seen bob?", "brb"].contains(&quote.content.as_str()));
+        assert!(db.random_message("carol", None).await.unwrap().is_none());
+    }
+}
@@ -0,0 +1,290 @@
+use serenity::all::ShardManager;
+use serenity::gateway::ConnectionStage;
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, LazyLock, Mutex, MutexGuard, PoisonError};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::watch;
+use tokio::time::{Duration, Instant, timeout};
+use tracing::{error, info};
+
+// How long a client has to send its request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::default);
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Counter {
+    PacketsCaptured,
+    MessagesDecoded,
+    // by reason
+    DecodeFailures,
+    UnknownKeys,
+    // by rule
+    LogLinesMatched,
+    DiscordSendFailures,
+    DbErrors,
+}
+
+impl Counter {
+    const ALL: [Self; 7] = [
+        Self::PacketsCaptured,
+        Self::MessagesDecoded,
+        Self::DecodeFailures,
+        Self::UnknownKeys,
+        Self::LogLinesMatched,
+        Self::DiscordSendFailures,
+        Self::DbErrors,
+    ];
+
+    // name, help, and the label it's split up by
+    fn describe(self) -> (&'static str, &'static str, &'static str) {
+        match self {
+            Self::PacketsCaptured => (
+                "terraria_packets_captured_total",
+                "Packets read from tcpdump",
+                "",
+            ),
+            Self::MessagesDecoded => (
+                "terraria_messages_decoded_total",
+                "Messages decoded from packets",
+                "",
+            ),
+            Self::DecodeFailures => (
+                "terraria_decode_failures_total",
+                "Packets that couldn't be decoded",
+                "reason",
+            ),
+            Self::UnknownKeys => (
+                "terraria_unknown_keys_total",
+                "Localization keys that weren't in the strings tables",
+                "",
+            ),
+            Self::LogLinesMatched => (
+                "terraria_log_lines_matched_total",
+                "Server log lines matched by a log pattern",
+                "rule",
+            ),
+            Self::DiscordSendFailures => (
+                "terraria_discord_send_failures_total",
+                "Messages discord failed to send",
+                "",
+            ),
+            Self::DbErrors => (
+                "terraria_db_errors_total",
+                "Database errors while bridging",
+                "",
+            ),
+        }
+    }
+}
+
+// Background tasks /healthz reports on
+#[derive(Clone, Copy)]
+pub enum Task {
+    Capture,
+    Log,
+}
+
+#[derive(Default)]
+struct Metrics {
+    counters: Mutex<BTreeMap<(Counter, &'static str), u64>>,
+    // total seconds and count of each server command's responses
+    command_latency: Mutex<BTreeMap<&'static str, (f64, u64)>>,
+    capture: AtomicBool,
+    log: AtomicBool,
+}
+
+impl Metrics {
+    fn counters(&self) -> MutexGuard<'_, BTreeMap<(Counter, &'static str), u64>> {
+        self.counters.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn command_latency(&self) -> MutexGuard<'_, BTreeMap<&'static str, (f64, u64)>> {
+        self.command_latency
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn alive(&self, task: Task) -> &AtomicBool {
+        match task {
+            Task::Capture => &self.capture,
+            Task::Log => &self.log,
+        }
+    }
+
+    // The Prometheus text format of every metric
+    fn render(&self) -> String {
+        let mut text = String::new();
+        let counters = self.counters();
+        for counter in Counter::ALL {
+            let (name, help, label) = counter.describe();
+            let _ = writeln!(text, "# HELP {name} {help}\n# TYPE {name} counter");
+            if label.is_empty() {
+                let value = counters.get(&(counter, "")).copied().unwrap_or_default();
+                let _ = writeln!(text, "{name} {value}");
+            }
+            for ((_, value), count) in counters
+                .range((counter, "")..)
+                .take_while(|((c, _), _)| *c == counter)
+                .filter(|((_, value), _)| !value.is_empty())
+            {
+                let _ = writeln!(text, "{name}{{{label}=\"{value}\"}} {count}");
+            }
+        }
+
+        let name = "terraria_server_command_seconds";
+        let _ = writeln!(
+            text,
+            "# HELP {name} Time for the server to answer a command\n# TYPE {name} summary"
+        );
+        for (command, (sum, count)) in self.command_latency().iter() {
+            let _ = writeln!(text, "{name}_sum{{command=\"{command}\"}} {sum}");
+            let _ = writeln!(text, "{name}_count{{command=\"{command}\"}} {count}");
+        }
+        text
+    }
+}
+
+pub fn count(counter: Counter) {
+    count_by(counter, "");
+}
+
+// Counts counter under a value of its label
+pub fn count_by(counter: Counter, value: &'static str) {
+    *METRICS.counters().entry((counter, value)).or_default() += 1;
+}
+
+pub fn command_answered(command: &'static str, sent: Instant) {
+    let mut latency = METRICS.command_latency();
+    let (sum, count) = latency.entry(command).or_default();
+    *sum += sent.elapsed().as_secs_f64();
+    *count += 1;
+}
+
+// Marks task as alive until this is dropped, which also happens if it panics
+pub struct Alive(Task);
+
+impl Alive {
+    pub fn new(task: Task) -> Self {
+        METRICS.alive(task).store(true, Ordering::Relaxed);
+        Self(task)
+    }
+}
+
+impl Drop for Alive {
+    fn drop(&mut self) {
+        METRICS.alive(self.0).store(false, Ordering::Relaxed);
+    }
+}
+
+// Serves /metrics and /healthz on addr until shutdown is set. shard_manager is None without a discord
+// connection, for --dry-run
+pub async fn serve(
+    addr: SocketAddr,
+    shard_manager: Option<Arc<ShardManager>>,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    let listener = match TcpListener::bind(addr).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!(error = %e, %addr, "Unable to listen for metrics");
+            return;
+        }
+    };
+    info!(%addr, "serving metrics");
+    loop {
+        let stream = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok((stream, _)) => stream,
+                Err(e) => {
+                    error!(error = %e, "Unable to accept metrics connection");
+                    continue;
+                }
+            },
+            _ = shutdown.changed() => return,
+        };
+        tokio::spawn(respond(stream, shard_manager.clone()));
+    }
+}
+
+// Answers a single request and closes the connection
+async fn respond(mut stream: TcpStream, shard_manager: Option<Arc<ShardManager>>) {
+    let mut request = vec![0; 1024];
+    let Ok(Ok(read)) = timeout(REQUEST_TIMEOUT, stream.read(&mut request)).await else {
+        return;
+    };
+    let request = String::from_utf8_lossy(&request[..read]);
+    let mut parts = request.split_whitespace();
+    let (status, content_type, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/metrics")) => {
+            ("200 OK", "text/plain; version=0.0.4", METRICS.render())
+        }
+        (Some("GET"), Some("/healthz")) => {
+            let mut checks = vec![
+                ("capture", METRICS.capture.load(Ordering::Relaxed)),
+                ("log", METRICS.log.load(Ordering::Relaxed)),
+            ];
+            if let Some(shard_manager) = &shard_manager {
+                let discord = shard_manager
+                    .runners
+                    .lock()
+                    .await
+                    .values()
+                    .any(|runner| runner.stage == ConnectionStage::Connected);
+                checks.push(("discord", discord));
+            }
+            let mut body = String::new();
+            for (check, ok) in &checks {
+                let _ = writeln!(body, "{check} {}", if *ok { "ok" } else { "down" });
+            }
+            let status = if checks.iter().all(|(_, ok)| *ok) {
+                "200 OK"
+            } else {
+                "503 Service Unavailable"
+            };
+            (status, "text/plain", body)
+        }
+        _ => ("404 Not Found", "text/plain", "not found\n".to_string()),
+    };
+    let response = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+    if let Err(e) = stream.write_all(response.as_bytes()).await {
+        error!(error = %e, "Unable to write metrics response");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Counter, Metrics};
+
+    #[test]
+    fn render() {
+        let metrics = Metrics::default();
+        {
+            let mut counters = metrics.counters();
+            counters.insert((Counter::PacketsCaptured, ""), 12);
+            counters.insert((Counter::LogLinesMatched, "chat"), 3);
+            counters.insert((Counter::LogLinesMatched, "join_leave"), 2);
+        }
+        metrics.command_latency().insert("playing", (0.5, 2));
+        let text = metrics.render();
+        for line in [
+            "# TYPE terraria_packets_captured_total counter",
+            "terraria_packets_captured_total 12",
+            "terraria_db_errors_total 0",
+            "terraria_log_lines_matched_total{rule=\"chat\"} 3",
+            "terraria_log_lines_matched_total{rule=\"join_leave\"} 2",
+            "terraria_server_command_seconds_sum{command=\"playing\"} 0.5",
+            "terraria_server_command_seconds_count{command=\"playing\"} 2",
+        ] {
+            assert!(text.lines().any(|l| l == line), "missing {line} in {text}");
+        }
+        assert!(!text.contains("terraria_log_lines_matched_total 0"));
+    }
+}
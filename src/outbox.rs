@@ -0,0 +1,321 @@
+use crate::metrics::{self, Counter};
+use crate::storage::Storage;
+use serenity::http::{Http, StatusCode};
+use serenity::model::id::ChannelId;
+use std::collections::VecDeque;
+use std::sync::{Arc, MutexGuard, PoisonError};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tokio::sync::{Mutex, Notify, watch};
+use tokio::time::{Duration, sleep};
+use tracing::{error, warn};
+
+// How long to wait before retrying a failed send, doubling each time it fails again up to the most
+const FIRST_RETRY: Duration = Duration::from_secs(1);
+const MAX_RETRY: Duration = Duration::from_mins(5);
+
+// Queues bridge messages in the database until they're sent, so they reach discord in order even
+// through an outage or a restart
+#[derive(Clone)]
+pub struct Outbox {
+    sink: Sink,
+    db: Arc<dyn Storage>,
+    // messages that couldn't be queued in the database, sent after everything that was, in order
+    held: Arc<std::sync::Mutex<VecDeque<(ChannelId, String)>>>,
+    queued: Arc<Notify>,
+}
+
+#[derive(Clone)]
+enum Sink {
+    Discord(Arc<Http>),
+    // --dry-run writes messages here instead, without queueing them
+    Console(Arc<Mutex<Box<dyn AsyncWrite + Send + Unpin>>>),
+    #[cfg(test)]
+    Fake(Arc<std::sync::Mutex<tests::FakeDiscord>>),
+}
+
+// Why a message wasn't sent
+#[derive(Clone)]
+enum Failure {
+    // it'll never be accepted, retrying would hold up everything after it
+    Rejected(String),
+    Retry(String),
+}
+
+impl Outbox {
+    pub fn new(http: Arc<Http>, db: Arc<dyn Storage>) -> Self {
+        Self::with_sink(Sink::Discord(http), db)
+    }
+
+    pub fn dry_run(out: Box<dyn AsyncWrite + Send + Unpin>, db: Arc<dyn Storage>) -> Self {
+        Self::with_sink(Sink::Console(Arc::new(Mutex::new(out))), db)
+    }
+
+    fn with_sink(sink: Sink, db: Arc<dyn Storage>) -> Self {
+        Self {
+            sink,
+            db,
+            held: Arc::default(),
+            queued: Arc::new(Notify::new()),
+        }
+    }
+
+    fn held(&self) -> MutexGuard<'_, VecDeque<(ChannelId, String)>> {
+        self.held.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub async fn send(&self, channel_id: ChannelId, content: &str) {
+        self.queue(channel_id, None, content).await;
+    }
+
+    // Sends a message about player, such as their chat or death, which is dropped from the queue if
+    // they're forgotten before it's sent
+    pub async fn send_about(&self, channel_id: ChannelId, player: &str, content: &str) {
+        self.queue(channel_id, Some(player), content).await;
+    }
+
+    async fn queue(&self, channel_id: ChannelId, player: Option<&str>, content: &str) {
+        if let Sink::Console(out) = &self.sink {
+            let line = format!("#{channel_id} {content}\n");
+            let mut out = out.lock().await;
+            if let Err(e) = out.write_all(line.as_bytes()).await {
+                error!(error = %e, "Unable to write dry run message");
+            }
+            if let Err(e) = out.flush().await {
+                error!(error = %e, "Unable to write dry run message");
+            }
+            return;
+        }
+        // while messages are held, queueing this in the database would put it ahead of them
+        if self.held().is_empty() {
+            match self
+                .db
+                .queue_outgoing(channel_id.get().cast_signed(), player, content)
+                .await
+            {
+                Ok(()) => {
+                    self.queued.notify_one();
+                    return;
+                }
+                Err(e) => {
+                    error!(error = %e, "Unable to queue message for discord, holding it in memory");
+                    metrics::count(Counter::DbErrors);
+                }
+            }
+        }
+        self.hold(channel_id, content);
+    }
+
+    fn hold(&self, channel_id: ChannelId, content: &str) {
+        self.held().push_back((channel_id, content.to_string()));
+        self.queued.notify_one();
+    }
+
+    async fn deliver(&self, channel_id: ChannelId, content: &str) -> Result<(), Failure> {
+        match &self.sink {
+            Sink::Discord(http) => match channel_id.say(http, content).await {
+                Ok(_) => Ok(()),
+                Err(serenity::Error::Http(e))
+                    if e.status_code().is_some_and(|status| {
+                        status.is_client_error() && status != StatusCode::TOO_MANY_REQUESTS
+                    }) =>
+                {
+                    Err(Failure::Rejected(e.to_string()))
+                }
+                Err(e) => Err(Failure::Retry(e.to_string())),
+            },
+            Sink::Console(_) => Ok(()),
+            #[cfg(test)]
+            Sink::Fake(discord) => discord
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .deliver(channel_id, content),
+        }
+    }
+
+    // Sends queued messages oldest first, then any held in memory, retrying each until it's sent or
+    // discord rejects it. While the queue can't be read held messages are still sent. serenity waits
+    // out discord's rate limits before sending. Once shutdown is set this stops when the outbox is
+    // empty
+    pub async fn run(self, mut shutdown: watch::Receiver<bool>) {
+        if let Sink::Console(_) = self.sink {
+            return;
+        }
+        let mut retry = FIRST_RETRY;
+        loop {
+            let (next, unreadable) = match self.db.next_outgoing().await {
+                Ok(next) => (next, false),
+                Err(e) => {
+                    error!(error = %e, "Unable to read outbox");
+                    metrics::count(Counter::DbErrors);
+                    (None, true)
+                }
+            };
+            let (id, channel_id, content) = match next {
+                Some(next) => (
+                    Some(next.id),
+                    ChannelId::new(next.channel_id.cast_unsigned()),
+                    next.content,
+                ),
+                None => {
+                    let held = self.held().front().cloned();
+                    let Some((channel_id, content)) = held else {
+                        if unreadable {
+                            back_off(&mut retry).await;
+                            continue;
+                        }
+                        if *shutdown.borrow() {
+                            return;
+                        }
+                        tokio::select! {
+                            () = self.queued.notified() => {}
+                            changed = shutdown.changed() => {
+                                if changed.is_err() {
+                                    return;
+                                }
+                            }
+                        }
+                        continue;
+                    };
+                    (None, channel_id, content)
+                }
+            };
+
+            match self.deliver(channel_id, &content).await {
+                Ok(()) => retry = FIRST_RETRY,
+                Err(Failure::Rejected(e)) => {
+                    error!(error = e, content, "Discord rejected message, dropping it");
+                    metrics::count(Counter::DiscordSendFailures);
+                }
+                Err(Failure::Retry(e)) => {
+                    warn!(error = e, ?retry, "Unable to send to discord, retrying");
+                    metrics::count(Counter::DiscordSendFailures);
+                    back_off(&mut retry).await;
+                    continue;
+                }
+            }
+            let Some(id) = id else {
+                self.held().pop_front();
+                continue;
+            };
+            if let Err(e) = self.db.remove_outgoing(id).await {
+                error!(error = %e, "Unable to remove sent message from outbox");
+                metrics::count(Counter::DbErrors);
+                back_off(&mut retry).await;
+            }
+        }
+    }
+}
+
+async fn back_off(retry: &mut Duration) {
+    sleep(*retry).await;
+    *retry = (*retry * 2).min(MAX_RETRY);
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::{Failure, Outbox, Sink};
+    use crate::storage::{MemoryStorage, Storage};
+    use serenity::model::id::ChannelId;
+    use std::collections::VecDeque;
+    use std::sync::{Arc, Mutex};
+    use tokio::sync::watch;
+
+    // Records what's sent instead of sending it, failing sends with results until they run out
+    #[derive(Default)]
+    pub struct FakeDiscord {
+        results: VecDeque<Failure>,
+        attempts: usize,
+        sent: Vec<String>,
+    }
+
+    impl FakeDiscord {
+        pub fn deliver(&mut self, _: ChannelId, content: &str) -> Result<(), Failure> {
+            self.attempts += 1;
+            match self.results.pop_front() {
+                Some(failure) => Err(failure),
+                None => {
+                    self.sent.push(content.to_string());
+                    Ok(())
+                }
+            }
+        }
+    }
+
+    fn outbox(results: &[Failure]) -> (Outbox, Arc<MemoryStorage>, Arc<Mutex<FakeDiscord>>) {
+        let db = Arc::new(MemoryStorage::default());
+        let discord = Arc::new(Mutex::new(FakeDiscord {
+            results: results.iter().cloned().collect(),
+            ..FakeDiscord::default()
+        }));
+        let outbox = Outbox::with_sink(Sink::Fake(discord.clone()), db.clone());
+        (outbox, db, discord)
+    }
+
+    // Sends everything in the outbox, stopping once it's empty
+    async fn drain(outbox: &Outbox) {
+        let (_tx, shutdown) = watch::channel(true);
+        outbox.clone().run(shutdown).await;
+    }
+
+    #[tokio::test]
+    async fn order() {
+        let (outbox, db, discord) = outbox(&[]);
+        let channel = ChannelId::new(1);
+        db.queue_outgoing(1, None, "queued before").await.unwrap();
+        // as if the database was unavailable
+        outbox.hold(channel, "held");
+        // goes behind what's held rather than ahead of it in the database
+        outbox.send(channel, "sent after").await;
+        drain(&outbox).await;
+        assert_eq!(
+            discord.lock().unwrap().sent,
+            ["queued before", "held", "sent after"]
+        );
+        assert!(db.next_outgoing().await.unwrap().is_none());
+        assert!(outbox.held().is_empty());
+    }
+
+    #[tokio::test]
+    async fn database_down() {
+        let (outbox, db, discord) = outbox(&[]);
+        db.set_outbox_unavailable(true);
+        outbox.send(ChannelId::new(1), "hello").await;
+        outbox.send(ChannelId::new(1), "again").await;
+        let (tx, shutdown) = watch::channel(false);
+        let run = tokio::spawn(outbox.clone().run(shutdown));
+        while discord.lock().unwrap().sent.len() < 2 {
+            tokio::task::yield_now().await;
+        }
+        assert!(outbox.held().is_empty());
+
+        db.set_outbox_unavailable(false);
+        tx.send(true).unwrap();
+        run.await.unwrap();
+        assert_eq!(discord.lock().unwrap().sent, ["hello", "again"]);
+    }
+
+    #[tokio::test]
+    async fn retry() {
+        let (outbox, db, discord) = outbox(&[Failure::Retry("unavailable".into())]);
+        outbox.send(ChannelId::new(1), "hello").await;
+        outbox.send(ChannelId::new(1), "again").await;
+        drain(&outbox).await;
+        assert!(db.next_outgoing().await.unwrap().is_none());
+        let discord = discord.lock().unwrap();
+        assert_eq!(discord.attempts, 3);
+        assert_eq!(discord.sent, ["hello", "again"]);
+    }
+
+    #[tokio::test]
+    async fn rejected() {
+        let (outbox, db, discord) = outbox(&[Failure::Rejected("too long".into())]);
+        outbox.send(ChannelId::new(1), "too long").await;
+        outbox.send(ChannelId::new(1), "fine").await;
+        drain(&outbox).await;
+        assert!(db.next_outgoing().await.unwrap().is_none());
+        let discord = discord.lock().unwrap();
+        assert_eq!(discord.attempts, 2);
+        assert_eq!(discord.sent, ["fine"]);
+    }
+}
@@ -0,0 +1,38 @@
+use crate::metrics::{self, Counter};
+use crate::settings;
+use crate::storage::Storage;
+use chrono::{TimeDelta, Utc};
+use std::sync::Arc;
+use tokio::sync::watch;
+use tokio::time::{Duration, interval};
+use tracing::{error, info};
+
+// How often rows past their retention window are deleted
+const PRUNE_INTERVAL: Duration = Duration::from_hours(1);
+
+// Deletes rows older than each table's retention window in days
+pub async fn run(
+    db: Arc<dyn Storage>,
+    settings: settings::Live,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    let mut ticks = interval(PRUNE_INTERVAL);
+    loop {
+        tokio::select! {
+            _ = ticks.tick() => {}
+            _ = shutdown.changed() => return,
+        }
+        let retention = settings.borrow().retention.clone();
+        for (table, days) in &retention {
+            let before = Utc::now() - TimeDelta::days(i64::from(*days));
+            match db.prune(*table, before).await {
+                Ok(0) => {}
+                Ok(rows) => info!(?table, rows, "pruned rows past retention"),
+                Err(e) => {
+                    error!(error = %e, ?table, "Unable to prune rows past retention");
+                    metrics::count(Counter::DbErrors);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,95 @@
+use crate::log_patterns::Lifecycle;
+use crate::outbox::Outbox;
+use crate::settings;
+use serenity::all::{ActivityData, OnlineStatus, ShardManager};
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use tokio::time::{Duration, Instant};
+
+// How long the server has to stop printing the same status before it's announced again, so a
+// save's progress lines are only announced once
+const REPEAT_AFTER: Duration = Duration::from_mins(1);
+
+// Announces server status changes to discord and shows whether it's up in the bot's presence
+pub struct ServerStatus {
+    outbox: Outbox,
+    settings: settings::Live,
+    // None when there's no discord connection, for --dry-run
+    shard_manager: Option<Arc<ShardManager>>,
+    // the status the bot's presence is showing, for when the bot connects
+    current: Arc<Mutex<Option<Lifecycle>>>,
+    last: Option<(Lifecycle, Instant)>,
+}
+
+impl ServerStatus {
+    pub fn new(
+        outbox: Outbox,
+        settings: settings::Live,
+        shard_manager: Option<Arc<ShardManager>>,
+        current: Arc<Mutex<Option<Lifecycle>>>,
+    ) -> Self {
+        Self {
+            outbox,
+            settings,
+            shard_manager,
+            current,
+            last: None,
+        }
+    }
+
+    pub async fn update(&mut self, lifecycle: Lifecycle) {
+        let now = Instant::now();
+        let repeated = self
+            .last
+            .is_some_and(|(last, at)| last == lifecycle && now - at < REPEAT_AFTER);
+        self.last = Some((lifecycle, now));
+        if repeated {
+            return;
+        }
+
+        let channel_id = self.settings.borrow().bridge_channel_id;
+        self.outbox.send(channel_id, announcement(lifecycle)).await;
+
+        // saving and backing up happen while the server's up
+        if matches!(lifecycle, Lifecycle::Saving | Lifecycle::BackingUp) {
+            return;
+        }
+        *self.current.lock().await = Some(lifecycle);
+        let Some(shard_manager) = &self.shard_manager else {
+            return;
+        };
+        let (activity, status) = presence(Some(lifecycle));
+        for runner in shard_manager.runners.lock().await.values() {
+            runner.runner_tx.set_presence(activity.clone(), status);
+        }
+    }
+}
+
+fn announcement(lifecycle: Lifecycle) -> &'static str {
+    match lifecycle {
+        Lifecycle::Loading => ":hourglass: Server starting, loading world",
+        Lifecycle::Started => ":green_circle: Server started",
+        Lifecycle::Saving => ":floppy_disk: Saving world",
+        Lifecycle::BackingUp => ":floppy_disk: Backing up world",
+        Lifecycle::Stopped => ":red_circle: Server stopped",
+    }
+}
+
+// The bot's presence for a server status, or for not having seen one yet
+pub fn presence(lifecycle: Option<Lifecycle>) -> (Option<ActivityData>, OnlineStatus) {
+    match lifecycle {
+        None => (None, OnlineStatus::Online),
+        Some(Lifecycle::Loading) => (
+            Some(ActivityData::custom("Server starting")),
+            OnlineStatus::Idle,
+        ),
+        Some(Lifecycle::Started | Lifecycle::Saving | Lifecycle::BackingUp) => (
+            Some(ActivityData::playing("Terraria")),
+            OnlineStatus::Online,
+        ),
+        Some(Lifecycle::Stopped) => (
+            Some(ActivityData::custom("Server down")),
+            OnlineStatus::DoNotDisturb,
+        ),
+    }
+}
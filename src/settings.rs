@@ -0,0 +1,206 @@
+use crate::config::Config;
+use crate::digest::Schedule;
+use crate::log_patterns::LogPatterns;
+use crate::storage::PrunedTable;
+use serenity::model::id::{ChannelId, RoleId, UserId};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::signal::unix::Signal;
+use tokio::sync::watch;
+use tracing::{error, info, warn};
+
+// The parts of the config that take effect without restarting, replaced when it's reloaded
+pub struct Settings {
+    pub bridge_channel_id: ChannelId,
+    pub admin_user_id: UserId,
+    // everyone allowed to use admin commands, including admin_user_id
+    pub admin_user_ids: Vec<UserId>,
+    pub admin_role_ids: Vec<RoleId>,
+    pub server_dir: String,
+    pub killing_spree: Option<u32>,
+    pub patterns: LogPatterns,
+    pub digest: Option<Schedule>,
+    // days to keep each table's rows
+    pub retention: HashMap<PrunedTable, u32>,
+}
+
+// The current settings, read each time they're used so reloads are picked up
+pub type Live = watch::Receiver<Arc<Settings>>;
+
+impl Settings {
+    pub fn new(cfg: &Config) -> Result<Self, String> {
+        let patterns = LogPatterns::new(&cfg.log_patterns)
+            .map_err(|e| format!("Invalid log_patterns config: {e}"))?;
+        let digest = match &cfg.digest {
+            Some(digest) => Some(
+                Schedule::new(
+                    digest.every,
+                    digest.at.as_deref(),
+                    digest.weekday,
+                    digest.timezone.as_deref(),
+                )
+                .map_err(|e| format!("Invalid digest config: {e}"))?,
+            ),
+            None => None,
+        };
+        Ok(Self {
+            bridge_channel_id: ChannelId::new(cfg.bridge_channel_id),
+            admin_user_id: UserId::new(cfg.admin_user_id),
+            admin_user_ids: std::iter::once(&cfg.admin_user_id)
+                .chain(&cfg.admin_user_ids)
+                .map(|&id| UserId::new(id))
+                .collect(),
+            admin_role_ids: cfg
+                .admin_role_ids
+                .iter()
+                .map(|&id| RoleId::new(id))
+                .collect(),
+            server_dir: cfg.server_dir.clone(),
+            killing_spree: cfg.killing_spree,
+            patterns,
+            digest,
+            retention: cfg.retention.clone(),
+        })
+    }
+}
+
+// Reloads the config at path on every SIGHUP, sending the new settings to tx if any changed. cfg is
+// the config running, changes to it that need a restart are only logged
+pub async fn reload_on_hangup(
+    mut sighup: Signal,
+    path: String,
+    mut cfg: Config,
+    tx: watch::Sender<Arc<Settings>>,
+) {
+    while sighup.recv().await.is_some() {
+        info!(path, "reloading config");
+        let new = match Config::load(&path) {
+            Ok(new) => new,
+            Err(e) => {
+                error!(error = %e, "Unable to reload config, keeping the current one");
+                continue;
+            }
+        };
+        let settings = match Settings::new(&new) {
+            Ok(settings) => settings,
+            Err(e) => {
+                error!(error = %e, "Unable to reload config, keeping the current one");
+                continue;
+            }
+        };
+
+        let (applied, restart) = changes(&cfg, &new);
+        if !restart.is_empty() {
+            warn!(settings = ?restart, "changed settings need a restart to take effect");
+        }
+        if applied.is_empty() {
+            info!("no settings to apply changed");
+            continue;
+        }
+        info!(settings = ?applied, "applied changed settings");
+        tx.send_replace(Arc::new(settings));
+        cfg.bridge_channel_id = new.bridge_channel_id;
+        cfg.admin_user_id = new.admin_user_id;
+        cfg.admin_user_ids = new.admin_user_ids;
+        cfg.admin_role_ids = new.admin_role_ids;
+        cfg.server_dir = new.server_dir;
+        cfg.killing_spree = new.killing_spree;
+        cfg.log_patterns = new.log_patterns;
+        cfg.digest = new.digest;
+        cfg.retention = new.retention;
+    }
+}
+
+// The settings that differ between old and new that can be applied, and those that need a restart
+fn changes(old: &Config, new: &Config) -> (Vec<&'static str>, Vec<&'static str>) {
+    let live = [
+        (
+            "bridge_channel_id",
+            old.bridge_channel_id != new.bridge_channel_id,
+        ),
+        ("admin_user_id", old.admin_user_id != new.admin_user_id),
+        ("admin_user_ids", old.admin_user_ids != new.admin_user_ids),
+        ("admin_role_ids", old.admin_role_ids != new.admin_role_ids),
+        ("server_dir", old.server_dir != new.server_dir),
+        ("killing_spree", old.killing_spree != new.killing_spree),
+        ("log_patterns", old.log_patterns != new.log_patterns),
+        ("digest", old.digest != new.digest),
+        ("retention", old.retention != new.retention),
+    ];
+    let mut restart = vec![
+        ("bot_token", old.bot_token != new.bot_token),
+        ("server_logfile", old.server_logfile != new.server_logfile),
+        (
+            "log_offset_file",
+            old.log_offset_file != new.log_offset_file,
+        ),
+        ("chart_font", old.chart_font != new.chart_font),
+        (
+            "migrate_on_start",
+            old.migrate_on_start != new.migrate_on_start,
+        ),
+        ("storage", old.storage != new.storage),
+        ("tcpdump", old.tcpdump != new.tcpdump),
+        ("metrics_addr", old.metrics_addr != new.metrics_addr),
+    ];
+    #[cfg(feature = "postgres")]
+    restart.push(("postgres", old.postgres != new.postgres));
+    #[cfg(feature = "sqlite")]
+    restart.push(("sqlite", old.sqlite != new.sqlite));
+
+    let changed = |settings: &[(&'static str, bool)]| {
+        settings
+            .iter()
+            .filter(|(_, changed)| *changed)
+            .map(|(name, _)| *name)
+            .collect()
+    };
+    (changed(&live), changed(&restart))
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::changes;
+    use crate::config::Config;
+
+    #[test]
+    fn changed() {
+        let config = |extra: &str| -> Config {
+            toml::from_str(&format!(
+                r#"
+                bot_token = "abc"
+                bridge_channel_id = 1
+                admin_user_id = 2
+                server_dir = "/srv/terraria"
+                server_logfile = "/srv/terraria/server_log.txt"
+                [tcpdump]
+                interface = "eth0"
+                port = 7777
+                {extra}
+                "#
+            ))
+            .unwrap()
+        };
+        let old = config("");
+        assert_eq!(changes(&old, &config("")), (vec![], vec![]));
+        assert_eq!(
+            changes(
+                &old,
+                &config("[log_patterns]\npreset = \"tshock\"\n[retention]\nmessage = 30")
+            ),
+            (vec!["log_patterns", "retention"], vec![])
+        );
+        let mut new = config("[digest]\nevery = \"day\"");
+        new.bridge_channel_id = 3;
+        new.admin_role_ids = vec![4];
+        new.tcpdump.port = 7778;
+        assert_eq!(
+            changes(&old, &new),
+            (
+                vec!["bridge_channel_id", "admin_role_ids", "digest"],
+                vec!["tcpdump"]
+            )
+        );
+    }
+}
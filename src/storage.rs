@@ -0,0 +1,454 @@
+mod memory;
+#[cfg(feature = "postgres")]
+mod postgres;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use futures::stream::BoxStream;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+pub use memory::MemoryStorage;
+#[cfg(feature = "postgres")]
+pub use postgres::PostgresStorage;
+#[cfg(feature = "sqlite")]
+pub use sqlite::SqliteStorage;
+
+pub type Result<T> = std::result::Result<T, sqlx::Error>;
+
+// Stands in for a forgotten player's name in other players' deaths
+pub const FORGOTTEN: &str = "Someone";
+
+pub struct NewDeath<'a> {
+    pub victim: &'a str,
+    pub killer: Option<&'a str>,
+    pub weapon: Option<&'a str>,
+    pub message: &'a str,
+    pub seconds_since_last: Option<i32>,
+    pub is_pk: bool,
+}
+
+pub struct NewKillMilestone<'a> {
+    pub player: Option<&'a str>,
+    pub count: i32,
+    pub npc_key: &'a str,
+    pub npc_name: &'a str,
+}
+
+// A chat message, join, or leave read from an old server log
+pub struct ImportedLine<'a> {
+    // which line of the log it's from, from 0
+    pub line: i64,
+    pub create_date: DateTime<Utc>,
+    pub event: ImportedEvent<'a>,
+}
+
+pub enum ImportedEvent<'a> {
+    Message { author: &'a str, content: &'a str },
+    Join(&'a str),
+    Leave(&'a str),
+}
+
+#[derive(Default)]
+pub struct DeathFilter {
+    pub since: Option<DateTime<Utc>>,
+    pub killer: Option<String>,
+    pub pvp_only: bool,
+    // None counts deaths in every world
+    pub world: Option<String>,
+}
+
+// What deaths are counted per for charts
+#[derive(Clone, Copy)]
+pub enum DeathGroup {
+    Day,
+    Hour,
+    Victim,
+}
+
+impl DeathGroup {
+    #[cfg(feature = "postgres")]
+    fn name(self) -> &'static str {
+        match self {
+            Self::Day => "day",
+            Self::Hour => "hour",
+            Self::Victim => "victim",
+        }
+    }
+
+    // The label a death of victim at create_date is counted under, with days and hours in timezone
+    fn label(self, create_date: DateTime<Utc>, victim: &str, timezone: Tz) -> String {
+        let local = create_date.with_timezone(&timezone);
+        match self {
+            Self::Day => local.format("%Y-%m-%d").to_string(),
+            Self::Hour => local.format("%H").to_string(),
+            Self::Victim => victim.to_string(),
+        }
+    }
+}
+
+// Tables with a retention window
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PrunedTable {
+    Message,
+    Death,
+    ServerJoin,
+    ServerLeave,
+}
+
+// Rows removed or changed by forgetting a player
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Erasure {
+    pub messages: u64,
+    // deaths of the player
+    pub deaths: u64,
+    // joins and leaves
+    pub sessions: u64,
+    // deaths caused by the player, which are kept with the killer anonymized
+    pub kills: u64,
+    // bridge messages about the player that hadn't been sent
+    pub queued: u64,
+}
+
+#[derive(sqlx::FromRow)]
+pub struct DeathCount {
+    pub victim: String,
+    pub deaths: i64,
+}
+
+// Deaths on a day ("2024-01-31"), in an hour of the day ("00" to "23"), or of a victim
+#[derive(sqlx::FromRow)]
+pub struct DeathsPer {
+    pub label: String,
+    pub deaths: i64,
+}
+
+#[derive(sqlx::FromRow)]
+pub struct PvpPlayer {
+    pub player: String,
+    pub kills: i64,
+    pub deaths: i64,
+}
+
+#[derive(sqlx::FromRow)]
+pub struct PvpWeapon {
+    pub weapon: String,
+    pub kills: i64,
+}
+
+#[derive(sqlx::FromRow)]
+pub struct Nemesis {
+    pub victim: String,
+    pub killer: String,
+    pub kills: i64,
+}
+
+#[derive(sqlx::FromRow)]
+pub struct SurvivalRecord {
+    pub victim: String,
+    pub longest: i32,
+    pub fastest: i32,
+}
+
+// How many deaths were from a cause, a death message without the victim's name
+#[derive(sqlx::FromRow)]
+pub struct DeathCause {
+    pub cause: String,
+    pub deaths: i64,
+}
+
+#[derive(sqlx::FromRow)]
+pub struct EndedFight {
+    pub boss: String,
+    pub start_date: DateTime<Utc>,
+    pub end_date: DateTime<Utc>,
+    // how many fights against this boss have ended since it was last defeated, including this one
+    // and not counting abandoned fights
+    pub attempt: i64,
+    pub world: Option<String>,
+}
+
+#[derive(sqlx::FromRow)]
+pub struct FightDeath {
+    pub victim: String,
+    pub message: String,
+}
+
+#[derive(sqlx::FromRow)]
+pub struct BossSummary {
+    pub boss: String,
+    pub first_kill: Option<DateTime<Utc>>,
+    pub kills: i64,
+    // fights up to and including the first kill, not counting abandoned fights
+    pub attempts: i64,
+    pub fastest: Option<i32>,
+}
+
+#[derive(sqlx::FromRow)]
+pub struct WorldEvent {
+    pub name: String,
+    pub is_invasion: bool,
+    pub start_date: DateTime<Utc>,
+    pub duration: Option<i32>,
+}
+
+#[derive(sqlx::FromRow)]
+pub struct KillMilestone {
+    pub npc_name: String,
+    pub player: String,
+    pub count: i32,
+}
+
+#[derive(sqlx::FromRow, Serialize)]
+pub struct Message {
+    pub author: String,
+    pub content: String,
+    pub create_date: DateTime<Utc>,
+}
+
+#[derive(sqlx::FromRow, Serialize)]
+pub struct Death {
+    pub create_date: DateTime<Utc>,
+    pub victim: String,
+    pub killer: Option<String>,
+    pub weapon: Option<String>,
+    pub message: String,
+    pub seconds_since_last: Option<i32>,
+    pub is_pk: bool,
+    pub world: Option<String>,
+}
+
+// A message waiting in the outbox to be sent to discord
+#[derive(sqlx::FromRow)]
+pub struct Outgoing {
+    pub id: i64,
+    pub channel_id: i64,
+    pub content: String,
+}
+
+// A join and the leave that followed it, if they've left
+#[derive(sqlx::FromRow, Serialize)]
+pub struct Session {
+    pub username: String,
+    pub joined: DateTime<Utc>,
+    pub left: Option<DateTime<Utc>>,
+}
+
+// An earlier import of a log, hash is of the lines imported
+#[derive(sqlx::FromRow)]
+struct LogImport {
+    id: i64,
+    lines: i64,
+    hash: Option<String>,
+}
+
+// The import, of those with the log's fingerprint, that the log continues, given hashes of the
+// log's first 1, 2, 3... lines. Imports without a hash are assumed to match
+fn continued_import<'a>(imports: &'a [LogImport], hashes: &[String]) -> Option<&'a LogImport> {
+    imports
+        .iter()
+        .filter(|import| {
+            usize::try_from(import.lines)
+                .ok()
+                .and_then(|lines| lines.checked_sub(1))
+                .and_then(|last| hashes.get(last))
+                .is_some_and(|hash| import.hash.as_ref().is_none_or(|import| import == hash))
+        })
+        .max_by_key(|import| import.lines)
+}
+
+// When each session started and ended. A session without a leave ends at the player's next join,
+// or now if they're still on
+// LIKE patterns for a search, matching each of its words anywhere in a message. Wildcards in the
+// words are escaped with \
+fn search_patterns(text: &str) -> Vec<String> {
+    text.split_whitespace()
+        .map(|word| {
+            let mut pattern = String::from("%");
+            for c in word.chars() {
+                if matches!(c, '%' | '_' | '\\') {
+                    pattern.push('\\');
+                }
+                pattern.push(c);
+            }
+            pattern.push('%');
+            pattern
+        })
+        .collect()
+}
+
+pub fn session_spans(
+    sessions: &[Session],
+    now: DateTime<Utc>,
+) -> Vec<(&str, DateTime<Utc>, DateTime<Utc>)> {
+    let mut joins: HashMap<&str, Vec<&Session>> = HashMap::new();
+    for session in sessions {
+        joins.entry(&session.username).or_default().push(session);
+    }
+    let mut spans = vec![];
+    for (username, joins) in &mut joins {
+        joins.sort_by_key(|s| s.joined);
+        for (i, session) in joins.iter().enumerate() {
+            let next_join = joins.get(i + 1).map(|s| s.joined);
+            let left = match (session.left, next_join) {
+                (Some(left), Some(next)) => left.min(next),
+                (Some(left), None) => left,
+                (None, Some(next)) => next,
+                (None, None) => now,
+            };
+            spans.push((*username, session.joined, left));
+        }
+    }
+    spans
+}
+
+// Everything the bridge and commands read from or write to the database
+#[async_trait]
+pub trait Storage: Send + Sync {
+    async fn migrate(&self) -> std::result::Result<(), sqlx::migrate::MigrateError>;
+
+    // Marks name as the world being played, everything recorded after this is from it
+    async fn set_world(&self, name: &str) -> Result<()>;
+    async fn current_world(&self) -> Result<Option<String>>;
+
+    async fn insert_message(&self, author: &str, content: &str) -> Result<()>;
+    async fn insert_join(&self, username: &str) -> Result<()>;
+    async fn insert_leave(&self, username: &str) -> Result<()>;
+    // Players who have joined the server more recently than they've left it
+    async fn online_players(&self) -> Result<Vec<String>>;
+
+    async fn insert_death(&self, death: &NewDeath<'_>) -> Result<()>;
+    // Survival streaks and kill counts only go back to the start of the current world
+    async fn last_death(&self, victim: &str) -> Result<Option<DateTime<Utc>>>;
+    // victim's longest survival streak and the server's longest survival streak
+    async fn longest_survivals(&self, victim: &str) -> Result<(Option<i32>, Option<i32>)>;
+    // Count of PvP kills player has gotten since they last died
+    async fn kills_since_death(&self, player: &str) -> Result<i64>;
+    // Count of players who have died in the current world in the last seconds
+    async fn recently_dead(&self, players: &[String], seconds: i64) -> Result<i64>;
+    async fn deaths_between(
+        &self,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        world: Option<&str>,
+    ) -> Result<Vec<FightDeath>>;
+
+    async fn start_fight(&self, boss: &str) -> Result<i64>;
+    // Fights (id and start) in the current world that have started but not ended, optionally only
+    // those against boss
+    async fn open_fights(&self, boss: Option<&str>) -> Result<Vec<(i64, DateTime<Utc>)>>;
+    async fn end_fight(&self, id: i64, defeated: bool) -> Result<EndedFight>;
+    // Closes a fight whose end we missed, which isn't counted as an attempt
+    async fn abandon_fight(&self, id: i64) -> Result<()>;
+    // Records a boss being defeated in a fight we didn't see start
+    async fn insert_defeat(&self, boss: &str) -> Result<()>;
+    async fn add_fight_players(&self, id: i64, players: &[String]) -> Result<()>;
+    async fn fight_players(&self, id: i64) -> Result<Vec<String>>;
+
+    // Invasions are announced more than once as they approach, only the first is recorded. Only
+    // invasions in the current world are ended
+    async fn start_invasion(&self, name: &str) -> Result<()>;
+    async fn end_invasion(&self, name: &str) -> Result<()>;
+    async fn insert_world_event(&self, name: &str) -> Result<()>;
+    async fn insert_kill_milestone(&self, milestone: &NewKillMilestone<'_>) -> Result<()>;
+
+    // Stats are for the named world, or every world when world is None
+    async fn death_counts(&self, filter: &DeathFilter) -> Result<Vec<DeathCount>>;
+    // Deaths counted per group, ordered by label. Days and hours are in timezone
+    async fn deaths_per(
+        &self,
+        group: DeathGroup,
+        since: Option<DateTime<Utc>>,
+        world: Option<&str>,
+        timezone: Tz,
+    ) -> Result<Vec<DeathsPer>>;
+    async fn pvp_players(&self, world: Option<&str>) -> Result<Vec<PvpPlayer>>;
+    async fn pvp_weapons(&self, limit: i64, world: Option<&str>) -> Result<Vec<PvpWeapon>>;
+    // each victim's nemesis is whoever has killed them the most
+    async fn pvp_nemeses(&self, world: Option<&str>) -> Result<Vec<Nemesis>>;
+    // Records set by deaths before before, or by every death when it's None
+    async fn survival_records(
+        &self,
+        before: Option<DateTime<Utc>>,
+        world: Option<&str>,
+    ) -> Result<Vec<SurvivalRecord>>;
+    // Every cause of the deaths before before
+    async fn death_causes(
+        &self,
+        before: DateTime<Utc>,
+        world: Option<&str>,
+    ) -> Result<Vec<DeathCause>>;
+    async fn boss_summaries(&self, world: Option<&str>) -> Result<Vec<BossSummary>>;
+    // Bosses defeated between start and end, in the order they were defeated
+    async fn bosses_defeated(
+        &self,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        world: Option<&str>,
+    ) -> Result<Vec<String>>;
+    async fn world_events(&self, limit: i64, world: Option<&str>) -> Result<Vec<WorldEvent>>;
+    async fn kill_milestones(
+        &self,
+        player: Option<&str>,
+        world: Option<&str>,
+    ) -> Result<Vec<KillMilestone>>;
+    // Messages containing every word of text, ignoring case, newest first
+    async fn search_messages(
+        &self,
+        text: &str,
+        player: Option<&str>,
+        since: Option<DateTime<Utc>>,
+        world: Option<&str>,
+        limit: i64,
+    ) -> Result<Vec<Message>>;
+    async fn random_message(&self, player: &str, world: Option<&str>) -> Result<Option<Message>>;
+
+    // Deletes rows from table that were created before before, returning how many were deleted
+    async fn prune(&self, table: PrunedTable, before: DateTime<Utc>) -> Result<u64>;
+    // Deletes player's chat, joins, leaves, deaths, and queued messages about them, and anonymizes
+    // deaths they caused, their kill milestones, and the fights they were in, recording requested_by
+    // in the audit trail
+    async fn forget_player(&self, player: &str, requested_by: &str) -> Result<Erasure>;
+    // Inserts lines from the log with fingerprint, whose first 1, 2, 3... lines hash to hashes,
+    // skipping any an earlier import of it already covered, returning how many were inserted. A log
+    // whose start doesn't hash the same as an earlier import with its fingerprint is a different log
+    async fn import_log(
+        &self,
+        fingerprint: &str,
+        hashes: &[String],
+        imported: &[ImportedLine<'_>],
+    ) -> Result<u64>;
+
+    // Adds a message for channel_id to the end of the outbox, player is who it's about if anyone
+    async fn queue_outgoing(
+        &self,
+        channel_id: i64,
+        player: Option<&str>,
+        content: &str,
+    ) -> Result<()>;
+    // The oldest message in the outbox
+    async fn next_outgoing(&self) -> Result<Option<Outgoing>>;
+    async fn remove_outgoing(&self, id: i64) -> Result<()>;
+
+    // Full rows for exports and charts, oldest first. Sessions are in the world they were joined in,
+    // and any that hadn't ended by since are included
+    fn export_deaths<'a>(
+        &'a self,
+        since: Option<DateTime<Utc>>,
+        world: Option<&'a str>,
+    ) -> BoxStream<'a, Result<Death>>;
+    fn export_messages<'a>(
+        &'a self,
+        since: Option<DateTime<Utc>>,
+        world: Option<&'a str>,
+    ) -> BoxStream<'a, Result<Message>>;
+    fn export_sessions<'a>(
+        &'a self,
+        since: Option<DateTime<Utc>>,
+        world: Option<&'a str>,
+    ) -> BoxStream<'a, Result<Session>>;
+}
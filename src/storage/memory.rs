@@ -0,0 +1,1036 @@
+// Storage kept in memory so the bridge's bookkeeping can be tested, or tried with --no-db, without a
+// database
+use super::{
+    BossSummary, DeathCause, DeathCount, DeathFilter, DeathGroup, DeathsPer, EndedFight, Erasure,
+    FORGOTTEN, FightDeath, ImportedEvent, ImportedLine, KillMilestone, LogImport, Message, Nemesis,
+    NewDeath, NewKillMilestone, Outgoing, PrunedTable, PvpPlayer, PvpWeapon, Result, Session,
+    Storage, SurvivalRecord, WorldEvent, continued_import,
+};
+use async_trait::async_trait;
+use chrono::{DateTime, TimeDelta, Utc};
+use chrono_tz::Tz;
+use futures::stream::{self, BoxStream, StreamExt};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet};
+use std::hash::{BuildHasher, Hasher, RandomState};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+struct Death {
+    victim: String,
+    killer: Option<String>,
+    weapon: Option<String>,
+    message: String,
+    seconds_since_last: Option<i32>,
+    is_pk: bool,
+    world: Option<String>,
+    create_date: DateTime<Utc>,
+}
+
+struct Fight {
+    id: i64,
+    boss: String,
+    start_date: Option<DateTime<Utc>>,
+    end_date: Option<DateTime<Utc>>,
+    defeated: bool,
+    abandoned: bool,
+    players: BTreeSet<String>,
+    world: Option<String>,
+}
+
+struct Event {
+    name: String,
+    is_invasion: bool,
+    start_date: DateTime<Utc>,
+    end_date: Option<DateTime<Utc>>,
+    world: Option<String>,
+}
+
+struct Milestone {
+    player: Option<String>,
+    count: i32,
+    npc_name: String,
+    world: Option<String>,
+}
+
+// a join, or a leave if not joined
+struct Status {
+    username: String,
+    joined: bool,
+    create_date: DateTime<Utc>,
+    world: Option<String>,
+}
+
+struct Chat {
+    author: String,
+    content: String,
+    create_date: DateTime<Utc>,
+    world: Option<String>,
+}
+
+struct Queued {
+    channel_id: i64,
+    player: Option<String>,
+    content: String,
+}
+
+#[derive(Default)]
+struct Tables {
+    // the current world, every row records which world it's from
+    world: Option<String>,
+    messages: Vec<Chat>,
+    statuses: Vec<Status>,
+    deaths: Vec<Death>,
+    fights: Vec<Fight>,
+    events: Vec<Event>,
+    milestones: Vec<Milestone>,
+    // earlier imports of logs by id, with their fingerprint
+    log_imports: BTreeMap<i64, (String, LogImport)>,
+    // messages waiting for discord by id
+    outbox: BTreeMap<i64, Queued>,
+    // fails reads and writes of the outbox, as if the database was down
+    #[cfg(test)]
+    outbox_unavailable: bool,
+}
+
+#[derive(Default)]
+pub struct MemoryStorage {
+    tables: Mutex<Tables>,
+}
+
+impl MemoryStorage {
+    fn tables(&self) -> MutexGuard<'_, Tables> {
+        self.tables.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    // Pretends the outbox can't be read or written until this is called again with false
+    #[cfg(test)]
+    pub fn set_outbox_unavailable(&self, unavailable: bool) {
+        self.tables().outbox_unavailable = unavailable;
+    }
+
+    // Pretends seconds have passed by moving everything stored back in time
+    #[cfg(test)]
+    pub fn age(&self, seconds: i64) {
+        let delta = TimeDelta::seconds(seconds);
+        let mut tables = self.tables();
+        for message in &mut tables.messages {
+            message.create_date -= delta;
+        }
+        for status in &mut tables.statuses {
+            status.create_date -= delta;
+        }
+        for death in &mut tables.deaths {
+            death.create_date -= delta;
+        }
+        for fight in &mut tables.fights {
+            fight.start_date = fight.start_date.map(|date| date - delta);
+            fight.end_date = fight.end_date.map(|date| date - delta);
+        }
+        for event in &mut tables.events {
+            event.start_date -= delta;
+            event.end_date = event.end_date.map(|date| date - delta);
+        }
+    }
+}
+
+// Whether a row from row_world is in world, or any world if that's None
+fn in_world(row_world: Option<&String>, world: Option<&str>) -> bool {
+    world.is_none_or(|world| row_world.is_some_and(|row_world| row_world == world))
+}
+
+fn seconds(delta: TimeDelta) -> i32 {
+    i32::try_from(delta.num_seconds()).unwrap_or(i32::MAX)
+}
+
+#[async_trait]
+impl Storage for MemoryStorage {
+    async fn migrate(&self) -> std::result::Result<(), sqlx::migrate::MigrateError> {
+        Ok(())
+    }
+
+    async fn set_world(&self, name: &str) -> Result<()> {
+        self.tables().world = Some(name.to_string());
+        Ok(())
+    }
+
+    async fn current_world(&self) -> Result<Option<String>> {
+        Ok(self.tables().world.clone())
+    }
+
+    async fn insert_message(&self, author: &str, content: &str) -> Result<()> {
+        let mut tables = self.tables();
+        let world = tables.world.clone();
+        tables.messages.push(Chat {
+            author: author.to_string(),
+            content: content.to_string(),
+            create_date: Utc::now(),
+            world,
+        });
+        Ok(())
+    }
+
+    async fn insert_join(&self, username: &str) -> Result<()> {
+        let mut tables = self.tables();
+        let world = tables.world.clone();
+        tables.statuses.push(Status {
+            username: username.to_string(),
+            joined: true,
+            create_date: Utc::now(),
+            world,
+        });
+        Ok(())
+    }
+
+    async fn insert_leave(&self, username: &str) -> Result<()> {
+        let mut tables = self.tables();
+        let world = tables.world.clone();
+        tables.statuses.push(Status {
+            username: username.to_string(),
+            joined: false,
+            create_date: Utc::now(),
+            world,
+        });
+        Ok(())
+    }
+
+    async fn online_players(&self) -> Result<Vec<String>> {
+        let mut last_status = BTreeMap::new();
+        for status in &self.tables().statuses {
+            last_status.insert(status.username.clone(), status.joined);
+        }
+        Ok(last_status
+            .into_iter()
+            .filter_map(|(username, joined)| joined.then_some(username))
+            .collect())
+    }
+
+    async fn insert_death(&self, death: &NewDeath<'_>) -> Result<()> {
+        let mut tables = self.tables();
+        let world = tables.world.clone();
+        tables.deaths.push(Death {
+            victim: death.victim.to_string(),
+            killer: death.killer.map(str::to_string),
+            weapon: death.weapon.map(str::to_string),
+            message: death.message.to_string(),
+            seconds_since_last: death.seconds_since_last,
+            is_pk: death.is_pk,
+            world,
+            create_date: Utc::now(),
+        });
+        Ok(())
+    }
+
+    async fn last_death(&self, victim: &str) -> Result<Option<DateTime<Utc>>> {
+        let tables = self.tables();
+        Ok(tables
+            .deaths
+            .iter()
+            .filter(|d| d.victim == victim && d.world == tables.world)
+            .map(|d| d.create_date)
+            .max())
+    }
+
+    async fn longest_survivals(&self, victim: &str) -> Result<(Option<i32>, Option<i32>)> {
+        let tables = self.tables();
+        let personal = tables
+            .deaths
+            .iter()
+            .filter(|d| d.victim == victim && d.world == tables.world)
+            .filter_map(|d| d.seconds_since_last)
+            .max();
+        let server = tables
+            .deaths
+            .iter()
+            .filter(|d| d.world == tables.world)
+            .filter_map(|d| d.seconds_since_last)
+            .max();
+        Ok((personal, server))
+    }
+
+    async fn kills_since_death(&self, player: &str) -> Result<i64> {
+        let last_death = self.last_death(player).await?;
+        let tables = self.tables();
+        Ok(tables
+            .deaths
+            .iter()
+            .filter(|d| d.is_pk && d.killer.as_deref() == Some(player) && d.world == tables.world)
+            .filter(|d| last_death.is_none_or(|last| d.create_date > last))
+            .count()
+            .try_into()
+            .unwrap_or(i64::MAX))
+    }
+
+    async fn recently_dead(&self, players: &[String], seconds: i64) -> Result<i64> {
+        let since = Utc::now() - TimeDelta::seconds(seconds);
+        let tables = self.tables();
+        let dead: BTreeSet<&str> = tables
+            .deaths
+            .iter()
+            .filter(|d| {
+                d.create_date > since && d.world == tables.world && players.contains(&d.victim)
+            })
+            .map(|d| d.victim.as_str())
+            .collect();
+        Ok(dead.len().try_into().unwrap_or(i64::MAX))
+    }
+
+    async fn deaths_between(
+        &self,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        world: Option<&str>,
+    ) -> Result<Vec<FightDeath>> {
+        Ok(self
+            .tables()
+            .deaths
+            .iter()
+            .filter(|d| d.create_date >= start && d.create_date <= end)
+            .filter(|d| in_world(d.world.as_ref(), world))
+            .map(|d| FightDeath {
+                victim: d.victim.clone(),
+                message: d.message.clone(),
+            })
+            .collect())
+    }
+
+    async fn start_fight(&self, boss: &str) -> Result<i64> {
+        let mut tables = self.tables();
+        let id = i64::try_from(tables.fights.len()).unwrap_or(i64::MAX) + 1;
+        let world = tables.world.clone();
+        tables.fights.push(Fight {
+            id,
+            boss: boss.to_string(),
+            start_date: Some(Utc::now()),
+            end_date: None,
+            defeated: false,
+            abandoned: false,
+            players: BTreeSet::new(),
+            world,
+        });
+        Ok(id)
+    }
+
+    async fn open_fights(&self, boss: Option<&str>) -> Result<Vec<(i64, DateTime<Utc>)>> {
+        let tables = self.tables();
+        let mut fights: Vec<(i64, DateTime<Utc>)> = tables
+            .fights
+            .iter()
+            .filter(|f| f.end_date.is_none() && f.world == tables.world)
+            .filter(|f| boss.is_none_or(|boss| f.boss == boss))
+            .filter_map(|f| f.start_date.map(|start| (f.id, start)))
+            .collect();
+        fights.sort_by(|a, b| b.1.cmp(&a.1).then(b.0.cmp(&a.0)));
+        Ok(fights)
+    }
+
+    async fn end_fight(&self, id: i64, defeated: bool) -> Result<EndedFight> {
+        let mut tables = self.tables();
+        let fight = tables
+            .fights
+            .iter_mut()
+            .find(|f| f.id == id)
+            .ok_or(sqlx::Error::RowNotFound)?;
+        let end_date = Utc::now();
+        fight.end_date = Some(end_date);
+        fight.defeated = defeated;
+        let boss = fight.boss.clone();
+        let world = fight.world.clone();
+        let start_date = fight.start_date.ok_or(sqlx::Error::RowNotFound)?;
+        let fights = || {
+            tables
+                .fights
+                .iter()
+                .filter(|f| f.boss == boss && f.world == world)
+        };
+        let last_defeat = fights()
+            .filter(|f| f.defeated && f.id != id)
+            .map(|f| f.id)
+            .max()
+            .unwrap_or_default();
+        let attempt = fights()
+            .filter(|f| f.id > last_defeat)
+            .filter(|f| f.start_date.is_some() && f.end_date.is_some() && !f.abandoned)
+            .count();
+        Ok(EndedFight {
+            boss,
+            start_date,
+            end_date,
+            attempt: attempt.try_into().unwrap_or(i64::MAX),
+            world,
+        })
+    }
+
+    async fn abandon_fight(&self, id: i64) -> Result<()> {
+        if let Some(fight) = self.tables().fights.iter_mut().find(|f| f.id == id) {
+            fight.end_date = Some(Utc::now());
+            fight.abandoned = true;
+        }
+        Ok(())
+    }
+
+    async fn insert_defeat(&self, boss: &str) -> Result<()> {
+        let mut tables = self.tables();
+        let id = i64::try_from(tables.fights.len()).unwrap_or(i64::MAX) + 1;
+        let world = tables.world.clone();
+        tables.fights.push(Fight {
+            id,
+            boss: boss.to_string(),
+            start_date: None,
+            end_date: Some(Utc::now()),
+            defeated: true,
+            abandoned: false,
+            players: BTreeSet::new(),
+            world,
+        });
+        Ok(())
+    }
+
+    async fn add_fight_players(&self, id: i64, players: &[String]) -> Result<()> {
+        if let Some(fight) = self.tables().fights.iter_mut().find(|f| f.id == id) {
+            fight.players.extend(players.iter().cloned());
+        }
+        Ok(())
+    }
+
+    async fn fight_players(&self, id: i64) -> Result<Vec<String>> {
+        Ok(self
+            .tables()
+            .fights
+            .iter()
+            .find(|f| f.id == id)
+            .map(|f| f.players.iter().cloned().collect())
+            .unwrap_or_default())
+    }
+
+    async fn start_invasion(&self, name: &str) -> Result<()> {
+        let mut tables = self.tables();
+        if !tables
+            .events
+            .iter()
+            .any(|e| e.name == name && e.end_date.is_none() && e.world == tables.world)
+        {
+            let world = tables.world.clone();
+            tables.events.push(Event {
+                name: name.to_string(),
+                is_invasion: true,
+                start_date: Utc::now(),
+                end_date: None,
+                world,
+            });
+        }
+        Ok(())
+    }
+
+    async fn end_invasion(&self, name: &str) -> Result<()> {
+        let mut tables = self.tables();
+        let world = tables.world.clone();
+        for event in &mut tables.events {
+            if event.name == name && event.end_date.is_none() && event.world == world {
+                event.end_date = Some(Utc::now());
+            }
+        }
+        Ok(())
+    }
+
+    async fn insert_world_event(&self, name: &str) -> Result<()> {
+        let mut tables = self.tables();
+        let world = tables.world.clone();
+        tables.events.push(Event {
+            name: name.to_string(),
+            is_invasion: false,
+            start_date: Utc::now(),
+            end_date: None,
+            world,
+        });
+        Ok(())
+    }
+
+    async fn insert_kill_milestone(&self, milestone: &NewKillMilestone<'_>) -> Result<()> {
+        let mut tables = self.tables();
+        let world = tables.world.clone();
+        tables.milestones.push(Milestone {
+            player: milestone.player.map(str::to_string),
+            count: milestone.count,
+            npc_name: milestone.npc_name.to_string(),
+            world,
+        });
+        Ok(())
+    }
+
+    async fn death_counts(&self, filter: &DeathFilter) -> Result<Vec<DeathCount>> {
+        let mut counts: BTreeMap<&str, i64> = BTreeMap::new();
+        let tables = self.tables();
+        for death in tables.deaths.iter().filter(|d| {
+            filter.since.is_none_or(|since| d.create_date >= since)
+                && (filter.killer.is_none() || d.killer == filter.killer)
+                && (!filter.pvp_only || d.is_pk)
+                && in_world(d.world.as_ref(), filter.world.as_deref())
+        }) {
+            *counts.entry(&death.victim).or_default() += 1;
+        }
+        let mut counts: Vec<DeathCount> = counts
+            .into_iter()
+            .map(|(victim, deaths)| DeathCount {
+                victim: victim.to_string(),
+                deaths,
+            })
+            .collect();
+        counts.sort_by_key(|count| Reverse(count.deaths));
+        Ok(counts)
+    }
+
+    async fn deaths_per(
+        &self,
+        group: DeathGroup,
+        since: Option<DateTime<Utc>>,
+        world: Option<&str>,
+        timezone: Tz,
+    ) -> Result<Vec<DeathsPer>> {
+        let mut counts: BTreeMap<String, i64> = BTreeMap::new();
+        for death in self.tables().deaths.iter().filter(|d| {
+            since.is_none_or(|since| d.create_date >= since) && in_world(d.world.as_ref(), world)
+        }) {
+            *counts
+                .entry(group.label(death.create_date, &death.victim, timezone))
+                .or_default() += 1;
+        }
+        Ok(counts
+            .into_iter()
+            .map(|(label, deaths)| DeathsPer { label, deaths })
+            .collect())
+    }
+
+    async fn pvp_players(&self, world: Option<&str>) -> Result<Vec<PvpPlayer>> {
+        let mut players: BTreeMap<&str, (i64, i64)> = BTreeMap::new();
+        let tables = self.tables();
+        for death in tables
+            .deaths
+            .iter()
+            .filter(|d| d.is_pk && in_world(d.world.as_ref(), world))
+        {
+            if let Some(killer) = &death.killer {
+                players.entry(killer).or_default().0 += 1;
+            }
+            players.entry(&death.victim).or_default().1 += 1;
+        }
+        let mut players: Vec<PvpPlayer> = players
+            .into_iter()
+            .map(|(player, (kills, deaths))| PvpPlayer {
+                player: player.to_string(),
+                kills,
+                deaths,
+            })
+            .collect();
+        players.sort_by(|a, b| b.kills.cmp(&a.kills).then(a.deaths.cmp(&b.deaths)));
+        Ok(players)
+    }
+
+    async fn pvp_weapons(&self, limit: i64, world: Option<&str>) -> Result<Vec<PvpWeapon>> {
+        let mut weapons: BTreeMap<&str, i64> = BTreeMap::new();
+        let tables = self.tables();
+        for death in tables
+            .deaths
+            .iter()
+            .filter(|d| d.is_pk && in_world(d.world.as_ref(), world))
+        {
+            if let Some(weapon) = &death.weapon {
+                *weapons.entry(weapon).or_default() += 1;
+            }
+        }
+        let mut weapons: Vec<PvpWeapon> = weapons
+            .into_iter()
+            .map(|(weapon, kills)| PvpWeapon {
+                weapon: weapon.to_string(),
+                kills,
+            })
+            .collect();
+        weapons.sort_by_key(|weapon| Reverse(weapon.kills));
+        weapons.truncate(usize::try_from(limit).unwrap_or(0));
+        Ok(weapons)
+    }
+
+    async fn pvp_nemeses(&self, world: Option<&str>) -> Result<Vec<Nemesis>> {
+        let mut kills: BTreeMap<(&str, &str), i64> = BTreeMap::new();
+        let tables = self.tables();
+        for death in tables
+            .deaths
+            .iter()
+            .filter(|d| d.is_pk && in_world(d.world.as_ref(), world))
+        {
+            if let Some(killer) = &death.killer {
+                *kills.entry((&death.victim, killer)).or_default() += 1;
+            }
+        }
+        let mut nemeses: BTreeMap<&str, Nemesis> = BTreeMap::new();
+        for ((victim, killer), kills) in kills {
+            // killers are visited alphabetically, so ties go to the first
+            if nemeses.get(victim).is_none_or(|n| kills > n.kills) {
+                nemeses.insert(
+                    victim,
+                    Nemesis {
+                        victim: victim.to_string(),
+                        killer: killer.to_string(),
+                        kills,
+                    },
+                );
+            }
+        }
+        Ok(nemeses.into_values().collect())
+    }
+
+    async fn survival_records(
+        &self,
+        before: Option<DateTime<Utc>>,
+        world: Option<&str>,
+    ) -> Result<Vec<SurvivalRecord>> {
+        let mut records: BTreeMap<&str, (i32, i32)> = BTreeMap::new();
+        let tables = self.tables();
+        for death in tables.deaths.iter().filter(|d| {
+            before.is_none_or(|before| d.create_date < before) && in_world(d.world.as_ref(), world)
+        }) {
+            if let Some(seconds) = death.seconds_since_last {
+                let record = records.entry(&death.victim).or_insert((seconds, seconds));
+                record.0 = record.0.max(seconds);
+                record.1 = record.1.min(seconds);
+            }
+        }
+        let mut records: Vec<SurvivalRecord> = records
+            .into_iter()
+            .map(|(victim, (longest, fastest))| SurvivalRecord {
+                victim: victim.to_string(),
+                longest,
+                fastest,
+            })
+            .collect();
+        records.sort_by_key(|record| Reverse(record.longest));
+        Ok(records)
+    }
+
+    async fn death_causes(
+        &self,
+        before: DateTime<Utc>,
+        world: Option<&str>,
+    ) -> Result<Vec<DeathCause>> {
+        let mut causes: BTreeMap<String, i64> = BTreeMap::new();
+        for death in self
+            .tables()
+            .deaths
+            .iter()
+            .filter(|d| d.create_date < before && in_world(d.world.as_ref(), world))
+        {
+            *causes
+                .entry(death.message.replacen(&death.victim, "", 1))
+                .or_default() += 1;
+        }
+        Ok(causes
+            .into_iter()
+            .map(|(cause, deaths)| DeathCause { cause, deaths })
+            .collect())
+    }
+
+    async fn boss_summaries(&self, world: Option<&str>) -> Result<Vec<BossSummary>> {
+        let mut fights: BTreeMap<&str, Vec<&Fight>> = BTreeMap::new();
+        let tables = self.tables();
+        for fight in tables
+            .fights
+            .iter()
+            .filter(|f| in_world(f.world.as_ref(), world))
+        {
+            fights.entry(&fight.boss).or_default().push(fight);
+        }
+        let mut summaries: Vec<BossSummary> = fights
+            .into_iter()
+            .map(|(boss, fights)| {
+                let defeats = fights.iter().filter(|f| f.defeated);
+                let first_kill = defeats.clone().filter_map(|f| f.end_date).min();
+                BossSummary {
+                    boss: boss.to_string(),
+                    first_kill,
+                    kills: defeats.clone().count().try_into().unwrap_or(i64::MAX),
+                    attempts: fights
+                        .iter()
+                        .filter(|f| {
+                            f.start_date.is_some()
+                                && !f.abandoned
+                                && f.end_date.is_some_and(|end| {
+                                    first_kill.is_none_or(|first_kill| end <= first_kill)
+                                })
+                        })
+                        .count()
+                        .try_into()
+                        .unwrap_or(i64::MAX),
+                    fastest: defeats
+                        .filter_map(|f| Some(seconds(f.end_date? - f.start_date?)))
+                        .min(),
+                }
+            })
+            .collect();
+        summaries.sort_by_key(|s| (s.first_kill.is_none(), s.first_kill));
+        Ok(summaries)
+    }
+
+    async fn bosses_defeated(
+        &self,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        world: Option<&str>,
+    ) -> Result<Vec<String>> {
+        let tables = self.tables();
+        let mut defeated: Vec<(DateTime<Utc>, &str)> = tables
+            .fights
+            .iter()
+            .filter(|f| f.defeated && in_world(f.world.as_ref(), world))
+            .filter_map(|f| f.end_date.map(|end| (end, f.boss.as_str())))
+            .filter(|(date, _)| *date >= start && *date < end)
+            .collect();
+        defeated.sort_by_key(|(date, _)| *date);
+        Ok(defeated
+            .into_iter()
+            .map(|(_, boss)| boss.to_string())
+            .collect())
+    }
+
+    async fn world_events(&self, limit: i64, world: Option<&str>) -> Result<Vec<WorldEvent>> {
+        let tables = self.tables();
+        let mut events: Vec<WorldEvent> = tables
+            .events
+            .iter()
+            .rev()
+            .filter(|e| in_world(e.world.as_ref(), world))
+            .map(|e| WorldEvent {
+                name: e.name.clone(),
+                is_invasion: e.is_invasion,
+                start_date: e.start_date,
+                duration: e.end_date.map(|end| seconds(end - e.start_date)),
+            })
+            .collect();
+        events.sort_by_key(|event| Reverse(event.start_date));
+        events.truncate(usize::try_from(limit).unwrap_or(0));
+        Ok(events)
+    }
+
+    async fn kill_milestones(
+        &self,
+        player: Option<&str>,
+        world: Option<&str>,
+    ) -> Result<Vec<KillMilestone>> {
+        let mut counts: BTreeMap<(&str, Option<&str>), i32> = BTreeMap::new();
+        let tables = self.tables();
+        for milestone in tables.milestones.iter().filter(|m| {
+            (player.is_none() || m.player.as_deref() == player) && in_world(m.world.as_ref(), world)
+        }) {
+            let count = counts
+                .entry((&milestone.npc_name, milestone.player.as_deref()))
+                .or_default();
+            *count = (*count).max(milestone.count);
+        }
+        let mut milestones: Vec<KillMilestone> = counts
+            .into_iter()
+            .map(|((npc_name, player), count)| KillMilestone {
+                npc_name: npc_name.to_string(),
+                player: player.unwrap_or("Someone").to_string(),
+                count,
+            })
+            .collect();
+        milestones.sort_by(|a, b| {
+            a.npc_name
+                .cmp(&b.npc_name)
+                .then(b.count.cmp(&a.count))
+                .then(a.player.cmp(&b.player))
+        });
+        Ok(milestones)
+    }
+
+    async fn search_messages(
+        &self,
+        text: &str,
+        player: Option<&str>,
+        since: Option<DateTime<Utc>>,
+        world: Option<&str>,
+        limit: i64,
+    ) -> Result<Vec<Message>> {
+        let words: Vec<String> = text.split_whitespace().map(str::to_lowercase).collect();
+        let tables = self.tables();
+        let mut messages: Vec<Message> = tables
+            .messages
+            .iter()
+            .rev()
+            .filter(|m| {
+                let content = m.content.to_lowercase();
+                words.iter().all(|word| content.contains(word))
+                    && player.is_none_or(|player| m.author == player)
+                    && since.is_none_or(|since| m.create_date >= since)
+                    && in_world(m.world.as_ref(), world)
+            })
+            .map(|m| Message {
+                author: m.author.clone(),
+                content: m.content.clone(),
+                create_date: m.create_date,
+            })
+            .collect();
+        messages.sort_by_key(|message| Reverse(message.create_date));
+        messages.truncate(usize::try_from(limit).unwrap_or(0));
+        Ok(messages)
+    }
+
+    // not random, the latest message so tests are repeatable
+    async fn random_message(&self, player: &str, world: Option<&str>) -> Result<Option<Message>> {
+        let tables = self.tables();
+        let messages: Vec<_> = tables
+            .messages
+            .iter()
+            .filter(|m| m.author == player && in_world(m.world.as_ref(), world))
+            .collect();
+        // a fresh RandomState is randomly keyed, which is random enough to pick a quote
+        let random = RandomState::new().build_hasher().finish();
+        let index = usize::try_from(random % messages.len().max(1) as u64).unwrap_or_default();
+        Ok(messages.get(index).map(|m| Message {
+            author: m.author.clone(),
+            content: m.content.clone(),
+            create_date: m.create_date,
+        }))
+    }
+
+    async fn prune(&self, table: PrunedTable, before: DateTime<Utc>) -> Result<u64> {
+        let mut tables = self.tables();
+        let count = |before_len: usize, after_len: usize| (before_len - after_len) as u64;
+        Ok(match table {
+            PrunedTable::Message => {
+                let len = tables.messages.len();
+                tables.messages.retain(|m| m.create_date >= before);
+                count(len, tables.messages.len())
+            }
+            PrunedTable::Death => {
+                let len = tables.deaths.len();
+                tables.deaths.retain(|d| d.create_date >= before);
+                count(len, tables.deaths.len())
+            }
+            PrunedTable::ServerJoin | PrunedTable::ServerLeave => {
+                let joined = table == PrunedTable::ServerJoin;
+                let len = tables.statuses.len();
+                tables
+                    .statuses
+                    .retain(|s| s.joined != joined || s.create_date >= before);
+                count(len, tables.statuses.len())
+            }
+        })
+    }
+
+    // there's no audit trail here
+    async fn forget_player(&self, player: &str, _requested_by: &str) -> Result<Erasure> {
+        let mut tables = self.tables();
+        let mut erasure = Erasure::default();
+
+        let len = tables.messages.len();
+        tables.messages.retain(|m| m.author != player);
+        erasure.messages = (len - tables.messages.len()) as u64;
+        let len = tables.deaths.len();
+        tables.deaths.retain(|d| d.victim != player);
+        erasure.deaths = (len - tables.deaths.len()) as u64;
+        let len = tables.statuses.len();
+        tables.statuses.retain(|s| s.username != player);
+        erasure.sessions = (len - tables.statuses.len()) as u64;
+
+        for death in &mut tables.deaths {
+            if death.killer.as_deref() == Some(player) {
+                death.killer = Some(FORGOTTEN.to_string());
+                death.message = death
+                    .message
+                    .replace(&format!("**{player}**"), &format!("**{FORGOTTEN}**"));
+                erasure.kills += 1;
+            }
+        }
+        for milestone in &mut tables.milestones {
+            if milestone.player.as_deref() == Some(player) {
+                milestone.player = Some(FORGOTTEN.to_string());
+            }
+        }
+        for fight in &mut tables.fights {
+            if fight.players.remove(player) {
+                fight.players.insert(FORGOTTEN.to_string());
+            }
+        }
+        let len = tables.outbox.len();
+        tables
+            .outbox
+            .retain(|_, queued| queued.player.as_deref() != Some(player));
+        erasure.queued = (len - tables.outbox.len()) as u64;
+        Ok(erasure)
+    }
+
+    async fn import_log(
+        &self,
+        fingerprint: &str,
+        hashes: &[String],
+        imported: &[ImportedLine<'_>],
+    ) -> Result<u64> {
+        let mut tables = self.tables();
+        let imports: Vec<LogImport> = tables
+            .log_imports
+            .values()
+            .filter(|(import_fingerprint, _)| import_fingerprint == fingerprint)
+            .map(|(_, import)| LogImport {
+                id: import.id,
+                lines: import.lines,
+                hash: import.hash.clone(),
+            })
+            .collect();
+        let earlier = continued_import(&imports, hashes);
+        let already = earlier.map_or(0, |import| import.lines);
+        let mut inserted = 0;
+        let world = tables.world.clone();
+        for line in imported.iter().filter(|line| line.line >= already) {
+            match line.event {
+                ImportedEvent::Message { author, content } => tables.messages.push(Chat {
+                    author: author.to_string(),
+                    content: content.to_string(),
+                    create_date: line.create_date,
+                    world: world.clone(),
+                }),
+                ImportedEvent::Join(username) | ImportedEvent::Leave(username) => {
+                    tables.statuses.push(Status {
+                        username: username.to_string(),
+                        joined: matches!(line.event, ImportedEvent::Join(_)),
+                        create_date: line.create_date,
+                        world: world.clone(),
+                    });
+                }
+            }
+            inserted += 1;
+        }
+        let id = match earlier {
+            Some(import) => import.id,
+            None => tables
+                .log_imports
+                .last_key_value()
+                .map_or(1, |(id, _)| id + 1),
+        };
+        let import = LogImport {
+            id,
+            lines: i64::try_from(hashes.len()).unwrap_or(i64::MAX),
+            hash: hashes.last().cloned(),
+        };
+        tables
+            .log_imports
+            .insert(id, (fingerprint.to_string(), import));
+        Ok(inserted)
+    }
+
+    async fn queue_outgoing(
+        &self,
+        channel_id: i64,
+        player: Option<&str>,
+        content: &str,
+    ) -> Result<()> {
+        let mut tables = self.tables();
+        #[cfg(test)]
+        if tables.outbox_unavailable {
+            return Err(sqlx::Error::PoolTimedOut);
+        }
+        let id = tables.outbox.last_key_value().map_or(1, |(id, _)| id + 1);
+        tables.outbox.insert(
+            id,
+            Queued {
+                channel_id,
+                player: player.map(str::to_string),
+                content: content.to_string(),
+            },
+        );
+        Ok(())
+    }
+
+    async fn next_outgoing(&self) -> Result<Option<Outgoing>> {
+        let tables = self.tables();
+        #[cfg(test)]
+        if tables.outbox_unavailable {
+            return Err(sqlx::Error::PoolTimedOut);
+        }
+        Ok(tables
+            .outbox
+            .first_key_value()
+            .map(|(id, queued)| Outgoing {
+                id: *id,
+                channel_id: queued.channel_id,
+                content: queued.content.clone(),
+            }))
+    }
+
+    async fn remove_outgoing(&self, id: i64) -> Result<()> {
+        self.tables().outbox.remove(&id);
+        Ok(())
+    }
+
+    fn export_deaths<'a>(
+        &'a self,
+        since: Option<DateTime<Utc>>,
+        world: Option<&'a str>,
+    ) -> BoxStream<'a, Result<super::Death>> {
+        let deaths: Vec<Result<super::Death>> = self
+            .tables()
+            .deaths
+            .iter()
+            .filter(|d| since.is_none_or(|since| d.create_date >= since))
+            .filter(|d| in_world(d.world.as_ref(), world))
+            .map(|d| {
+                Ok(super::Death {
+                    create_date: d.create_date,
+                    victim: d.victim.clone(),
+                    killer: d.killer.clone(),
+                    weapon: d.weapon.clone(),
+                    message: d.message.clone(),
+                    seconds_since_last: d.seconds_since_last,
+                    is_pk: d.is_pk,
+                    world: d.world.clone(),
+                })
+            })
+            .collect();
+        stream::iter(deaths).boxed()
+    }
+
+    fn export_messages<'a>(
+        &'a self,
+        since: Option<DateTime<Utc>>,
+        world: Option<&'a str>,
+    ) -> BoxStream<'a, Result<Message>> {
+        let messages: Vec<Result<Message>> = self
+            .tables()
+            .messages
+            .iter()
+            .filter(|m| since.is_none_or(|since| m.create_date >= since))
+            .filter(|m| in_world(m.world.as_ref(), world))
+            .map(|m| {
+                Ok(Message {
+                    author: m.author.clone(),
+                    content: m.content.clone(),
+                    create_date: m.create_date,
+                })
+            })
+            .collect();
+        stream::iter(messages).boxed()
+    }
+
+    fn export_sessions<'a>(
+        &'a self,
+        since: Option<DateTime<Utc>>,
+        world: Option<&'a str>,
+    ) -> BoxStream<'a, Result<Session>> {
+        let tables = self.tables();
+        let sessions: Vec<Result<Session>> = tables
+            .statuses
+            .iter()
+            .enumerate()
+            .filter(|(_, s)| s.joined && in_world(s.world.as_ref(), world))
+            .map(|(i, join)| Session {
+                username: join.username.clone(),
+                joined: join.create_date,
+                left: tables.statuses[i..]
+                    .iter()
+                    .find(|s| !s.joined && s.username == join.username)
+                    .map(|leave| leave.create_date),
+            })
+            .filter(|s| since.is_none_or(|since| s.left.is_none_or(|left| left >= since)))
+            .map(Ok)
+            .collect();
+        stream::iter(sessions).boxed()
+    }
+}
@@ -0,0 +1,795 @@
+use super::{
+    BossSummary, Death, DeathCause, DeathCount, DeathFilter, DeathGroup, DeathsPer, EndedFight,
+    Erasure, FORGOTTEN, FightDeath, ImportedEvent, ImportedLine, KillMilestone, LogImport, Message,
+    Nemesis, NewDeath, NewKillMilestone, Outgoing, PrunedTable, PvpPlayer, PvpWeapon, Result,
+    Session, Storage, SurvivalRecord, WorldEvent, continued_import, search_patterns,
+};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use futures::stream::BoxStream;
+use sqlx::{Pool, Postgres};
+
+pub struct PostgresStorage {
+    db: Pool<Postgres>,
+}
+
+impl PostgresStorage {
+    pub fn new(db: Pool<Postgres>) -> Self {
+        Self { db }
+    }
+}
+
+#[async_trait]
+#[allow(clippy::panic)]
+impl Storage for PostgresStorage {
+    async fn migrate(&self) -> std::result::Result<(), sqlx::migrate::MigrateError> {
+        sqlx::migrate!("migrations/postgres").run(&self.db).await
+    }
+
+    async fn set_world(&self, name: &str) -> Result<()> {
+        let mut tx = self.db.begin().await?;
+        sqlx::query!(
+            r#"UPDATE world SET is_current = false WHERE is_current AND name <> $1"#,
+            name
+        )
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query!(
+            r#"INSERT INTO world(name, is_current) VALUES ($1, true) ON CONFLICT (name) DO UPDATE SET is_current = true"#,
+            name
+        )
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await
+    }
+
+    async fn current_world(&self) -> Result<Option<String>> {
+        sqlx::query_scalar!(r#"SELECT name FROM world WHERE is_current"#)
+            .fetch_optional(&self.db)
+            .await
+    }
+
+    async fn insert_message(&self, author: &str, content: &str) -> Result<()> {
+        sqlx::query!(
+            r#"INSERT INTO message(author, content, world_id) VALUES ($1, $2, (SELECT id FROM world WHERE is_current))"#,
+            author,
+            content
+        )
+        .execute(&self.db)
+        .await
+        .map(|_| ())
+    }
+
+    async fn insert_join(&self, username: &str) -> Result<()> {
+        sqlx::query!(
+            r#"INSERT INTO server_join(username, world_id) VALUES ($1, (SELECT id FROM world WHERE is_current))"#,
+            username
+        )
+        .execute(&self.db)
+        .await
+        .map(|_| ())
+    }
+
+    async fn insert_leave(&self, username: &str) -> Result<()> {
+        sqlx::query!(
+            r#"INSERT INTO server_leave(username, world_id) VALUES ($1, (SELECT id FROM world WHERE is_current))"#,
+            username
+        )
+        .execute(&self.db)
+        .await
+        .map(|_| ())
+    }
+
+    async fn online_players(&self) -> Result<Vec<String>> {
+        sqlx::query_scalar!(
+            r#"SELECT DISTINCT username FROM server_join j
+WHERE create_date > coalesce((SELECT max(create_date) FROM server_leave l WHERE l.username = j.username), '-infinity')
+ORDER BY username"#
+        )
+        .fetch_all(&self.db)
+        .await
+    }
+
+    async fn insert_death(&self, death: &NewDeath<'_>) -> Result<()> {
+        sqlx::query!(
+            r#"INSERT INTO death(victim, killer, weapon, message, seconds_since_last, is_pk, world_id)
+VALUES ($1, $2, $3, $4, $5, $6, (SELECT id FROM world WHERE is_current))"#,
+            death.victim,
+            death.killer,
+            death.weapon,
+            death.message,
+            death.seconds_since_last,
+            death.is_pk
+        )
+        .execute(&self.db)
+        .await
+        .map(|_| ())
+    }
+
+    async fn last_death(&self, victim: &str) -> Result<Option<DateTime<Utc>>> {
+        sqlx::query_scalar!(
+            r#"SELECT max(create_date) as last_date FROM death
+WHERE victim = $1 AND world_id IS NOT DISTINCT FROM (SELECT id FROM world WHERE is_current)"#,
+            victim
+        )
+        .fetch_one(&self.db)
+        .await
+    }
+
+    async fn longest_survivals(&self, victim: &str) -> Result<(Option<i32>, Option<i32>)> {
+        sqlx::query!(
+            r#"SELECT max(seconds_since_last) FILTER (WHERE victim = $1) AS personal, max(seconds_since_last) AS server FROM death
+WHERE world_id IS NOT DISTINCT FROM (SELECT id FROM world WHERE is_current)"#,
+            victim
+        )
+        .fetch_one(&self.db)
+        .await
+        .map(|r| (r.personal, r.server))
+    }
+
+    async fn kills_since_death(&self, player: &str) -> Result<i64> {
+        sqlx::query_scalar!(
+            r#"SELECT count(*) AS "kills!" FROM death
+WHERE is_pk AND killer = $1 AND world_id IS NOT DISTINCT FROM (SELECT id FROM world WHERE is_current)
+AND create_date > coalesce((SELECT max(create_date) FROM death WHERE victim = $1), '-infinity')"#,
+            player
+        )
+        .fetch_one(&self.db)
+        .await
+    }
+
+    async fn recently_dead(&self, players: &[String], seconds: i64) -> Result<i64> {
+        sqlx::query_scalar!(
+            r#"SELECT count(DISTINCT victim) AS "dead!" FROM death WHERE victim = ANY($1) AND create_date > now() - make_interval(secs => $2::int8)
+AND world_id IS NOT DISTINCT FROM (SELECT id FROM world WHERE is_current)"#,
+            players,
+            seconds
+        )
+        .fetch_one(&self.db)
+        .await
+    }
+
+    async fn deaths_between(
+        &self,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        world: Option<&str>,
+    ) -> Result<Vec<FightDeath>> {
+        sqlx::query_as!(
+            FightDeath,
+            r#"SELECT victim, message FROM death WHERE create_date BETWEEN $1 AND $2
+AND ($3::varchar IS NULL OR world_id = (SELECT id FROM world WHERE name = $3))
+ORDER BY create_date"#,
+            start,
+            end,
+            world
+        )
+        .fetch_all(&self.db)
+        .await
+    }
+
+    async fn start_fight(&self, boss: &str) -> Result<i64> {
+        sqlx::query_scalar!(
+            r#"INSERT INTO boss_fight(boss, world_id) VALUES ($1, (SELECT id FROM world WHERE is_current)) RETURNING id"#,
+            boss
+        )
+        .fetch_one(&self.db)
+        .await
+    }
+
+    async fn open_fights(&self, boss: Option<&str>) -> Result<Vec<(i64, DateTime<Utc>)>> {
+        sqlx::query!(
+            r#"SELECT id, start_date AS "start_date!" FROM boss_fight
+WHERE end_date IS NULL AND start_date IS NOT NULL AND ($1::varchar IS NULL OR boss = $1)
+AND world_id IS NOT DISTINCT FROM (SELECT id FROM world WHERE is_current)
+ORDER BY start_date DESC"#,
+            boss
+        )
+        .fetch_all(&self.db)
+        .await
+        .map(|rows| rows.into_iter().map(|r| (r.id, r.start_date)).collect())
+    }
+
+    async fn end_fight(&self, id: i64, defeated: bool) -> Result<EndedFight> {
+        sqlx::query_as!(
+            EndedFight,
+            r#"UPDATE boss_fight SET end_date = now(), defeated = $2 WHERE id = $1
+RETURNING boss, start_date AS "start_date!", end_date AS "end_date!",
+(SELECT count(*) FROM boss_fight b WHERE b.boss = boss_fight.boss AND b.end_date IS NOT NULL AND b.start_date IS NOT NULL
+AND NOT b.abandoned AND b.world_id IS NOT DISTINCT FROM boss_fight.world_id
+AND NOT EXISTS (SELECT FROM boss_fight d WHERE d.boss = b.boss AND d.defeated AND d.id >= b.id
+AND d.world_id IS NOT DISTINCT FROM b.world_id)) + 1 AS "attempt!",
+(SELECT name FROM world WHERE id = boss_fight.world_id) AS world"#,
+            id,
+            defeated
+        )
+        .fetch_one(&self.db)
+        .await
+    }
+
+    async fn abandon_fight(&self, id: i64) -> Result<()> {
+        sqlx::query!(
+            r#"UPDATE boss_fight SET end_date = now(), abandoned = true WHERE id = $1"#,
+            id
+        )
+        .execute(&self.db)
+        .await
+        .map(|_| ())
+    }
+
+    async fn insert_defeat(&self, boss: &str) -> Result<()> {
+        sqlx::query!(
+            r#"INSERT INTO boss_fight(boss, start_date, end_date, defeated, world_id)
+VALUES ($1, NULL, now(), true, (SELECT id FROM world WHERE is_current))"#,
+            boss
+        )
+        .execute(&self.db)
+        .await
+        .map(|_| ())
+    }
+
+    async fn add_fight_players(&self, id: i64, players: &[String]) -> Result<()> {
+        sqlx::query!(
+            r#"INSERT INTO boss_fight_player(boss_fight_id, username) SELECT $1, unnest($2::varchar[]) ON CONFLICT DO NOTHING"#,
+            id,
+            players
+        )
+        .execute(&self.db)
+        .await
+        .map(|_| ())
+    }
+
+    async fn fight_players(&self, id: i64) -> Result<Vec<String>> {
+        sqlx::query_scalar!(
+            r#"SELECT username FROM boss_fight_player WHERE boss_fight_id = $1 ORDER BY username"#,
+            id
+        )
+        .fetch_all(&self.db)
+        .await
+    }
+
+    async fn start_invasion(&self, name: &str) -> Result<()> {
+        sqlx::query!(
+            r#"INSERT INTO world_event(name, is_invasion, world_id)
+SELECT $1::varchar, true, (SELECT id FROM world WHERE is_current) WHERE NOT EXISTS (SELECT 1 FROM world_event WHERE name = $1 AND end_date IS NULL
+AND world_id IS NOT DISTINCT FROM (SELECT id FROM world WHERE is_current))"#,
+            name
+        )
+        .execute(&self.db)
+        .await
+        .map(|_| ())
+    }
+
+    async fn end_invasion(&self, name: &str) -> Result<()> {
+        sqlx::query!(
+            r#"UPDATE world_event SET end_date = now() WHERE name = $1 AND end_date IS NULL
+AND world_id IS NOT DISTINCT FROM (SELECT id FROM world WHERE is_current)"#,
+            name
+        )
+        .execute(&self.db)
+        .await
+        .map(|_| ())
+    }
+
+    async fn insert_world_event(&self, name: &str) -> Result<()> {
+        sqlx::query!(
+            r#"INSERT INTO world_event(name, is_invasion, world_id) VALUES ($1, false, (SELECT id FROM world WHERE is_current))"#,
+            name
+        )
+        .execute(&self.db)
+        .await
+        .map(|_| ())
+    }
+
+    async fn insert_kill_milestone(&self, milestone: &NewKillMilestone<'_>) -> Result<()> {
+        sqlx::query!(
+            r#"INSERT INTO kill_milestone(player, count, npc_key, npc_name, world_id)
+VALUES ($1, $2, $3, $4, (SELECT id FROM world WHERE is_current))"#,
+            milestone.player,
+            milestone.count,
+            milestone.npc_key,
+            milestone.npc_name
+        )
+        .execute(&self.db)
+        .await
+        .map(|_| ())
+    }
+
+    async fn death_counts(&self, filter: &DeathFilter) -> Result<Vec<DeathCount>> {
+        sqlx::query_as!(
+            DeathCount,
+            r#"SELECT victim, count(*) AS "deaths!" FROM death
+WHERE ($1::timestamptz IS NULL OR create_date >= $1)
+AND ($2::varchar IS NULL OR killer = $2)
+AND (NOT $3 OR is_pk)
+AND ($4::varchar IS NULL OR world_id = (SELECT id FROM world WHERE name = $4))
+GROUP BY victim
+ORDER BY count(*) DESC, victim"#,
+            filter.since,
+            filter.killer,
+            filter.pvp_only,
+            filter.world
+        )
+        .fetch_all(&self.db)
+        .await
+    }
+
+    async fn deaths_per(
+        &self,
+        group: DeathGroup,
+        since: Option<DateTime<Utc>>,
+        world: Option<&str>,
+        timezone: Tz,
+    ) -> Result<Vec<DeathsPer>> {
+        sqlx::query_as!(
+            DeathsPer,
+            r#"SELECT CASE $1::text WHEN 'day' THEN to_char(local, 'YYYY-MM-DD') WHEN 'hour' THEN to_char(local, 'HH24') ELSE victim END AS "label!",
+count(*) AS "deaths!"
+FROM (SELECT victim, create_date AT TIME ZONE $4::text AS local FROM death
+WHERE ($2::timestamptz IS NULL OR create_date >= $2)
+AND ($3::varchar IS NULL OR world_id = (SELECT id FROM world WHERE name = $3))) d
+GROUP BY 1
+ORDER BY 1"#,
+            group.name(),
+            since,
+            world,
+            timezone.name()
+        )
+        .fetch_all(&self.db)
+        .await
+    }
+
+    async fn pvp_players(&self, world: Option<&str>) -> Result<Vec<PvpPlayer>> {
+        sqlx::query_as!(
+            PvpPlayer,
+            r#"WITH pk AS (SELECT killer, victim FROM death WHERE is_pk AND ($1::varchar IS NULL OR world_id = (SELECT id FROM world WHERE name = $1))),
+kills AS (SELECT killer AS player, count(*) AS kills FROM pk WHERE killer IS NOT NULL GROUP BY killer),
+deaths AS (SELECT victim AS player, count(*) AS deaths FROM pk GROUP BY victim)
+SELECT coalesce(kills.player, deaths.player) AS "player!", coalesce(kills.kills, 0) AS "kills!", coalesce(deaths.deaths, 0) AS "deaths!"
+FROM kills FULL OUTER JOIN deaths ON kills.player = deaths.player
+ORDER BY 2 DESC, 3, 1"#,
+            world
+        )
+        .fetch_all(&self.db)
+        .await
+    }
+
+    async fn pvp_weapons(&self, limit: i64, world: Option<&str>) -> Result<Vec<PvpWeapon>> {
+        sqlx::query_as!(
+            PvpWeapon,
+            r#"SELECT weapon AS "weapon!", count(*) AS "kills!" FROM death
+WHERE is_pk AND weapon IS NOT NULL AND ($2::varchar IS NULL OR world_id = (SELECT id FROM world WHERE name = $2))
+GROUP BY weapon
+ORDER BY 2 DESC, 1
+LIMIT $1"#,
+            limit,
+            world
+        )
+        .fetch_all(&self.db)
+        .await
+    }
+
+    async fn pvp_nemeses(&self, world: Option<&str>) -> Result<Vec<Nemesis>> {
+        sqlx::query_as!(
+            Nemesis,
+            r#"SELECT DISTINCT ON (victim) victim, killer AS "killer!", count(*) AS "kills!" FROM death
+WHERE is_pk AND killer IS NOT NULL AND ($1::varchar IS NULL OR world_id = (SELECT id FROM world WHERE name = $1))
+GROUP BY victim, killer
+ORDER BY victim, 3 DESC, 2"#,
+            world
+        )
+        .fetch_all(&self.db)
+        .await
+    }
+
+    async fn survival_records(
+        &self,
+        before: Option<DateTime<Utc>>,
+        world: Option<&str>,
+    ) -> Result<Vec<SurvivalRecord>> {
+        sqlx::query_as!(
+            SurvivalRecord,
+            r#"SELECT victim, max(seconds_since_last) AS "longest!", min(seconds_since_last) AS "fastest!" FROM death
+WHERE seconds_since_last IS NOT NULL AND ($1::timestamptz IS NULL OR create_date < $1)
+AND ($2::varchar IS NULL OR world_id = (SELECT id FROM world WHERE name = $2))
+GROUP BY victim
+ORDER BY 2 DESC, 1"#,
+            before,
+            world
+        )
+        .fetch_all(&self.db)
+        .await
+    }
+
+    async fn death_causes(
+        &self,
+        before: DateTime<Utc>,
+        world: Option<&str>,
+    ) -> Result<Vec<DeathCause>> {
+        sqlx::query_as!(
+            DeathCause,
+            r#"SELECT cause AS "cause!", count(*) AS "deaths!" FROM (SELECT CASE WHEN strpos(message, victim) > 0
+THEN left(message, strpos(message, victim) - 1) || substr(message, strpos(message, victim) + length(victim))
+ELSE message END AS cause FROM death
+WHERE create_date < $1 AND ($2::varchar IS NULL OR world_id = (SELECT id FROM world WHERE name = $2))) d
+GROUP BY cause"#,
+            before,
+            world
+        )
+        .fetch_all(&self.db)
+        .await
+    }
+
+    async fn boss_summaries(&self, world: Option<&str>) -> Result<Vec<BossSummary>> {
+        sqlx::query_as!(
+            BossSummary,
+            r#"WITH fight AS (SELECT * FROM boss_fight WHERE ($1::varchar IS NULL OR world_id = (SELECT id FROM world WHERE name = $1))),
+first AS (SELECT boss, min(end_date) FILTER (WHERE defeated) AS first_kill FROM fight GROUP BY boss)
+SELECT boss, first_kill, count(*) FILTER (WHERE defeated) AS "kills!",
+count(*) FILTER (WHERE start_date IS NOT NULL AND NOT abandoned AND end_date <= coalesce(first_kill, 'infinity')) AS "attempts!",
+(min(extract(epoch FROM end_date - start_date)) FILTER (WHERE defeated))::int4 AS fastest
+FROM fight JOIN first USING (boss)
+GROUP BY boss, first_kill
+ORDER BY 2 NULLS LAST, 1"#,
+            world
+        )
+        .fetch_all(&self.db)
+        .await
+    }
+
+    async fn bosses_defeated(
+        &self,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        world: Option<&str>,
+    ) -> Result<Vec<String>> {
+        sqlx::query_scalar!(
+            r#"SELECT boss FROM boss_fight WHERE defeated AND end_date >= $1 AND end_date < $2
+AND ($3::varchar IS NULL OR world_id = (SELECT id FROM world WHERE name = $3))
+ORDER BY end_date"#,
+            start,
+            end,
+            world
+        )
+        .fetch_all(&self.db)
+        .await
+    }
+
+    async fn world_events(&self, limit: i64, world: Option<&str>) -> Result<Vec<WorldEvent>> {
+        sqlx::query_as!(
+            WorldEvent,
+            r#"SELECT name, is_invasion, start_date, extract(epoch FROM end_date - start_date)::int4 AS duration
+FROM world_event
+WHERE ($2::varchar IS NULL OR world_id = (SELECT id FROM world WHERE name = $2))
+ORDER BY start_date DESC
+LIMIT $1"#,
+            limit,
+            world
+        )
+        .fetch_all(&self.db)
+        .await
+    }
+
+    async fn kill_milestones(
+        &self,
+        player: Option<&str>,
+        world: Option<&str>,
+    ) -> Result<Vec<KillMilestone>> {
+        sqlx::query_as!(
+            KillMilestone,
+            r#"SELECT npc_name, coalesce(player, 'Someone') AS "player!", max(count) AS "count!" FROM kill_milestone
+WHERE ($1::varchar IS NULL OR player = $1) AND ($2::varchar IS NULL OR world_id = (SELECT id FROM world WHERE name = $2))
+GROUP BY npc_name, player
+ORDER BY npc_name, 3 DESC, 2"#,
+            player,
+            world
+        )
+        .fetch_all(&self.db)
+        .await
+    }
+
+    async fn search_messages(
+        &self,
+        text: &str,
+        player: Option<&str>,
+        since: Option<DateTime<Utc>>,
+        world: Option<&str>,
+        limit: i64,
+    ) -> Result<Vec<Message>> {
+        sqlx::query_as!(
+            Message,
+            r#"SELECT author, content, create_date FROM message
+WHERE NOT EXISTS (SELECT FROM unnest($1::text[]) AS pattern WHERE content NOT ILIKE pattern ESCAPE '\')
+AND ($2::varchar IS NULL OR author = $2)
+AND ($3::timestamptz IS NULL OR create_date >= $3)
+AND ($4::varchar IS NULL OR world_id = (SELECT id FROM world WHERE name = $4))
+ORDER BY create_date DESC
+LIMIT $5"#,
+            &search_patterns(text),
+            player,
+            since,
+            world,
+            limit
+        )
+        .fetch_all(&self.db)
+        .await
+    }
+
+    async fn random_message(&self, player: &str, world: Option<&str>) -> Result<Option<Message>> {
+        sqlx::query_as!(
+            Message,
+            r#"SELECT author, content, create_date FROM message WHERE author = $1
+AND ($2::varchar IS NULL OR world_id = (SELECT id FROM world WHERE name = $2))
+ORDER BY random() LIMIT 1"#,
+            player,
+            world
+        )
+        .fetch_optional(&self.db)
+        .await
+    }
+
+    async fn prune(&self, table: PrunedTable, before: DateTime<Utc>) -> Result<u64> {
+        let pruned = match table {
+            PrunedTable::Message => {
+                sqlx::query!(r#"DELETE FROM message WHERE create_date < $1"#, before)
+                    .execute(&self.db)
+                    .await?
+            }
+            PrunedTable::Death => {
+                sqlx::query!(r#"DELETE FROM death WHERE create_date < $1"#, before)
+                    .execute(&self.db)
+                    .await?
+            }
+            PrunedTable::ServerJoin => {
+                sqlx::query!(r#"DELETE FROM server_join WHERE create_date < $1"#, before)
+                    .execute(&self.db)
+                    .await?
+            }
+            PrunedTable::ServerLeave => {
+                sqlx::query!(r#"DELETE FROM server_leave WHERE create_date < $1"#, before)
+                    .execute(&self.db)
+                    .await?
+            }
+        };
+        Ok(pruned.rows_affected())
+    }
+
+    async fn forget_player(&self, player: &str, requested_by: &str) -> Result<Erasure> {
+        let mut tx = self.db.begin().await?;
+        let messages = sqlx::query!(r#"DELETE FROM message WHERE author = $1"#, player)
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+        let deaths = sqlx::query!(r#"DELETE FROM death WHERE victim = $1"#, player)
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+        let joins = sqlx::query!(r#"DELETE FROM server_join WHERE username = $1"#, player)
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+        let leaves = sqlx::query!(r#"DELETE FROM server_leave WHERE username = $1"#, player)
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+        let queued = sqlx::query!(r#"DELETE FROM outbox WHERE player = $1"#, player)
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+        let kills = sqlx::query!(
+            r#"UPDATE death SET killer = $2::varchar, message = replace(message, '**' || $1::varchar || '**', '**' || $2::varchar || '**') WHERE killer = $1"#,
+            player,
+            FORGOTTEN
+        )
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+        sqlx::query!(
+            r#"UPDATE kill_milestone SET player = $2 WHERE player = $1"#,
+            player,
+            FORGOTTEN
+        )
+        .execute(&mut *tx)
+        .await?;
+        // a fight they were in may already have someone forgotten in it
+        sqlx::query!(
+            r#"INSERT INTO boss_fight_player(boss_fight_id, username) SELECT boss_fight_id, $2 FROM boss_fight_player WHERE username = $1 ON CONFLICT DO NOTHING"#,
+            player,
+            FORGOTTEN
+        )
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query!(
+            r#"DELETE FROM boss_fight_player WHERE username = $1"#,
+            player
+        )
+        .execute(&mut *tx)
+        .await?;
+        let erasure = Erasure {
+            messages,
+            deaths,
+            sessions: joins + leaves,
+            kills,
+            queued,
+        };
+        let count = |rows: u64| i64::try_from(rows).unwrap_or(i64::MAX);
+        sqlx::query!(
+            r#"INSERT INTO player_erasure(player, requested_by, messages, deaths, sessions, kills, queued) VALUES ($1, $2, $3, $4, $5, $6, $7)"#,
+            player,
+            requested_by,
+            count(erasure.messages),
+            count(erasure.deaths),
+            count(erasure.sessions),
+            count(erasure.kills),
+            count(erasure.queued)
+        )
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        Ok(erasure)
+    }
+
+    async fn import_log(
+        &self,
+        fingerprint: &str,
+        hashes: &[String],
+        imported: &[ImportedLine<'_>],
+    ) -> Result<u64> {
+        let mut tx = self.db.begin().await?;
+        let imports = sqlx::query_as!(
+            LogImport,
+            r#"SELECT id, lines, hash FROM log_import WHERE fingerprint = $1 FOR UPDATE"#,
+            fingerprint
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+        let earlier = continued_import(&imports, hashes);
+        let already = earlier.map_or(0, |import| import.lines);
+        let mut inserted = 0;
+        for line in imported.iter().filter(|line| line.line >= already) {
+            match line.event {
+                ImportedEvent::Message { author, content } => {
+                    sqlx::query!(
+                        r#"INSERT INTO message(author, content, create_date, world_id) VALUES ($1, $2, $3, (SELECT id FROM world WHERE is_current))"#,
+                        author,
+                        content,
+                        line.create_date
+                    )
+                    .execute(&mut *tx)
+                    .await?;
+                }
+                ImportedEvent::Join(username) => {
+                    sqlx::query!(
+                        r#"INSERT INTO server_join(username, create_date, world_id) VALUES ($1, $2, (SELECT id FROM world WHERE is_current))"#,
+                        username,
+                        line.create_date
+                    )
+                    .execute(&mut *tx)
+                    .await?;
+                }
+                ImportedEvent::Leave(username) => {
+                    sqlx::query!(
+                        r#"INSERT INTO server_leave(username, create_date, world_id) VALUES ($1, $2, (SELECT id FROM world WHERE is_current))"#,
+                        username,
+                        line.create_date
+                    )
+                    .execute(&mut *tx)
+                    .await?;
+                }
+            }
+            inserted += 1;
+        }
+        let lines = i64::try_from(hashes.len()).unwrap_or(i64::MAX);
+        let hash = hashes.last();
+        match earlier {
+            Some(import) => {
+                sqlx::query!(
+                    r#"UPDATE log_import SET lines = $2, hash = $3, update_date = now() WHERE id = $1"#,
+                    import.id,
+                    lines,
+                    hash
+                )
+                .execute(&mut *tx)
+                .await?;
+            }
+            None => {
+                sqlx::query!(
+                    r#"INSERT INTO log_import(fingerprint, lines, hash) VALUES ($1, $2, $3)"#,
+                    fingerprint,
+                    lines,
+                    hash
+                )
+                .execute(&mut *tx)
+                .await?;
+            }
+        }
+        tx.commit().await?;
+        Ok(inserted)
+    }
+
+    async fn queue_outgoing(
+        &self,
+        channel_id: i64,
+        player: Option<&str>,
+        content: &str,
+    ) -> Result<()> {
+        sqlx::query!(
+            r#"INSERT INTO outbox(channel_id, player, content) VALUES ($1, $2, $3)"#,
+            channel_id,
+            player,
+            content
+        )
+        .execute(&self.db)
+        .await?;
+        Ok(())
+    }
+
+    async fn next_outgoing(&self) -> Result<Option<Outgoing>> {
+        sqlx::query_as!(
+            Outgoing,
+            r#"SELECT id, channel_id, content FROM outbox ORDER BY id LIMIT 1"#
+        )
+        .fetch_optional(&self.db)
+        .await
+    }
+
+    async fn remove_outgoing(&self, id: i64) -> Result<()> {
+        sqlx::query!(r#"DELETE FROM outbox WHERE id = $1"#, id)
+            .execute(&self.db)
+            .await?;
+        Ok(())
+    }
+
+    fn export_deaths<'a>(
+        &'a self,
+        since: Option<DateTime<Utc>>,
+        world: Option<&'a str>,
+    ) -> BoxStream<'a, Result<Death>> {
+        sqlx::query_as!(
+            Death,
+            r#"SELECT d.create_date, victim, killer, weapon, message, seconds_since_last, is_pk, w.name AS "world?"
+FROM death d LEFT JOIN world w ON w.id = d.world_id
+WHERE ($1::timestamptz IS NULL OR d.create_date >= $1) AND ($2::varchar IS NULL OR w.name = $2)
+ORDER BY d.create_date, d.id"#,
+            since,
+            world
+        )
+        .fetch(&self.db)
+    }
+
+    fn export_messages<'a>(
+        &'a self,
+        since: Option<DateTime<Utc>>,
+        world: Option<&'a str>,
+    ) -> BoxStream<'a, Result<Message>> {
+        sqlx::query_as!(
+            Message,
+            r#"SELECT author, content, create_date FROM message
+WHERE ($1::timestamptz IS NULL OR create_date >= $1) AND ($2::varchar IS NULL OR world_id = (SELECT id FROM world WHERE name = $2))
+ORDER BY create_date, id"#,
+            since,
+            world
+        )
+        .fetch(&self.db)
+    }
+
+    fn export_sessions<'a>(
+        &'a self,
+        since: Option<DateTime<Utc>>,
+        world: Option<&'a str>,
+    ) -> BoxStream<'a, Result<Session>> {
+        sqlx::query_as!(
+            Session,
+            r#"SELECT username AS "username!", joined AS "joined!", "left" FROM (SELECT id, username, create_date AS joined,
+(SELECT min(l.create_date) FROM server_leave l WHERE l.username = j.username AND l.create_date >= j.create_date) AS left
+FROM server_join j
+WHERE ($2::varchar IS NULL OR world_id = (SELECT id FROM world WHERE name = $2))) s
+WHERE $1::timestamptz IS NULL OR "left" IS NULL OR "left" >= $1
+ORDER BY joined, id"#,
+            since,
+            world
+        )
+        .fetch(&self.db)
+    }
+}
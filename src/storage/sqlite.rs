@@ -0,0 +1,1191 @@
+// SQLite can't be checked by sqlx's query macros alongside postgres, so these are checked at
+// runtime instead (and by the test at the bottom of this file)
+use super::{
+    BossSummary, Death, DeathCause, DeathCount, DeathFilter, DeathGroup, DeathsPer, EndedFight,
+    Erasure, FORGOTTEN, FightDeath, ImportedEvent, ImportedLine, KillMilestone, LogImport, Message,
+    Nemesis, NewDeath, NewKillMilestone, Outgoing, PrunedTable, PvpPlayer, PvpWeapon, Result,
+    Session, Storage, SurvivalRecord, WorldEvent, continued_import, search_patterns,
+};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use futures::TryStreamExt;
+use futures::stream::BoxStream;
+use sqlx::{Pool, QueryBuilder, Sqlite};
+use std::collections::BTreeMap;
+
+pub struct SqliteStorage {
+    db: Pool<Sqlite>,
+}
+
+impl SqliteStorage {
+    pub fn new(db: Pool<Sqlite>) -> Self {
+        Self { db }
+    }
+}
+
+#[async_trait]
+impl Storage for SqliteStorage {
+    async fn migrate(&self) -> std::result::Result<(), sqlx::migrate::MigrateError> {
+        sqlx::migrate!("migrations/sqlite").run(&self.db).await
+    }
+
+    async fn set_world(&self, name: &str) -> Result<()> {
+        let mut tx = self.db.begin().await?;
+        sqlx::query("UPDATE world SET is_current = false WHERE is_current AND name <> ?1")
+            .bind(name)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query(
+            "INSERT INTO world(name, is_current) VALUES (?1, true) ON CONFLICT (name) DO UPDATE SET is_current = true",
+        )
+        .bind(name)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await
+    }
+
+    async fn current_world(&self) -> Result<Option<String>> {
+        sqlx::query_scalar("SELECT name FROM world WHERE is_current")
+            .fetch_optional(&self.db)
+            .await
+    }
+
+    async fn insert_message(&self, author: &str, content: &str) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO message(author, content, world_id) VALUES (?1, ?2, (SELECT id FROM world WHERE is_current))",
+        )
+        .bind(author)
+        .bind(content)
+        .execute(&self.db)
+        .await
+        .map(|_| ())
+    }
+
+    async fn insert_join(&self, username: &str) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO server_join(username, world_id) VALUES (?1, (SELECT id FROM world WHERE is_current))",
+        )
+        .bind(username)
+        .execute(&self.db)
+        .await
+        .map(|_| ())
+    }
+
+    async fn insert_leave(&self, username: &str) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO server_leave(username, world_id) VALUES (?1, (SELECT id FROM world WHERE is_current))",
+        )
+        .bind(username)
+        .execute(&self.db)
+        .await
+        .map(|_| ())
+    }
+
+    // timestamps are only to the second, assume a rejoin when leaving and joining in the same second
+    async fn online_players(&self) -> Result<Vec<String>> {
+        sqlx::query_scalar(
+            "SELECT DISTINCT username FROM server_join j
+WHERE create_date >= coalesce((SELECT max(create_date) FROM server_leave l WHERE l.username = j.username), 0)
+ORDER BY username",
+        )
+        .fetch_all(&self.db)
+        .await
+    }
+
+    async fn insert_death(&self, death: &NewDeath<'_>) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO death(victim, killer, weapon, message, seconds_since_last, is_pk, world_id)
+VALUES (?1, ?2, ?3, ?4, ?5, ?6, (SELECT id FROM world WHERE is_current))",
+        )
+            .bind(death.victim)
+            .bind(death.killer)
+            .bind(death.weapon)
+            .bind(death.message)
+            .bind(death.seconds_since_last)
+            .bind(death.is_pk)
+            .execute(&self.db)
+            .await
+            .map(|_| ())
+    }
+
+    async fn last_death(&self, victim: &str) -> Result<Option<DateTime<Utc>>> {
+        sqlx::query_scalar(
+            "SELECT max(create_date) FROM death WHERE victim = ?1 AND world_id IS (SELECT id FROM world WHERE is_current)",
+        )
+            .bind(victim)
+            .fetch_one(&self.db)
+            .await
+    }
+
+    async fn longest_survivals(&self, victim: &str) -> Result<(Option<i32>, Option<i32>)> {
+        sqlx::query_as(
+            "SELECT max(seconds_since_last) FILTER (WHERE victim = ?1), max(seconds_since_last) FROM death
+WHERE world_id IS (SELECT id FROM world WHERE is_current)",
+        )
+            .bind(victim)
+            .fetch_one(&self.db)
+            .await
+    }
+
+    async fn kills_since_death(&self, player: &str) -> Result<i64> {
+        sqlx::query_scalar(
+            "SELECT count(*) FROM death
+WHERE is_pk AND killer = ?1 AND world_id IS (SELECT id FROM world WHERE is_current)
+AND create_date > coalesce((SELECT max(create_date) FROM death WHERE victim = ?1), 0)",
+        )
+        .bind(player)
+        .fetch_one(&self.db)
+        .await
+    }
+
+    async fn recently_dead(&self, players: &[String], seconds: i64) -> Result<i64> {
+        let mut query = QueryBuilder::new(
+            "SELECT count(DISTINCT victim) FROM death WHERE world_id IS (SELECT id FROM world WHERE is_current) AND create_date > unixepoch() - ",
+        );
+        query.push_bind(seconds).push(" AND victim IN (");
+        let mut victims = query.separated(", ");
+        for player in players {
+            victims.push_bind(player);
+        }
+        query.push(")");
+        query.build_query_scalar().fetch_one(&self.db).await
+    }
+
+    async fn deaths_between(
+        &self,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        world: Option<&str>,
+    ) -> Result<Vec<FightDeath>> {
+        sqlx::query_as(
+            "SELECT victim, message FROM death WHERE create_date BETWEEN ?1 AND ?2
+AND (?3 IS NULL OR world_id = (SELECT id FROM world WHERE name = ?3))
+ORDER BY create_date, id",
+        )
+        .bind(start.timestamp())
+        .bind(end.timestamp())
+        .bind(world)
+        .fetch_all(&self.db)
+        .await
+    }
+
+    async fn start_fight(&self, boss: &str) -> Result<i64> {
+        sqlx::query_scalar("INSERT INTO boss_fight(boss, world_id) VALUES (?1, (SELECT id FROM world WHERE is_current)) RETURNING id")
+            .bind(boss)
+            .fetch_one(&self.db)
+            .await
+    }
+
+    async fn open_fights(&self, boss: Option<&str>) -> Result<Vec<(i64, DateTime<Utc>)>> {
+        sqlx::query_as(
+            "SELECT id, start_date FROM boss_fight
+WHERE end_date IS NULL AND start_date IS NOT NULL AND (?1 IS NULL OR boss = ?1)
+AND world_id IS (SELECT id FROM world WHERE is_current)
+ORDER BY start_date DESC, id DESC",
+        )
+        .bind(boss)
+        .fetch_all(&self.db)
+        .await
+    }
+
+    async fn end_fight(&self, id: i64, defeated: bool) -> Result<EndedFight> {
+        let mut tx = self.db.begin().await?;
+        sqlx::query("UPDATE boss_fight SET end_date = unixepoch(), defeated = ?2 WHERE id = ?1")
+            .bind(id)
+            .bind(defeated)
+            .execute(&mut *tx)
+            .await?;
+        let fight = sqlx::query_as(
+            "SELECT boss, start_date, end_date,
+(SELECT count(*) FROM boss_fight b WHERE b.boss = boss_fight.boss AND b.end_date IS NOT NULL AND b.start_date IS NOT NULL
+AND NOT b.abandoned AND b.world_id IS boss_fight.world_id
+AND NOT EXISTS (SELECT 1 FROM boss_fight d WHERE d.boss = b.boss AND d.defeated AND d.id >= b.id
+AND d.world_id IS b.world_id AND d.id <> boss_fight.id)) AS attempt,
+(SELECT name FROM world WHERE id = boss_fight.world_id) AS world
+FROM boss_fight WHERE id = ?1",
+        )
+        .bind(id)
+        .fetch_one(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        Ok(fight)
+    }
+
+    async fn abandon_fight(&self, id: i64) -> Result<()> {
+        sqlx::query("UPDATE boss_fight SET end_date = unixepoch(), abandoned = true WHERE id = ?1")
+            .bind(id)
+            .execute(&self.db)
+            .await
+            .map(|_| ())
+    }
+
+    async fn insert_defeat(&self, boss: &str) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO boss_fight(boss, start_date, end_date, defeated, world_id)
+VALUES (?1, NULL, unixepoch(), true, (SELECT id FROM world WHERE is_current))",
+        )
+        .bind(boss)
+        .execute(&self.db)
+        .await
+        .map(|_| ())
+    }
+
+    async fn add_fight_players(&self, id: i64, players: &[String]) -> Result<()> {
+        if players.is_empty() {
+            return Ok(());
+        }
+        let mut query =
+            QueryBuilder::new("INSERT OR IGNORE INTO boss_fight_player(boss_fight_id, username) ");
+        query.push_values(players, |mut row, player| {
+            row.push_bind(id).push_bind(player);
+        });
+        query.build().execute(&self.db).await.map(|_| ())
+    }
+
+    async fn fight_players(&self, id: i64) -> Result<Vec<String>> {
+        sqlx::query_scalar(
+            "SELECT username FROM boss_fight_player WHERE boss_fight_id = ?1 ORDER BY username",
+        )
+        .bind(id)
+        .fetch_all(&self.db)
+        .await
+    }
+
+    async fn start_invasion(&self, name: &str) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO world_event(name, is_invasion, world_id)
+SELECT ?1, true, (SELECT id FROM world WHERE is_current) WHERE NOT EXISTS (SELECT 1 FROM world_event WHERE name = ?1 AND end_date IS NULL
+AND world_id IS (SELECT id FROM world WHERE is_current))",
+        )
+        .bind(name)
+        .execute(&self.db)
+        .await
+        .map(|_| ())
+    }
+
+    async fn end_invasion(&self, name: &str) -> Result<()> {
+        sqlx::query(
+            "UPDATE world_event SET end_date = unixepoch() WHERE name = ?1 AND end_date IS NULL
+AND world_id IS (SELECT id FROM world WHERE is_current)",
+        )
+        .bind(name)
+        .execute(&self.db)
+        .await
+        .map(|_| ())
+    }
+
+    async fn insert_world_event(&self, name: &str) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO world_event(name, is_invasion, world_id) VALUES (?1, false, (SELECT id FROM world WHERE is_current))",
+        )
+            .bind(name)
+            .execute(&self.db)
+            .await
+            .map(|_| ())
+    }
+
+    async fn insert_kill_milestone(&self, milestone: &NewKillMilestone<'_>) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO kill_milestone(player, count, npc_key, npc_name, world_id)
+VALUES (?1, ?2, ?3, ?4, (SELECT id FROM world WHERE is_current))",
+        )
+        .bind(milestone.player)
+        .bind(milestone.count)
+        .bind(milestone.npc_key)
+        .bind(milestone.npc_name)
+        .execute(&self.db)
+        .await
+        .map(|_| ())
+    }
+
+    async fn death_counts(&self, filter: &DeathFilter) -> Result<Vec<DeathCount>> {
+        sqlx::query_as(
+            "SELECT victim, count(*) AS deaths FROM death
+WHERE (?1 IS NULL OR create_date >= ?1)
+AND (?2 IS NULL OR killer = ?2)
+AND (NOT ?3 OR is_pk)
+AND (?4 IS NULL OR world_id = (SELECT id FROM world WHERE name = ?4))
+GROUP BY victim
+ORDER BY count(*) DESC, victim",
+        )
+        .bind(filter.since.map(|since| since.timestamp()))
+        .bind(&filter.killer)
+        .bind(filter.pvp_only)
+        .bind(&filter.world)
+        .fetch_all(&self.db)
+        .await
+    }
+
+    async fn deaths_per(
+        &self,
+        group: DeathGroup,
+        since: Option<DateTime<Utc>>,
+        world: Option<&str>,
+        timezone: Tz,
+    ) -> Result<Vec<DeathsPer>> {
+        // SQLite only knows UTC and the system's timezone, so days and hours are worked out here
+        let mut rows = sqlx::query_as::<_, (DateTime<Utc>, String)>(
+            "SELECT create_date, victim FROM death
+WHERE (?1 IS NULL OR create_date >= ?1)
+AND (?2 IS NULL OR world_id = (SELECT id FROM world WHERE name = ?2))",
+        )
+        .bind(since.map(|since| since.timestamp()))
+        .bind(world)
+        .fetch(&self.db);
+        let mut counts: BTreeMap<String, i64> = BTreeMap::new();
+        while let Some((create_date, victim)) = rows.try_next().await? {
+            *counts
+                .entry(group.label(create_date, &victim, timezone))
+                .or_default() += 1;
+        }
+        Ok(counts
+            .into_iter()
+            .map(|(label, deaths)| DeathsPer { label, deaths })
+            .collect())
+    }
+
+    async fn pvp_players(&self, world: Option<&str>) -> Result<Vec<PvpPlayer>> {
+        sqlx::query_as(
+            "WITH pk AS (SELECT killer, victim FROM death WHERE is_pk AND (?1 IS NULL OR world_id = (SELECT id FROM world WHERE name = ?1)))
+SELECT player, sum(kills) AS kills, sum(deaths) AS deaths FROM (
+SELECT killer AS player, 1 AS kills, 0 AS deaths FROM pk WHERE killer IS NOT NULL
+UNION ALL
+SELECT victim AS player, 0 AS kills, 1 AS deaths FROM pk
+)
+GROUP BY player
+ORDER BY 2 DESC, 3, 1",
+        )
+        .bind(world)
+        .fetch_all(&self.db)
+        .await
+    }
+
+    async fn pvp_weapons(&self, limit: i64, world: Option<&str>) -> Result<Vec<PvpWeapon>> {
+        sqlx::query_as(
+            "SELECT weapon, count(*) AS kills FROM death
+WHERE is_pk AND weapon IS NOT NULL AND (?2 IS NULL OR world_id = (SELECT id FROM world WHERE name = ?2))
+GROUP BY weapon
+ORDER BY 2 DESC, 1
+LIMIT ?1",
+        )
+        .bind(limit)
+        .bind(world)
+        .fetch_all(&self.db)
+        .await
+    }
+
+    async fn pvp_nemeses(&self, world: Option<&str>) -> Result<Vec<Nemesis>> {
+        sqlx::query_as(
+            "SELECT victim, killer, kills FROM (
+SELECT victim, killer, count(*) AS kills, row_number() OVER (PARTITION BY victim ORDER BY count(*) DESC, killer) AS rank FROM death
+WHERE is_pk AND killer IS NOT NULL AND (?1 IS NULL OR world_id = (SELECT id FROM world WHERE name = ?1))
+GROUP BY victim, killer
+)
+WHERE rank = 1
+ORDER BY victim",
+        )
+        .bind(world)
+        .fetch_all(&self.db)
+        .await
+    }
+
+    async fn survival_records(
+        &self,
+        before: Option<DateTime<Utc>>,
+        world: Option<&str>,
+    ) -> Result<Vec<SurvivalRecord>> {
+        sqlx::query_as(
+            "SELECT victim, max(seconds_since_last) AS longest, min(seconds_since_last) AS fastest FROM death
+WHERE seconds_since_last IS NOT NULL AND (?1 IS NULL OR create_date < ?1)
+AND (?2 IS NULL OR world_id = (SELECT id FROM world WHERE name = ?2))
+GROUP BY victim
+ORDER BY 2 DESC, 1",
+        )
+        .bind(before.map(|before| before.timestamp()))
+        .bind(world)
+        .fetch_all(&self.db)
+        .await
+    }
+
+    async fn death_causes(
+        &self,
+        before: DateTime<Utc>,
+        world: Option<&str>,
+    ) -> Result<Vec<DeathCause>> {
+        sqlx::query_as(
+            "SELECT cause, count(*) AS deaths FROM (SELECT CASE WHEN instr(message, victim) > 0
+THEN substr(message, 1, instr(message, victim) - 1) || substr(message, instr(message, victim) + length(victim))
+ELSE message END AS cause FROM death
+WHERE create_date < ?1 AND (?2 IS NULL OR world_id = (SELECT id FROM world WHERE name = ?2)))
+GROUP BY cause",
+        )
+        .bind(before.timestamp())
+        .bind(world)
+        .fetch_all(&self.db)
+        .await
+    }
+
+    async fn boss_summaries(&self, world: Option<&str>) -> Result<Vec<BossSummary>> {
+        sqlx::query_as(
+            "WITH fight AS (SELECT * FROM boss_fight WHERE (?1 IS NULL OR world_id = (SELECT id FROM world WHERE name = ?1))),
+first AS (SELECT boss, min(end_date) FILTER (WHERE defeated) AS first_kill FROM fight GROUP BY boss)
+SELECT boss, first_kill, count(*) FILTER (WHERE defeated) AS kills,
+count(*) FILTER (WHERE start_date IS NOT NULL AND NOT abandoned AND end_date <= coalesce(first_kill, 9223372036854775807)) AS attempts,
+min(end_date - start_date) FILTER (WHERE defeated) AS fastest
+FROM fight JOIN first USING (boss)
+GROUP BY boss, first_kill
+ORDER BY first_kill IS NULL, 2, 1",
+        )
+        .bind(world)
+        .fetch_all(&self.db)
+        .await
+    }
+
+    async fn bosses_defeated(
+        &self,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        world: Option<&str>,
+    ) -> Result<Vec<String>> {
+        sqlx::query_scalar(
+            "SELECT boss FROM boss_fight WHERE defeated AND end_date >= ?1 AND end_date < ?2
+AND (?3 IS NULL OR world_id = (SELECT id FROM world WHERE name = ?3))
+ORDER BY end_date, id",
+        )
+        .bind(start.timestamp())
+        .bind(end.timestamp())
+        .bind(world)
+        .fetch_all(&self.db)
+        .await
+    }
+
+    async fn world_events(&self, limit: i64, world: Option<&str>) -> Result<Vec<WorldEvent>> {
+        sqlx::query_as(
+            "SELECT name, is_invasion, start_date, end_date - start_date AS duration
+FROM world_event
+WHERE (?2 IS NULL OR world_id = (SELECT id FROM world WHERE name = ?2))
+ORDER BY start_date DESC, id DESC
+LIMIT ?1",
+        )
+        .bind(limit)
+        .bind(world)
+        .fetch_all(&self.db)
+        .await
+    }
+
+    async fn kill_milestones(
+        &self,
+        player: Option<&str>,
+        world: Option<&str>,
+    ) -> Result<Vec<KillMilestone>> {
+        sqlx::query_as(
+            "SELECT npc_name, coalesce(player, 'Someone') AS player, max(count) AS count FROM kill_milestone
+WHERE (?1 IS NULL OR player = ?1) AND (?2 IS NULL OR world_id = (SELECT id FROM world WHERE name = ?2))
+GROUP BY npc_name, player
+ORDER BY npc_name, 3 DESC, 2",
+        )
+        .bind(player)
+        .bind(world)
+        .fetch_all(&self.db)
+        .await
+    }
+
+    async fn search_messages(
+        &self,
+        text: &str,
+        player: Option<&str>,
+        since: Option<DateTime<Utc>>,
+        world: Option<&str>,
+        limit: i64,
+    ) -> Result<Vec<Message>> {
+        sqlx::query_as(
+            "SELECT author, content, create_date FROM message
+WHERE NOT EXISTS (SELECT 1 FROM json_each(?1) WHERE content NOT LIKE value ESCAPE '\\')
+AND (?2 IS NULL OR author = ?2)
+AND (?3 IS NULL OR create_date >= ?3)
+AND (?4 IS NULL OR world_id = (SELECT id FROM world WHERE name = ?4))
+ORDER BY create_date DESC, id DESC
+LIMIT ?5",
+        )
+        .bind(serde_json::Value::from(search_patterns(text)).to_string())
+        .bind(player)
+        .bind(since.map(|since| since.timestamp()))
+        .bind(world)
+        .bind(limit)
+        .fetch_all(&self.db)
+        .await
+    }
+
+    async fn random_message(&self, player: &str, world: Option<&str>) -> Result<Option<Message>> {
+        sqlx::query_as(
+            "SELECT author, content, create_date FROM message WHERE author = ?1
+AND (?2 IS NULL OR world_id = (SELECT id FROM world WHERE name = ?2))
+ORDER BY random() LIMIT 1",
+        )
+        .bind(player)
+        .bind(world)
+        .fetch_optional(&self.db)
+        .await
+    }
+
+    async fn prune(&self, table: PrunedTable, before: DateTime<Utc>) -> Result<u64> {
+        let query = match table {
+            PrunedTable::Message => "DELETE FROM message WHERE create_date < ?1",
+            PrunedTable::Death => "DELETE FROM death WHERE create_date < ?1",
+            PrunedTable::ServerJoin => "DELETE FROM server_join WHERE create_date < ?1",
+            PrunedTable::ServerLeave => "DELETE FROM server_leave WHERE create_date < ?1",
+        };
+        sqlx::query(query)
+            .bind(before.timestamp())
+            .execute(&self.db)
+            .await
+            .map(|result| result.rows_affected())
+    }
+
+    async fn forget_player(&self, player: &str, requested_by: &str) -> Result<Erasure> {
+        let mut tx = self.db.begin().await?;
+        let mut delete = async |query| {
+            sqlx::query(query)
+                .bind(player)
+                .execute(&mut *tx)
+                .await
+                .map(|result| result.rows_affected())
+        };
+        let messages = delete("DELETE FROM message WHERE author = ?1").await?;
+        let deaths = delete("DELETE FROM death WHERE victim = ?1").await?;
+        let joins = delete("DELETE FROM server_join WHERE username = ?1").await?;
+        let leaves = delete("DELETE FROM server_leave WHERE username = ?1").await?;
+        let queued = delete("DELETE FROM outbox WHERE player = ?1").await?;
+        let kills = sqlx::query(
+            "UPDATE death SET killer = ?2, message = replace(message, '**' || ?1 || '**', '**' || ?2 || '**') WHERE killer = ?1",
+        )
+        .bind(player)
+        .bind(FORGOTTEN)
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+        // a fight they were in may already have someone forgotten in it
+        for query in [
+            "UPDATE kill_milestone SET player = ?2 WHERE player = ?1",
+            "INSERT OR IGNORE INTO boss_fight_player(boss_fight_id, username) SELECT boss_fight_id, ?2 FROM boss_fight_player WHERE username = ?1",
+            "DELETE FROM boss_fight_player WHERE username = ?1",
+        ] {
+            sqlx::query(query)
+                .bind(player)
+                .bind(FORGOTTEN)
+                .execute(&mut *tx)
+                .await?;
+        }
+        let erasure = Erasure {
+            messages,
+            deaths,
+            sessions: joins + leaves,
+            kills,
+            queued,
+        };
+        let count = |rows: u64| i64::try_from(rows).unwrap_or(i64::MAX);
+        sqlx::query(
+            "INSERT INTO player_erasure(player, requested_by, messages, deaths, sessions, kills, queued) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        )
+        .bind(player)
+        .bind(requested_by)
+        .bind(count(erasure.messages))
+        .bind(count(erasure.deaths))
+        .bind(count(erasure.sessions))
+        .bind(count(erasure.kills))
+        .bind(count(erasure.queued))
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        Ok(erasure)
+    }
+
+    async fn import_log(
+        &self,
+        fingerprint: &str,
+        hashes: &[String],
+        imported: &[ImportedLine<'_>],
+    ) -> Result<u64> {
+        let mut tx = self.db.begin().await?;
+        let imports: Vec<LogImport> =
+            sqlx::query_as("SELECT id, lines, hash FROM log_import WHERE fingerprint = ?1")
+                .bind(fingerprint)
+                .fetch_all(&mut *tx)
+                .await?;
+        let earlier = continued_import(&imports, hashes);
+        let already = earlier.map_or(0, |import| import.lines);
+        let mut inserted = 0;
+        for line in imported.iter().filter(|line| line.line >= already) {
+            let create_date = line.create_date.timestamp();
+            match line.event {
+                ImportedEvent::Message { author, content } => {
+                    sqlx::query(
+                        "INSERT INTO message(author, content, create_date, world_id) VALUES (?1, ?2, ?3, (SELECT id FROM world WHERE is_current))",
+                    )
+                    .bind(author)
+                    .bind(content)
+                    .bind(create_date)
+                    .execute(&mut *tx)
+                    .await?;
+                }
+                ImportedEvent::Join(username) => {
+                    sqlx::query(
+                        "INSERT INTO server_join(username, create_date, world_id) VALUES (?1, ?2, (SELECT id FROM world WHERE is_current))",
+                    )
+                    .bind(username)
+                    .bind(create_date)
+                    .execute(&mut *tx)
+                    .await?;
+                }
+                ImportedEvent::Leave(username) => {
+                    sqlx::query(
+                        "INSERT INTO server_leave(username, create_date, world_id) VALUES (?1, ?2, (SELECT id FROM world WHERE is_current))",
+                    )
+                    .bind(username)
+                    .bind(create_date)
+                    .execute(&mut *tx)
+                    .await?;
+                }
+            }
+            inserted += 1;
+        }
+        let query = match earlier {
+            Some(import) => sqlx::query(
+                "UPDATE log_import SET lines = ?2, hash = ?3, update_date = unixepoch() WHERE id = ?1",
+            )
+            .bind(import.id),
+            None => sqlx::query("INSERT INTO log_import(fingerprint, lines, hash) VALUES (?1, ?2, ?3)")
+                .bind(fingerprint),
+        };
+        query
+            .bind(i64::try_from(hashes.len()).unwrap_or(i64::MAX))
+            .bind(hashes.last())
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        Ok(inserted)
+    }
+
+    async fn queue_outgoing(
+        &self,
+        channel_id: i64,
+        player: Option<&str>,
+        content: &str,
+    ) -> Result<()> {
+        sqlx::query("INSERT INTO outbox(channel_id, player, content) VALUES (?1, ?2, ?3)")
+            .bind(channel_id)
+            .bind(player)
+            .bind(content)
+            .execute(&self.db)
+            .await?;
+        Ok(())
+    }
+
+    async fn next_outgoing(&self) -> Result<Option<Outgoing>> {
+        sqlx::query_as("SELECT id, channel_id, content FROM outbox ORDER BY id LIMIT 1")
+            .fetch_optional(&self.db)
+            .await
+    }
+
+    async fn remove_outgoing(&self, id: i64) -> Result<()> {
+        sqlx::query("DELETE FROM outbox WHERE id = ?1")
+            .bind(id)
+            .execute(&self.db)
+            .await?;
+        Ok(())
+    }
+
+    fn export_deaths<'a>(
+        &'a self,
+        since: Option<DateTime<Utc>>,
+        world: Option<&'a str>,
+    ) -> BoxStream<'a, Result<Death>> {
+        sqlx::query_as(
+            "SELECT d.create_date, victim, killer, weapon, message, seconds_since_last, is_pk, w.name AS world
+FROM death d LEFT JOIN world w ON w.id = d.world_id
+WHERE (?1 IS NULL OR d.create_date >= ?1) AND (?2 IS NULL OR w.name = ?2)
+ORDER BY d.create_date, d.id",
+        )
+        .bind(since.map(|since| since.timestamp()))
+        .bind(world)
+        .fetch(&self.db)
+    }
+
+    fn export_messages<'a>(
+        &'a self,
+        since: Option<DateTime<Utc>>,
+        world: Option<&'a str>,
+    ) -> BoxStream<'a, Result<Message>> {
+        sqlx::query_as(
+            "SELECT author, content, create_date FROM message
+WHERE (?1 IS NULL OR create_date >= ?1) AND (?2 IS NULL OR world_id = (SELECT id FROM world WHERE name = ?2))
+ORDER BY create_date, id",
+        )
+        .bind(since.map(|since| since.timestamp()))
+        .bind(world)
+        .fetch(&self.db)
+    }
+
+    fn export_sessions<'a>(
+        &'a self,
+        since: Option<DateTime<Utc>>,
+        world: Option<&'a str>,
+    ) -> BoxStream<'a, Result<Session>> {
+        sqlx::query_as(
+            "SELECT username, joined, left FROM (SELECT id, username, create_date AS joined,
+(SELECT min(l.create_date) FROM server_leave l WHERE l.username = j.username AND l.create_date >= j.create_date) AS left
+FROM server_join j
+WHERE (?2 IS NULL OR world_id = (SELECT id FROM world WHERE name = ?2)))
+WHERE ?1 IS NULL OR left IS NULL OR left >= ?1
+ORDER BY joined, id",
+        )
+        .bind(since.map(|since| since.timestamp()))
+        .bind(world)
+        .fetch(&self.db)
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+    use futures::{StreamExt, TryStreamExt};
+    use sqlx::sqlite::SqlitePoolOptions;
+
+    fn death<'a>(victim: &'a str, killer: Option<&'a str>, seconds: Option<i32>) -> NewDeath<'a> {
+        NewDeath {
+            victim,
+            killer,
+            weapon: killer.map(|_| "Copper Shortsword"),
+            message: "died",
+            seconds_since_last: seconds,
+            is_pk: killer.is_some(),
+        }
+    }
+
+    // runs every query against a fresh database so a typo or type mismatch fails here
+    #[tokio::test]
+    async fn queries() {
+        // each in-memory connection is its own database
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        let db = SqliteStorage::new(pool);
+        db.migrate().await.unwrap();
+
+        assert_eq!(db.current_world().await.unwrap(), None);
+        db.set_world("World").await.unwrap();
+        db.set_world("World").await.unwrap();
+        db.insert_join("alice").await.unwrap();
+        db.insert_join("bob").await.unwrap();
+        db.insert_join("carol").await.unwrap();
+        db.insert_leave("carol").await.unwrap();
+        db.insert_message("alice", "hello there").await.unwrap();
+        assert_eq!(
+            db.online_players().await.unwrap(),
+            ["alice", "bob", "carol"]
+        );
+
+        let first_fight = db.start_fight("Eye of Cthulhu").await.unwrap();
+        let fight = first_fight;
+        assert_eq!(db.open_fights(None).await.unwrap().len(), 1);
+        db.add_fight_players(fight, &["alice".into(), "bob".into()])
+            .await
+            .unwrap();
+        db.add_fight_players(fight, &["alice".into()])
+            .await
+            .unwrap();
+
+        assert_eq!(db.last_death("alice").await.unwrap(), None);
+        db.insert_death(&death("alice", None, None)).await.unwrap();
+        db.insert_death(&death("bob", Some("alice"), Some(60)))
+            .await
+            .unwrap();
+        db.insert_death(&death("bob", Some("alice"), Some(30)))
+            .await
+            .unwrap();
+        assert!(db.last_death("alice").await.unwrap().is_some());
+        assert_eq!(
+            db.longest_survivals("alice").await.unwrap(),
+            (None, Some(60))
+        );
+        // killed bob in the same second as dying
+        assert_eq!(db.kills_since_death("alice").await.unwrap(), 0);
+        assert_eq!(
+            db.recently_dead(&["alice".into(), "bob".into()], 10)
+                .await
+                .unwrap(),
+            2
+        );
+
+        let ended = db.end_fight(fight, true).await.unwrap();
+        assert_eq!(ended.boss, "Eye of Cthulhu");
+        assert_eq!(ended.attempt, 1);
+        assert_eq!(
+            db.deaths_between(ended.start_date, ended.end_date, None)
+                .await
+                .unwrap()
+                .len(),
+            3
+        );
+        assert_eq!(db.fight_players(fight).await.unwrap(), ["alice", "bob"]);
+        db.insert_defeat("Skeletron").await.unwrap();
+        assert!(db.open_fights(Some("Skeletron")).await.unwrap().is_empty());
+
+        db.start_invasion("Goblin Army").await.unwrap();
+        db.start_invasion("Goblin Army").await.unwrap();
+        db.end_invasion("Goblin Army").await.unwrap();
+        db.insert_world_event("Blood Moon").await.unwrap();
+        db.insert_kill_milestone(&NewKillMilestone {
+            player: Some("bob"),
+            count: 50,
+            npc_key: "NPCName.BlueSlime",
+            npc_name: "Blue Slime",
+        })
+        .await
+        .unwrap();
+
+        let deaths = db
+            .death_counts(&DeathFilter {
+                pvp_only: true,
+                ..DeathFilter::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(deaths.len(), 1);
+        assert_eq!((deaths[0].victim.as_str(), deaths[0].deaths), ("bob", 2));
+
+        let victims = db
+            .deaths_per(DeathGroup::Victim, None, Some("World"), Tz::UTC)
+            .await
+            .unwrap();
+        assert_eq!(
+            victims
+                .iter()
+                .map(|v| (v.label.as_str(), v.deaths))
+                .collect::<Vec<_>>(),
+            [("alice", 1), ("bob", 2)]
+        );
+        let days = db
+            .deaths_per(
+                DeathGroup::Day,
+                Some(ended.start_date),
+                None,
+                chrono_tz::Europe::Paris,
+            )
+            .await
+            .unwrap();
+        assert_eq!(days[0].label.len(), "2024-01-31".len());
+        assert_eq!(days.iter().map(|d| d.deaths).sum::<i64>(), 3);
+        let hours = db
+            .deaths_per(DeathGroup::Hour, None, None, chrono_tz::Atlantic::Azores)
+            .await
+            .unwrap();
+        assert_eq!(hours[0].label.len(), 2);
+
+        let players = db.pvp_players(Some("World")).await.unwrap();
+        assert_eq!(
+            (
+                players[0].player.as_str(),
+                players[0].kills,
+                players[0].deaths
+            ),
+            ("alice", 2, 0)
+        );
+        assert_eq!(db.pvp_weapons(5, None).await.unwrap()[0].kills, 2);
+        assert_eq!(db.pvp_nemeses(None).await.unwrap()[0].killer, "alice");
+        let records = db.survival_records(None, None).await.unwrap();
+        assert_eq!((records[0].longest, records[0].fastest), (60, 30));
+        assert!(
+            db.survival_records(Some(ended.start_date), None)
+                .await
+                .unwrap()
+                .is_empty()
+        );
+        let causes = db
+            .death_causes(Utc::now() + chrono::TimeDelta::seconds(1), Some("World"))
+            .await
+            .unwrap();
+        assert_eq!((causes[0].cause.as_str(), causes[0].deaths), ("died", 3));
+
+        assert_eq!(
+            db.bosses_defeated(
+                ended.start_date,
+                Utc::now() + chrono::TimeDelta::seconds(1),
+                Some("World")
+            )
+            .await
+            .unwrap(),
+            ["Eye of Cthulhu", "Skeletron"]
+        );
+        let bosses = db.boss_summaries(None).await.unwrap();
+        assert_eq!(bosses.len(), 2);
+        assert_eq!((bosses[0].kills, bosses[0].attempts), (1, 1));
+        assert_eq!(bosses[0].fastest, Some(0));
+
+        let events = db.world_events(200, None).await.unwrap();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].name, "Blood Moon");
+        assert!(events[1].is_invasion && events[1].duration.is_some());
+
+        assert_eq!(db.kill_milestones(None, None).await.unwrap()[0].count, 50);
+        assert!(
+            db.kill_milestones(Some("alice"), None)
+                .await
+                .unwrap()
+                .is_empty()
+        );
+
+        let messages = db
+            .search_messages("THERE", None, None, Some("World"), 500)
+            .await
+            .unwrap();
+        assert_eq!(messages.len(), 1);
+        let quote = db
+            .random_message("alice", Some("World"))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(quote.content, "hello there");
+        assert!(db.random_message("bob", None).await.unwrap().is_none());
+
+        let deaths: Vec<Death> = db
+            .export_deaths(None, Some("World"))
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(deaths.len(), 3);
+        assert_eq!(deaths[0].world.as_deref(), Some("World"));
+        assert_eq!(db.export_messages(None, Some("World")).count().await, 1);
+        let sessions: Vec<Session> = db.export_sessions(None, None).try_collect().await.unwrap();
+        assert_eq!(sessions.len(), 3);
+        assert!(sessions[2].left.is_some());
+        // only sessions still going
+        let tomorrow = Utc::now() + chrono::TimeDelta::days(1);
+        let sessions: Vec<Session> = db
+            .export_sessions(Some(tomorrow), None)
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(sessions.len(), 2);
+
+        assert!(db.next_outgoing().await.unwrap().is_none());
+        db.queue_outgoing(1, None, "first").await.unwrap();
+        db.queue_outgoing(2, None, "second").await.unwrap();
+        let next = db.next_outgoing().await.unwrap().unwrap();
+        assert_eq!((next.channel_id, next.content.as_str()), (1, "first"));
+        db.remove_outgoing(next.id).await.unwrap();
+        assert_eq!(db.next_outgoing().await.unwrap().unwrap().content, "second");
+
+        // left open when the world changes
+        db.start_invasion("Pirate Invasion").await.unwrap();
+        db.start_fight("Skeletron").await.unwrap();
+
+        // a new world starts its stats over
+        db.set_world("Second World").await.unwrap();
+        assert!(db.open_fights(None).await.unwrap().is_empty());
+        assert_eq!(
+            db.recently_dead(&["alice".into(), "bob".into()], 10)
+                .await
+                .unwrap(),
+            0
+        );
+        db.start_invasion("Pirate Invasion").await.unwrap();
+        db.end_invasion("Pirate Invasion").await.unwrap();
+        let events = db.world_events(200, Some("Second World")).await.unwrap();
+        assert_eq!(events.len(), 1);
+        assert!(events[0].duration.is_some());
+        let events = db.world_events(200, Some("World")).await.unwrap();
+        assert_eq!(events[0].name, "Pirate Invasion");
+        assert!(events[0].duration.is_none());
+        assert_eq!(
+            db.current_world().await.unwrap().as_deref(),
+            Some("Second World")
+        );
+        assert_eq!(db.last_death("bob").await.unwrap(), None);
+        db.insert_message("dave", "eye is 100% dead").await.unwrap();
+        db.insert_message("dave", "eye is 100 percent dead")
+            .await
+            .unwrap();
+        db.insert_message("dave", "the dead eye_of_cthulhu")
+            .await
+            .unwrap();
+        let search = |text| db.search_messages(text, None, None, Some("Second World"), 500);
+        assert_eq!(search("DEAD eye").await.unwrap().len(), 3);
+        let messages = search("100%").await.unwrap();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].content, "eye is 100% dead");
+        assert_eq!(search("dead eye_").await.unwrap().len(), 1);
+        assert!(search("eye \\").await.unwrap().is_empty());
+        assert!(
+            db.random_message("alice", Some("Second World"))
+                .await
+                .unwrap()
+                .is_none()
+        );
+        assert_eq!(
+            db.export_sessions(None, Some("Second World")).count().await,
+            0
+        );
+        assert_eq!(db.longest_survivals("bob").await.unwrap(), (None, None));
+        db.insert_death(&death("alice", None, None)).await.unwrap();
+        let second = DeathFilter {
+            world: Some("Second World".into()),
+            ..DeathFilter::default()
+        };
+        assert_eq!(db.death_counts(&second).await.unwrap().len(), 1);
+        assert_eq!(
+            db.death_counts(&DeathFilter::default())
+                .await
+                .unwrap()
+                .len(),
+            2
+        );
+        assert!(
+            db.pvp_players(Some("Second World"))
+                .await
+                .unwrap()
+                .is_empty()
+        );
+        assert!(
+            db.boss_summaries(Some("Second World"))
+                .await
+                .unwrap()
+                .is_empty()
+        );
+        assert!(
+            db.world_events(200, Some("Nowhere"))
+                .await
+                .unwrap()
+                .is_empty()
+        );
+        let fight = db.start_fight("Eye of Cthulhu").await.unwrap();
+        assert_eq!(db.end_fight(fight, false).await.unwrap().attempt, 1);
+        // a fight whose end was missed isn't an attempt
+        let fight = db.start_fight("Eye of Cthulhu").await.unwrap();
+        db.abandon_fight(fight).await.unwrap();
+        assert!(db.open_fights(None).await.unwrap().is_empty());
+        let fight = db.start_fight("Eye of Cthulhu").await.unwrap();
+        assert_eq!(db.end_fight(fight, true).await.unwrap().attempt, 2);
+        let bosses = db.boss_summaries(Some("Second World")).await.unwrap();
+        assert_eq!((bosses[0].kills, bosses[0].attempts), (1, 2));
+        // attempts start over once it's defeated
+        let fight = db.start_fight("Eye of Cthulhu").await.unwrap();
+        assert_eq!(db.end_fight(fight, false).await.unwrap().attempt, 1);
+
+        db.insert_kill_milestone(&NewKillMilestone {
+            player: Some("alice"),
+            count: 100,
+            npc_key: "NPCName.BlueSlime",
+            npc_name: "Blue Slime",
+        })
+        .await
+        .unwrap();
+        db.queue_outgoing(1, Some("alice"), "**alice** was slain")
+            .await
+            .unwrap();
+        // mentions alice but is from al
+        db.queue_outgoing(1, Some("al"), "<al> hi alice")
+            .await
+            .unwrap();
+        assert_eq!(
+            db.forget_player("alice", "admin").await.unwrap(),
+            Erasure {
+                messages: 1,
+                deaths: 2,
+                sessions: 1,
+                kills: 2,
+                queued: 1,
+            }
+        );
+        let players = db.pvp_players(None).await.unwrap();
+        assert_eq!(
+            (players[0].player.as_str(), players[0].kills),
+            ("Someone", 2)
+        );
+        assert!(db.random_message("alice", None).await.unwrap().is_none());
+        assert!(
+            db.kill_milestones(Some("alice"), None)
+                .await
+                .unwrap()
+                .is_empty()
+        );
+        assert_eq!(
+            db.kill_milestones(Some(FORGOTTEN), None).await.unwrap()[0].count,
+            100
+        );
+        assert_eq!(
+            db.fight_players(first_fight).await.unwrap(),
+            [FORGOTTEN, "bob"]
+        );
+        let next = db.next_outgoing().await.unwrap().unwrap();
+        assert_eq!(next.content, "second");
+        db.remove_outgoing(next.id).await.unwrap();
+        let next = db.next_outgoing().await.unwrap().unwrap();
+        assert_eq!(next.content, "<al> hi alice");
+        db.remove_outgoing(next.id).await.unwrap();
+        assert!(db.next_outgoing().await.unwrap().is_none());
+
+        assert_eq!(
+            db.prune(PrunedTable::ServerJoin, tomorrow).await.unwrap(),
+            2
+        );
+        assert_eq!(
+            db.prune(PrunedTable::ServerLeave, tomorrow).await.unwrap(),
+            1
+        );
+        assert_eq!(
+            db.prune(PrunedTable::Death, ended.start_date)
+                .await
+                .unwrap(),
+            0
+        );
+        assert_eq!(db.prune(PrunedTable::Message, tomorrow).await.unwrap(), 3);
+
+        let imported = [
+            ImportedLine {
+                line: 0,
+                create_date: ended.start_date,
+                event: ImportedEvent::Join("dave"),
+            },
+            ImportedLine {
+                line: 1,
+                create_date: ended.start_date,
+                event: ImportedEvent::Message {
+                    author: "dave",
+                    content: "hi",
+                },
+            },
+            ImportedLine {
+                line: 2,
+                create_date: ended.start_date,
+                event: ImportedEvent::Leave("dave"),
+            },
+        ];
+        let hashes = ["a".to_string(), "b".to_string(), "c".to_string()];
+        assert_eq!(
+            db.import_log("log", &hashes[..2], &imported[..2])
+                .await
+                .unwrap(),
+            2
+        );
+        assert_eq!(db.import_log("log", &hashes, &imported).await.unwrap(), 1);
+        assert_eq!(db.import_log("log", &hashes, &imported).await.unwrap(), 0);
+        // starts the same but isn't the same log
+        let other = ["x".to_string(), "y".to_string()];
+        assert_eq!(
+            db.import_log("log", &other, &imported[1..2]).await.unwrap(),
+            1
+        );
+        assert_eq!(
+            db.import_log("log", &other, &imported[1..2]).await.unwrap(),
+            0
+        );
+        let sessions: Vec<Session> = db.export_sessions(None, None).try_collect().await.unwrap();
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0].left, Some(ended.start_date));
+    }
+}
@@ -1,16 +1,22 @@
+use crate::events;
+use crate::metrics::{self, Counter};
+use crate::outbox::Outbox;
+use crate::settings;
+use crate::storage::{NewDeath, Storage};
 use crate::strings;
-use serenity::http::Http;
-use serenity::model::id::ChannelId;
-use sqlx::{Pool, Postgres};
 use std::collections::HashMap;
 use std::error::Error;
 use std::fmt;
 use std::process::Stdio;
 use std::sync::Arc;
 use tokio::process::Command;
+use tokio::sync::watch;
 use tracing::{error, info};
 
 const STRING_START: usize = 7;
+const WORLD_INFO: u8 = 7;
+// WorldInfo packets have the world name after the time, moon phase, world size, and spawn point
+const WORLD_NAME_START: usize = 25;
 
 #[derive(Debug)]
 struct MissingDeathData {
@@ -31,15 +37,17 @@ struct Death {
     killer: Option<String>,
     weapon: Option<String>,
     is_pk: bool,
+    world: Option<String>,
 }
 
 // read pcap file of server output looking for relevant messages
 pub async fn parse_packets(
-    http: Arc<Http>,
-    channel_id: ChannelId,
+    outbox: Outbox,
     interface: String,
     port: u16,
-    db: Pool<Postgres>,
+    db: Arc<dyn Storage>,
+    settings: settings::Live,
+    mut shutdown: watch::Receiver<bool>,
 ) {
     #[allow(clippy::expect_used)]
     let mut tcpdump = Command::new("tcpdump")
@@ -73,20 +81,35 @@ pub async fn parse_packets(
     .expect("Unable to start pcap reader");
 
     let mut last_sends: HashMap<String, u32> = HashMap::new();
+    let mut world = match db.current_world().await {
+        Ok(world) => world,
+        Err(e) => {
+            error!(error = %e, "Unable to get current world");
+            metrics::count(Counter::DbErrors);
+            None
+        }
+    };
 
     info!("starting packet reader loop");
+    let _alive = metrics::Alive::new(metrics::Task::Capture);
     loop {
-        let packet = match reader.read_packet().await {
+        let packet = tokio::select! {
+            packet = reader.read_packet() => packet,
+            _ = shutdown.changed() => break,
+        };
+        let packet = match packet {
             Ok(p) => p,
             Err(e) => {
                 error!(error = %e, "Unable to read packet");
                 break;
             }
         };
+        metrics::count(Counter::PacketsCaptured);
         let data = match reader.data(packet.bytes()) {
             Ok(d) => d,
             Err(e) => {
                 error!(error = %e, "Unable to parse data from packet");
+                metrics::count_by(Counter::DecodeFailures, "packet");
                 continue;
             }
         };
@@ -100,27 +123,61 @@ pub async fn parse_packets(
         if length < 8 {
             continue;
         }
+        if data[2] == WORLD_INFO {
+            if let Some(name) = world_name(data) {
+                update_world(name, &mut world, db.as_ref()).await;
+            }
+            continue;
+        }
         if data[2..7] != [0x52, 1, 0, 0xff, 2] {
             // server message? in deaths and server chats, not sure of meaning
             continue;
         }
+        let current = settings.borrow().clone();
+        let mut event = None;
+        // and who it's about, for deaths
         let message = if length >= 12 && data[8..13] == [0x44, 0x65, 0x61, 0x74, 0x68] {
             // death messages start with "Death"
-            try_death(data, &strings, &db).await
+            event = Some(events::Event::PlayerDied);
+            try_death(
+                data,
+                &strings,
+                &mut world,
+                db.as_ref(),
+                current.killing_spree,
+            )
+            .await
         } else {
-            match try_generic(&data[6..], &strings) {
+            match decode_text(&data[6..], &strings) {
                 None => None,
-                Some(s) => Some(s.0),
+                Some((text, _)) => {
+                    event = events::Event::from_text(&text);
+                    Some((None, text.text))
+                }
             }
         };
-        if let Some(message) = message {
+        if let Some((victim, message)) = message {
+            metrics::count(Counter::MessagesDecoded);
             let repeat = match last_sends.get(&message) {
                 None => false,
                 Some(last_send) => packet.epoch_seconds() - last_send < 5,
             };
             last_sends.insert(message.clone(), packet.epoch_seconds());
-            if !repeat && let Err(e) = channel_id.say(&http, message).await {
-                error!(error = %e, "Unable to announce to discord");
+            if repeat {
+                continue;
+            }
+            match &victim {
+                Some(victim) => {
+                    outbox
+                        .send_about(current.bridge_channel_id, victim, &message)
+                        .await;
+                }
+                None => outbox.send(current.bridge_channel_id, &message).await,
+            }
+            if let Some(event) = event
+                && let Some(summary) = event.record(db.as_ref()).await
+            {
+                outbox.send(current.bridge_channel_id, &summary).await;
             }
         }
     }
@@ -133,67 +190,161 @@ pub async fn parse_packets(
 async fn try_death(
     data: &[u8],
     strings: &HashMap<&'static str, HashMap<&'static str, &'static str>>,
-    db: &Pool<Postgres>,
-) -> Option<String> {
+    world: &mut Option<String>,
+    db: &dyn Storage,
+    killing_spree: Option<u32>,
+) -> Option<(Option<String>, String)> {
     match build_death(&data[STRING_START..], strings) {
         Err(e) => {
             error!(error = %e, "Error building death message");
+            metrics::count_by(Counter::DecodeFailures, "death");
             None
         }
         Ok(death) => {
-            #[allow(clippy::panic)]
-            let seconds_since_last: Option<i32> = match sqlx::query!(
-                "SELECT max(create_date) as last_date FROM death WHERE victim = $1",
-                death.victim
-            )
-            .fetch_one(db)
-            .await
-            {
-                Ok(r) => match r.last_date {
-                    Some(last_date) => {
-                        let now = sqlx::types::chrono::Local::now();
-                        let since = now.signed_duration_since(last_date);
-                        if since.num_seconds() < 9 {
-                            //respawn timer is 10s, this is a repeat packet/message
-                            return None;
-                        }
-                        match since.num_seconds().try_into() {
-                            Ok(s) => Some(s),
-                            Err(e) => {
-                                error!(error = %e, "error converting seconds since death");
-                                None
-                            }
-                        }
-                    }
-                    None => None,
-                },
+            if let Some(name) = &death.world {
+                update_world(name, world, db).await;
+            }
+            let victim = death.victim.clone();
+            record_death(death, db, killing_spree)
+                .await
+                .map(|message| (Some(victim), message))
+        }
+    }
+}
+
+// Records name as the world being played if it isn't already
+async fn update_world(name: &str, world: &mut Option<String>, db: &dyn Storage) {
+    if world.as_deref() == Some(name) {
+        return;
+    }
+    info!(world = name, "world changed");
+    if let Err(e) = db.set_world(name).await {
+        error!(error = %e, "Unable to set current world");
+        metrics::count(Counter::DbErrors);
+        return;
+    }
+    *world = Some(name.to_string());
+}
+
+// The world's name from a WorldInfo packet
+fn world_name(data: &[u8]) -> Option<&str> {
+    let length = *data.get(WORLD_NAME_START)? as usize;
+    if length == 0 || data.len() <= WORLD_NAME_START + length {
+        return None;
+    }
+    get_string(data, WORLD_NAME_START).ok()
+}
+
+// Stores death, returning the message to announce or None if it's a repeat of the last death
+async fn record_death(
+    death: Death,
+    db: &dyn Storage,
+    killing_spree: Option<u32>,
+) -> Option<String> {
+    let seconds_since_last: Option<i32> = match db.last_death(&death.victim).await {
+        Ok(Some(last_date)) => {
+            let since = chrono::Utc::now().signed_duration_since(last_date);
+            if since.num_seconds() < 9 {
+                //respawn timer is 10s, this is a repeat packet/message
+                return None;
+            }
+            match since.num_seconds().try_into() {
+                Ok(s) => Some(s),
                 Err(e) => {
-                    error!(error = %e, "error getting last death");
+                    error!(error = %e, "error converting seconds since death");
                     None
                 }
-            };
-
-            #[allow(clippy::panic)]
-            if let Err(e) = sqlx::query!(r#"INSERT INTO death(victim, killer, weapon, message, seconds_since_last, is_pk) VALUES ($1, $2, $3, $4, $5, $6)"#,
-                                         death.victim, death.killer, death.weapon, death.msg, seconds_since_last, death.is_pk).execute(db).await {
-                error!(error = %e, "Error inserting death");
             }
+        }
+        Ok(None) => None,
+        Err(e) => {
+            error!(error = %e, "error getting last death");
+            metrics::count(Counter::DbErrors);
+            None
+        }
+    };
+
+    let record = match seconds_since_last {
+        Some(seconds) => record_broken(&death.victim, seconds, db).await,
+        None => None,
+    };
+
+    if let Err(e) = db
+        .insert_death(&NewDeath {
+            victim: &death.victim,
+            killer: death.killer.as_deref(),
+            weapon: death.weapon.as_deref(),
+            message: &death.msg,
+            seconds_since_last,
+            is_pk: death.is_pk,
+        })
+        .await
+    {
+        error!(error = %e, "Error inserting death");
+        metrics::count(Counter::DbErrors);
+    }
 
-            let message = match seconds_since_last {
-                None => death.msg,
-                Some(seconds) => format!(
-                    "{}  *({} since last death)*",
-                    death.msg,
-                    friendly_duration(seconds)
-                ),
-            };
+    let mut message = match seconds_since_last {
+        None => death.msg,
+        Some(seconds) => format!(
+            "{}  *({} since last death)*",
+            death.msg,
+            friendly_duration(seconds)
+        ),
+    };
+
+    if let Some(record) = record {
+        message = format!("{message}\n{record}");
+    }
 
-            Some(message)
+    if let (true, Some(killer), Some(spree)) = (death.is_pk, &death.killer, killing_spree)
+        && let Some(kills) = kills_since_death(killer, db).await
+        && spree > 0
+        && kills > 0
+        && kills % i64::from(spree) == 0
+    {
+        message = format!("{message}\n**{killer}** is on a killing spree! ({kills} kills)");
+    }
+
+    Some(message)
+}
+
+// Count of PvP kills player has gotten since they last died
+async fn kills_since_death(player: &str, db: &dyn Storage) -> Option<i64> {
+    match db.kills_since_death(player).await {
+        Ok(kills) => Some(kills),
+        Err(e) => {
+            error!(error = %e, "error counting kills since last death");
+            metrics::count(Counter::DbErrors);
+            None
         }
     }
 }
 
-fn friendly_duration(secs: i32) -> String {
+// Checks if surviving for seconds beats the server's or victim's longest survival streak, must be
+// called before the death is inserted
+async fn record_broken(victim: &str, seconds: i32, db: &dyn Storage) -> Option<String> {
+    match db.longest_survivals(victim).await {
+        Ok((personal, server)) => match (server, personal) {
+            (Some(server), _) if seconds > server => Some(format!(
+                ":trophy: **{victim}** set a new server record, surviving for {}!",
+                friendly_duration(seconds)
+            )),
+            (_, Some(personal)) if seconds > personal => Some(format!(
+                ":medal: **{victim}** beat their personal best, surviving for {}!",
+                friendly_duration(seconds)
+            )),
+            _ => None,
+        },
+        Err(e) => {
+            error!(error = %e, "error getting survival records");
+            metrics::count(Counter::DbErrors);
+            None
+        }
+    }
+}
+
+pub fn friendly_duration(secs: i32) -> String {
     if secs < 120 {
         format!("{secs} seconds")
     } else if secs < 7200 {
@@ -220,6 +371,7 @@ fn lookup_string<'a>(
     match s.find('.') {
         None => {
             error!(string = s, "Missing . in lookup");
+            metrics::count(Counter::UnknownKeys);
             None
         }
         Some(i) => {
@@ -228,11 +380,13 @@ fn lookup_string<'a>(
             match strings.get(s1) {
                 None => {
                     error!(string = s, "Unable to lookup first half");
+                    metrics::count(Counter::UnknownKeys);
                     None
                 }
                 Some(strings) => match strings.get(s2) {
                     None => {
                         error!(string = s, "Unable to lookup second half");
+                        metrics::count(Counter::UnknownKeys);
                         None
                     }
                     Some(s_final) => Some(s_final),
@@ -313,6 +467,7 @@ fn build_from_death_source(
                             None
                         },
                         is_pk,
+                        world: Some(params[1].to_string()),
                     })
                 }
             }
@@ -357,10 +512,19 @@ fn build_from_death_text(
             killer: None,
             weapon: None,
             is_pk: false,
+            world: None,
         }),
     }
 }
 
+// A decoded network string, along with the localization key and substitutions it was built from
+pub struct NetText {
+    // localization key, or the literal string if this wasn't localized
+    pub key: String,
+    pub subs: Vec<NetText>,
+    pub text: String,
+}
+
 // strings are [mode][length][string...][num_substitutions (only if mode != 0)]
 // ex. [0x2, 0x17, Announcement.HasArrived, 0x1]
 // ex. [0x0, 0x8, username]
@@ -369,22 +533,30 @@ fn build_from_death_text(
 // a string with mode 0 has no trailing byte (the next byte after a mode 0 string is the next string's mode (if there's another)
 // we can recursively assemble a string by assembling all of its substitutions (and the substitutions' substitutions, etc) and then subbing them in
 // TODO: this should probably also be used for death message deserialization but i still have some special functionality to work around there
-fn try_generic(
+fn decode_text(
     data: &[u8],
     strings: &HashMap<&'static str, HashMap<&'static str, &'static str>>,
-) -> Option<(String, usize)> {
+) -> Option<(NetText, usize)> {
     let mut offset = 0;
     let mode = data[offset];
     offset += 1;
     match get_string(data, offset) {
         Err(e) => {
             error!(error = %e, ?data, "Error parsing first generic string");
+            metrics::count_by(Counter::DecodeFailures, "string");
             None
         }
         Ok(key) => {
             offset += key.len() + 1;
             if mode == 0 {
-                return Some((key.to_string(), offset));
+                return Some((
+                    NetText {
+                        key: key.to_string(),
+                        subs: vec![],
+                        text: key.to_string(),
+                    },
+                    offset,
+                ));
             }
 
             if key.find("CLI.") == Some(0)
@@ -399,7 +571,7 @@ fn try_generic(
             offset += 1;
             let mut subs = Vec::with_capacity(num_subs);
             for _ in 0..num_subs {
-                let sub = try_generic(&data[offset..], strings)?;
+                let sub = decode_text(&data[offset..], strings)?;
                 subs.push(sub.0);
                 offset += sub.1;
             }
@@ -408,20 +580,42 @@ fn try_generic(
                 Some(val) => val.to_string(),
             };
             for (i, p) in subs.iter().enumerate() {
-                info!("replace: {{{}}} {}", i, p);
-                val = val.replacen(&format!("{{{i}}}"), p, 1);
+                info!("replace: {{{}}} {}", i, p.text);
+                val = val.replacen(&format!("{{{i}}}"), &p.text, 1);
             }
-            Some((val, offset))
+            Some((
+                NetText {
+                    key: key.to_string(),
+                    subs,
+                    text: val,
+                },
+                offset,
+            ))
         }
     }
 }
 
 #[cfg(test)]
+#[allow(clippy::unwrap_used)]
 mod tests {
+    use super::{Death, strings};
+    use crate::events::Event;
+    use crate::storage::{MemoryStorage, Storage};
+    use std::collections::HashMap;
+
+    fn generic(
+        data: &[u8],
+        strings: &HashMap<&'static str, HashMap<&'static str, &'static str>>,
+    ) -> Option<(String, usize)> {
+        super::decode_text(data, strings).map(|(text, offset)| (text.text, offset))
+    }
+
+    fn event(data: &[u8]) -> Option<Event> {
+        super::decode_text(data, &strings::get()).and_then(|(text, _)| Event::from_text(&text))
+    }
+
     #[test]
     fn try_generic() {
-        use super::strings;
-
         let strings = strings::get();
 
         let flesh = vec![
@@ -432,7 +626,7 @@ mod tests {
         ];
         assert_eq!(
             Some(("Wall of Flesh has awoken!".to_string(), 47)),
-            super::try_generic(&flesh, &strings)
+            generic(&flesh, &strings)
         );
 
         let eclipse = vec![
@@ -441,7 +635,7 @@ mod tests {
         ];
         assert_eq!(
             Some(("A solar eclipse is happening!".to_string(), 16)),
-            super::try_generic(&eclipse, &strings)
+            generic(&eclipse, &strings)
         );
 
         let merchant = vec![
@@ -453,7 +647,7 @@ mod tests {
         ];
         assert_eq!(
             Some(("Willy the Traveling Merchant has arrived!".to_string(), 78)),
-            super::try_generic(&merchant, &strings)
+            generic(&merchant, &strings)
         );
 
         let n_slime = vec![
@@ -467,7 +661,7 @@ mod tests {
                 "heydabop has defeated the 200th Blue Slime!".to_string(),
                 72
             )),
-            super::try_generic(&n_slime, &strings)
+            generic(&n_slime, &strings)
         );
 
         let kill = vec![
@@ -487,7 +681,92 @@ mod tests {
                     .to_string(),
                 117
             )),
-            super::try_generic(&kill, &strings)
+            generic(&kill, &strings)
+        );
+
+        assert_eq!(
+            Some(Event::BossAwoken("Wall of Flesh".to_string())),
+            event(&flesh)
+        );
+        assert_eq!(Some(Event::Started("Solar Eclipse")), event(&eclipse));
+        assert_eq!(None, event(&merchant));
+        assert_eq!(
+            Some(Event::KillMilestone {
+                player: Some("heydabop".to_string()),
+                count: 200,
+                npc_key: "NPCName.BlueSlime".to_string(),
+                npc_name: "Blue Slime".to_string(),
+            }),
+            event(&n_slime)
+        );
+    }
+
+    fn death(victim: &str) -> Death {
+        Death {
+            msg: format!("{victim} was slain..."),
+            victim: victim.to_string(),
+            killer: None,
+            weapon: None,
+            is_pk: false,
+            world: Some("World".to_string()),
+        }
+    }
+
+    #[tokio::test]
+    async fn record_death() {
+        let db = MemoryStorage::default();
+
+        assert_eq!(
+            Some("bob was slain...".to_string()),
+            super::record_death(death("bob"), &db, None).await
+        );
+
+        // the same death is sent more than once within the 10s respawn timer
+        db.age(5);
+        assert_eq!(None, super::record_death(death("bob"), &db, None).await);
+        assert_eq!(
+            Some("alice was slain...".to_string()),
+            super::record_death(death("alice"), &db, None).await
         );
+
+        db.age(95);
+        assert_eq!(
+            Some("bob was slain...  *(100 seconds since last death)*".to_string()),
+            super::record_death(death("bob"), &db, None).await
+        );
+        assert_eq!(
+            (Some(100), Some(100)),
+            db.longest_survivals("bob").await.unwrap()
+        );
+
+        db.age(200);
+        assert_eq!(
+            Some(
+                "alice was slain...  *(5 minutes since last death)*\n:trophy: **alice** set a new server record, surviving for 5 minutes!"
+                    .to_string()
+            ),
+            super::record_death(death("alice"), &db, None).await
+        );
+
+        // a new world's first deaths aren't survival streaks
+        db.set_world("Second World").await.unwrap();
+        db.age(60);
+        assert_eq!(
+            Some("bob was slain...".to_string()),
+            super::record_death(death("bob"), &db, None).await
+        );
+    }
+
+    #[test]
+    fn world_name() {
+        let mut world_info = vec![0; super::WORLD_NAME_START];
+        world_info[2] = super::WORLD_INFO;
+        world_info.push(5);
+        world_info.extend_from_slice(b"World");
+        world_info.extend_from_slice(&[0; 20]);
+        assert_eq!(Some("World"), super::world_name(&world_info));
+
+        world_info.truncate(super::WORLD_NAME_START + 3);
+        assert_eq!(None, super::world_name(&world_info));
     }
 }